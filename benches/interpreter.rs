@@ -0,0 +1,54 @@
+// Instructions per second of each engine: the interpreter decoding every instruction, the decoded-instruction cache
+// and threaded code.
+// Run with `cargo bench --bench interpreter`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use chip8::cpu::Chip8;
+use chip8::engine::{Engine, Executor};
+use chip8::loader;
+
+const FRAMES: u32 = 600;
+const CYCLES_PER_FRAME: u32 = 10;
+const ROMS: [&str; 4] = ["BRIX", "INVADERS", "TETRIS", "BLINKY"];
+
+fn machine(rom: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::initialize();
+
+    chip8.seed_rng(1);
+    chip8.load_rom(rom).unwrap();
+    chip8
+}
+
+// Runs until the ROM fails or the frames are done, returning the instructions run.
+fn run(chip8: &mut Chip8, engine: Engine) -> u64 {
+    let mut executor = Executor::new(engine);
+
+    for _ in 0..FRAMES {
+        if executor.emulate_frame(chip8, CYCLES_PER_FRAME).is_err() {
+            break;
+        }
+    }
+
+    chip8.cycle_count
+}
+
+fn interpreter(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("instructions");
+
+    for name in ROMS.iter() {
+        let rom = loader::read_rom_file(&format!("roms/{}", name)).unwrap();
+
+        group.throughput(Throughput::Elements(run(&mut machine(&rom), Engine::Interpreter)));
+
+        for &engine in Engine::ALL.iter() {
+            group.bench_with_input(BenchmarkId::new(engine.name(), name), &rom, |bencher, rom| {
+                bencher.iter(|| run(&mut machine(rom), engine))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
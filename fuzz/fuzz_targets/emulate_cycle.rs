@@ -0,0 +1,43 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip8::cpu::Chip8;
+use chip8::quirks::Quirks;
+
+const HEADER_SIZE: usize = 24;
+
+// The first bytes of the input set up registers, stack and quirks, the rest is loaded as the ROM,
+// so single instructions get exercised from machine states a real ROM would take long to reach.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < HEADER_SIZE {
+        return;
+    }
+
+    let (header, rom) = data.split_at(HEADER_SIZE);
+    let flags = header[21];
+    let quirks = Quirks {
+        vf_reset: flags & 0x01 != 0,
+        shift_uses_vy: flags & 0x02 != 0,
+        load_store_increments_i: flags & 0x04 != 0,
+        jump_uses_vx: flags & 0x08 != 0,
+        clip_sprites: flags & 0x10 != 0,
+    };
+    let mut chip8 = Chip8::initialize_with_quirks(quirks);
+
+    if chip8.load_rom(rom).is_err() {
+        return;
+    }
+
+    chip8.registers.v.copy_from_slice(&header[..16]);
+    chip8.registers.i = u16::from_be_bytes([header[16], header[17]]);
+    chip8.registers.program_counter = u16::from_be_bytes([header[18], header[19]]);
+    chip8.stack.stack_pointer = (header[20] % 17) as u16;
+    chip8.keypad.keys[(header[22] & 0xF) as usize] = true;
+    chip8.timers.delay_timer = header[23];
+
+    for _ in 0..10_000 {
+        if chip8.emulate_cycle().is_err() {
+            return;
+        }
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip8::cpu::Chip8;
+
+// Loads the input as a ROM and runs it for a while, holding down keys picked from the ROM itself.
+fuzz_target!(|rom: &[u8]| {
+    let mut chip8 = Chip8::initialize();
+
+    if chip8.load_rom(rom).is_err() {
+        return;
+    }
+
+    for frame in 0..1000 {
+        if let Some(byte) = rom.get(frame % rom.len().max(1)) {
+            chip8.keypad.keys[(byte & 0xF) as usize] = byte & 0x10 != 0;
+        }
+
+        if chip8.emulate_frame(10).is_err() {
+            return;
+        }
+    }
+});
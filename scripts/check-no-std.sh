@@ -0,0 +1,18 @@
+#!/bin/sh
+# Builds and tests the core without std on the host, then builds it for a Cortex-M4F microcontroller.
+# The library is built as an rlib only, a cdylib would need a panic handler from the firmware.
+set -e
+
+cd "$(dirname "$0")/.."
+
+TARGET=thumbv7em-none-eabihf
+
+cargo rustc --lib --no-default-features --crate-type rlib
+cargo test --lib --no-default-features
+
+if ! rustup target list --installed | grep -qx "$TARGET"; then
+    echo "$TARGET is not installed, add it with: rustup target add $TARGET" >&2
+    exit 1
+fi
+
+cargo rustc --lib --no-default-features --crate-type rlib --target "$TARGET"
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Downloads the ROMs of the public CHIP-8 test suite that src/conformance.rs runs, see tests/roms/README.md.
+set -e
+
+cd "$(dirname "$0")/../tests/roms"
+
+SUITE=https://raw.githubusercontent.com/Timendus/chip8-test-suite/main/bin
+
+for ROM in 3-corax+.ch8 4-flags.ch8 5-quirks.ch8 6-keypad.ch8; do
+    curl --fail --silent --show-error --location --output "$ROM" "$SUITE/$ROM"
+done
@@ -0,0 +1,267 @@
+// Frontend backends for hosts with std: headless ones for tests and tools, a terminal screen and keyboard and the
+// wall clock.
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Instant;
+
+use crate::cpu::{Keypad, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::frontend::{Audio, Clock, Display, Input, FRAME_DURATION};
+use crate::gamepad::Gamepad;
+use crate::keymap::KeyMap;
+
+// Terminals report key presses but not releases, so a typed key is held this many frames, which key repeat extends.
+pub const TERMINAL_HOLD_FRAMES: u32 = 6;
+
+// Keeps the last frame presented and counts them, for tests and tools without a screen.
+#[derive(Default)]
+pub struct HeadlessDisplay {
+    pub frames_presented: u64,
+    pub pixels: Vec<bool>,
+}
+
+// Records when the buzzer turns on and off.
+#[derive(Default)]
+pub struct HeadlessAudio {
+    pub on: bool,
+    pub changes: Vec<bool>,
+}
+
+// Presses and releases keys at given frames, (frame, key, pressed).
+#[derive(Default)]
+pub struct ScriptedInput {
+    pub events: Vec<(u64, u8, bool)>,
+    frame: u64,
+}
+
+// Keeps to 60 frames a second of wall clock time.
+pub struct SystemClock {
+    next_frame: Instant,
+}
+
+// Draws the screen with half blocks, two pixel rows per line of text.
+pub struct TerminalDisplay<W: Write> {
+    output: W,
+}
+
+// Keys typed into a terminal, through the same key maps as the browser. The terminal has to be in a mode that
+// passes keys on as they are typed, without waiting for a line. Keys are only touched when typed and when their hold
+// runs out, so a script or another input can press the others.
+pub struct TerminalInput {
+    key_map: KeyMap,
+    typed: Receiver<u8>,
+    // Bytes of an escape sequence, such as ESC [ A for the up arrow, read so far.
+    sequence: Vec<u8>,
+    // None for keys the terminal leaves alone, Some(0) for keys to let go of at the next poll.
+    frames_left: [Option<u32>; 16],
+}
+
+impl Display for HeadlessDisplay {
+    fn present(&mut self, pixels: &[bool]) {
+        self.frames_presented += 1;
+        self.pixels = pixels.to_vec();
+    }
+}
+
+impl Audio for HeadlessAudio {
+    fn set_buzzer(&mut self, on: bool) {
+        if on != self.on {
+            self.on = on;
+            self.changes.push(on);
+        }
+    }
+}
+
+impl ScriptedInput {
+    pub fn new(events: Vec<(u64, u8, bool)>) -> ScriptedInput {
+        ScriptedInput { events, frame: 0 }
+    }
+}
+
+impl Input for ScriptedInput {
+    fn poll(&mut self, keypad: &mut Keypad) {
+        for &(_, key, pressed) in self.events.iter().filter(|&&(frame, _, _)| frame == self.frame) {
+            keypad.keys[(key & 0xF) as usize] = pressed;
+        }
+
+        self.frame += 1;
+    }
+}
+
+impl Input for Gamepad {
+    fn poll(&mut self, keypad: &mut Keypad) {
+        self.update(keypad);
+    }
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { next_frame: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    // Sleeps until the next frame is due rather than returning 0.
+    fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+
+        if now < self.next_frame {
+            std::thread::sleep(self.next_frame - now);
+        }
+
+        let mut frames = 0;
+
+        while self.next_frame <= Instant::now() {
+            self.next_frame += FRAME_DURATION;
+            frames += 1;
+        }
+
+        frames
+    }
+}
+
+impl<W: Write> TerminalDisplay<W> {
+    pub fn new(output: W) -> TerminalDisplay<W> {
+        TerminalDisplay { output }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> Display for TerminalDisplay<W> {
+    fn present(&mut self, pixels: &[bool]) {
+        let mut text = String::from("\x1b[H");
+
+        for y in (0..SCREEN_HEIGHT).step_by(2) {
+            for x in 0..SCREEN_WIDTH {
+                let top = pixels[y * SCREEN_WIDTH + x];
+                let bottom = pixels[(y + 1) * SCREEN_WIDTH + x];
+
+                text.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' '
+                });
+            }
+
+            text.push('\n');
+        }
+
+        // A terminal that went away is no reason to stop the game.
+        let _ = self.output.write_all(text.as_bytes()).and_then(|_| self.output.flush());
+    }
+}
+
+impl TerminalInput {
+    pub fn new(key_map: KeyMap, typed: Receiver<u8>) -> TerminalInput {
+        TerminalInput { key_map, typed, sequence: Vec::new(), frames_left: [None; 16] }
+    }
+
+    // Reads what is typed on another thread, until the input ends.
+    pub fn spawn<R: Read + Send + 'static>(key_map: KeyMap, mut input: R) -> TerminalInput {
+        let (sender, typed) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+
+            while let Ok(count) = input.read(&mut buffer) {
+                if count == 0 || buffer[..count].iter().any(|&byte| sender.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+
+        TerminalInput::new(key_map, typed)
+    }
+
+    // The host key name of what was typed, once a whole key has been read.
+    fn host_key(&mut self, byte: u8) -> Option<String> {
+        self.sequence.push(byte);
+
+        let name = match self.sequence.as_slice() {
+            [0x1B] | [0x1B, b'['] => return None,
+            [0x1B, b'[', b'A'] => "ArrowUp".to_string(),
+            [0x1B, b'[', b'B'] => "ArrowDown".to_string(),
+            [0x1B, b'[', b'C'] => "ArrowRight".to_string(),
+            [0x1B, b'[', b'D'] => "ArrowLeft".to_string(),
+            [byte] if byte.is_ascii_graphic() || *byte == b' ' => (*byte as char).to_string(),
+            _ => String::new()
+        };
+
+        self.sequence.clear();
+        Some(name)
+    }
+}
+
+impl Input for TerminalInput {
+    fn poll(&mut self, keypad: &mut Keypad) {
+        while let Ok(byte) = self.typed.try_recv() {
+            if let Some(key) = self.host_key(byte).and_then(|name| self.key_map.key_for(&name)) {
+                self.frames_left[key as usize] = Some(TERMINAL_HOLD_FRAMES);
+            }
+        }
+
+        for (pressed, frames_left) in keypad.keys.iter_mut().zip(self.frames_left.iter_mut()) {
+            match *frames_left {
+                Some(0) => {
+                    *pressed = false;
+                    *frames_left = None;
+                }
+                Some(frames) => {
+                    *pressed = true;
+                    *frames_left = Some(frames - 1);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::{TerminalInput, TERMINAL_HOLD_FRAMES};
+    use crate::cpu::Keypad;
+    use crate::frontend::Input;
+    use crate::keymap::KeyMapConfig;
+
+    #[test]
+    fn typed_keys_go_through_the_key_map_and_are_held_a_while() {
+        let pong = "b232ef880bd6060fb45fa6effed7edf0ae95670e";
+        let (sender, typed) = mpsc::channel();
+        let mut input = TerminalInput::new(KeyMapConfig::built_in().for_hash(pong), typed);
+        let mut keypad = Keypad { keys: [false; 16] };
+
+        keypad.keys[0xF] = true;
+
+        // W, then the up arrow, then a stray escape sequence and P, which is not mapped.
+        for &byte in b"w\x1b[A\x1b[Hp".iter() {
+            sender.send(byte).unwrap();
+        }
+
+        input.poll(&mut keypad);
+
+        assert!(keypad.keys[0x1] && keypad.keys[0xC] && keypad.keys[0xF]);
+        assert_eq!(keypad.keys.iter().filter(|&&pressed| pressed).count(), 3);
+
+        for _ in 1..TERMINAL_HOLD_FRAMES {
+            input.poll(&mut keypad);
+        }
+
+        assert!(keypad.keys[0xC]);
+
+        input.poll(&mut keypad);
+
+        assert!(!keypad.keys[0xC] && keypad.keys[0xF]);
+    }
+}
@@ -0,0 +1,255 @@
+// Many instances of one ROM stepped a frame at a time on the rayon thread pool, for training agents.
+//
+// Every instance is its own Chip8 with its own engine state, nothing is shared between them, and the results of a
+// step land in arrays with one entry per instance, ready to hand over without copying them around:
+//
+//     frames    SCREEN_WIDTH * SCREEN_HEIGHT bytes per instance, 1 for pixels that are on
+//     buzzers   whether the sound timer is running
+//     rewards   the change of the reward reader's value over the step, 0 without a reader
+//     errors    the error an instance stopped at; stopped instances stay as they are until reset
+use rayon::prelude::*;
+
+use crate::cpu::{Chip8, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::engine::{Engine, Executor};
+use crate::environment::RewardReader;
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+
+pub const FRAME_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+struct Instance {
+    chip8: Chip8,
+    executor: Executor,
+    seed: u64,
+    value: f32,
+}
+
+pub struct Batch {
+    rom: Vec<u8>,
+    quirks: Quirks,
+    cycles_per_frame: u32,
+    engine: Engine,
+    reward_reader: Option<RewardReader>,
+    instances: Vec<Instance>,
+    frames: Vec<u8>,
+    buzzers: Vec<bool>,
+    rewards: Vec<f32>,
+    errors: Vec<Option<Chip8Error>>,
+}
+
+impl Instance {
+    fn new(rom: &[u8], quirks: Quirks, engine: Engine, seed: u64, reward_reader: Option<RewardReader>) -> Result<Instance, Chip8Error> {
+        let mut chip8 = Chip8::initialize_with_quirks(quirks);
+
+        chip8.seed_rng(seed);
+        chip8.load_rom(rom)?;
+
+        let value = reward_reader.map_or(0.0, |read| read(&chip8));
+
+        Ok(Instance { chip8, executor: Executor::new(engine), seed, value })
+    }
+}
+
+impl Batch {
+    // The instances are seeded 0, 1, 2 and so on, so a batch runs the same every time.
+    pub fn new(rom: &[u8], count: usize, quirks: Quirks, cycles_per_frame: u32) -> Result<Batch, Chip8Error> {
+        let engine = Engine::default();
+        let instances = (0..count as u64)
+            .map(|seed| Instance::new(rom, quirks, engine, seed, None))
+            .collect::<Result<Vec<Instance>, Chip8Error>>()?;
+
+        Ok(Batch {
+            rom: rom.to_vec(),
+            quirks,
+            cycles_per_frame,
+            engine,
+            reward_reader: None,
+            instances,
+            frames: vec![0; count * FRAME_SIZE],
+            buzzers: vec![false; count],
+            rewards: vec![0.0; count],
+            errors: vec![None; count],
+        })
+    }
+
+    // Resets every instance, as engines keep state about the memory they ran.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.reset_all();
+    }
+
+    pub fn set_reward_reader(&mut self, reward_reader: Option<RewardReader>) {
+        self.reward_reader = reward_reader;
+
+        for instance in &mut self.instances {
+            instance.value = reward_reader.map_or(0.0, |read| read(&instance.chip8));
+        }
+    }
+
+    // Starts an instance over from the ROM with a new seed.
+    pub fn reset(&mut self, index: usize, seed: u64) {
+        self.instances[index] = Instance::new(&self.rom, self.quirks, self.engine, seed, self.reward_reader).expect("the ROM fits in memory");
+        self.frames[index * FRAME_SIZE..(index + 1) * FRAME_SIZE].iter_mut().for_each(|pixel| *pixel = 0);
+        self.buzzers[index] = false;
+        self.rewards[index] = 0.0;
+        self.errors[index] = None;
+    }
+
+    // Starts every instance over with the seed it had.
+    pub fn reset_all(&mut self) {
+        for index in 0..self.len() {
+            self.reset(index, self.instances[index].seed);
+        }
+    }
+
+    // Presses the keys set in each instance's mask, bit 0 for key 0, then runs a frame on all of them in parallel.
+    pub fn step(&mut self, keys: &[u16]) {
+        assert_eq!(keys.len(), self.len(), "one key mask per instance");
+
+        let cycles_per_frame = self.cycles_per_frame;
+        let reward_reader = self.reward_reader;
+
+        (
+            self.instances.par_iter_mut(),
+            keys.par_iter(),
+            self.frames.par_chunks_mut(FRAME_SIZE),
+            self.buzzers.par_iter_mut(),
+            self.rewards.par_iter_mut(),
+            self.errors.par_iter_mut(),
+        )
+            .into_par_iter()
+            .for_each(|(instance, &mask, frame, buzzer, reward, error)| {
+                if error.is_some() {
+                    *reward = 0.0;
+                    return;
+                }
+
+                let chip8 = &mut instance.chip8;
+
+                for (key, pressed) in chip8.keypad.keys.iter_mut().enumerate() {
+                    *pressed = mask & (1 << key) != 0;
+                }
+
+                *error = instance.executor.emulate_frame(chip8, cycles_per_frame).err();
+
+                for (pixel, &on) in frame.iter_mut().zip(chip8.graphics.gfx.iter()) {
+                    *pixel = on as u8;
+                }
+
+                *buzzer = chip8.timers.sound_timer > 0;
+                *reward = match reward_reader {
+                    Some(read) => {
+                        let value = read(chip8);
+                        let change = value - instance.value;
+
+                        instance.value = value;
+                        change
+                    }
+                    None => 0.0
+                };
+            });
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn chip8(&self, index: usize) -> &Chip8 {
+        &self.instances[index].chip8
+    }
+
+    pub fn frames(&self) -> &[u8] {
+        &self.frames
+    }
+
+    pub fn frame(&self, index: usize) -> &[u8] {
+        &self.frames[index * FRAME_SIZE..(index + 1) * FRAME_SIZE]
+    }
+
+    pub fn buzzers(&self) -> &[bool] {
+        &self.buzzers
+    }
+
+    pub fn rewards(&self) -> &[f32] {
+        &self.rewards
+    }
+
+    pub fn errors(&self) -> &[Option<Chip8Error>] {
+        &self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Batch, FRAME_SIZE};
+    use crate::cpu::Chip8;
+    use crate::engine::{Engine, Executor};
+    use crate::error::Chip8Error;
+    use crate::loader;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn instances_run_like_separate_machines() {
+        let rom = loader::read_rom_file("roms/BRIX").unwrap();
+        let mut batch = Batch::new(&rom, 6, Quirks::default(), 10).unwrap();
+        let masks: Vec<u16> = (0..6).map(|index| 1 << (index * 2)).collect();
+
+        batch.set_engine(Engine::Threaded);
+
+        let mut alone = Chip8::initialize();
+        let mut executor = Executor::new(Engine::Interpreter);
+
+        alone.seed_rng(4);
+        alone.load_rom(&rom).unwrap();
+
+        for _ in 0..300 {
+            batch.step(&masks);
+            alone.keypad.keys[8] = true;
+            executor.emulate_frame(&mut alone, 10).unwrap();
+        }
+
+        let pixels: Vec<u8> = alone.graphics.gfx.iter().map(|&on| on as u8).collect();
+
+        assert_eq!(batch.frames().len(), 6 * FRAME_SIZE);
+        assert_eq!(batch.frame(4), &pixels[..]);
+        assert_eq!(batch.chip8(4).registers, alone.registers);
+        assert!(batch.errors().iter().all(Option::is_none));
+        assert_ne!(batch.chip8(0).registers, batch.chip8(1).registers);
+    }
+
+    fn ten_per_v1(chip8: &Chip8) -> f32 {
+        chip8.registers.v[1] as f32 * 10.0
+    }
+
+    #[test]
+    fn rewards_errors_and_resets() {
+        // 200: ADD V1, 01; LD ST, V1; SE V1, 03; JP 200; then 0x5001, which is no instruction.
+        let rom = [0x71, 0x01, 0xF1, 0x18, 0x31, 0x03, 0x12, 0x00, 0x50, 0x01];
+        let mut batch = Batch::new(&rom, 2, Quirks::default(), 3).unwrap();
+
+        batch.set_reward_reader(Some(ten_per_v1));
+        batch.step(&[0, 0]);
+        assert_eq!(batch.rewards(), &[10.0, 10.0]);
+
+        batch.step(&[0, 0]);
+        assert_eq!(batch.buzzers(), &[true, true]);
+
+        batch.step(&[0, 0]);
+        batch.step(&[0, 0]);
+        assert_eq!(batch.errors(), &[Some(Chip8Error::UnknownOpCode(0x5001)); 2]);
+
+        let mut seeded = Chip8::initialize();
+
+        seeded.seed_rng(9);
+        batch.reset(1, 9);
+        batch.step(&[0, 0]);
+        assert_eq!(batch.rewards(), &[0.0, 10.0]);
+        assert_eq!(batch.errors()[1], None);
+        assert_eq!(batch.chip8(1).rng, seeded.rng);
+        assert!(Batch::new(&[0; 4000], 1, Quirks::default(), 10).is_err());
+    }
+}
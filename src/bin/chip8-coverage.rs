@@ -0,0 +1,78 @@
+// Runs a ROM for a number of frames and prints which of its bytes ran as code, were read as data or were written.
+use std::fs;
+use std::process;
+
+use chip8::coverage::Coverage;
+use chip8::loader::{self, MachineOptions};
+
+const USAGE: &str = "usage: chip8-coverage <rom> [--frames N] [--cycles-per-frame N] [--quirks default|vip|schip] [--database file] [--json output]";
+const ROM_START: usize = 0x200;
+
+struct Options {
+    rom: String,
+    frames: usize,
+    machine: MachineOptions,
+    json: Option<String>,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Options, String> {
+    let mut options = Options { rom: String::new(), frames: 600, machine: MachineOptions::new(), json: None };
+    let mut positional = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().cloned().ok_or(format!("{} needs a value", argument));
+
+        match argument.as_str() {
+            "--frames" => options.frames = value()?.parse().map_err(|_| "--frames needs a number")?,
+            "--json" => options.json = Some(value()?),
+            _ => match options.machine.parse_argument(argument, &mut value)? {
+                true => {}
+                false => positional.push(argument.clone())
+            }
+        }
+    }
+
+    match positional.as_slice() {
+        [rom] => options.rom = rom.clone(),
+        _ => return Err(USAGE.to_string())
+    }
+
+    Ok(options)
+}
+
+fn run(arguments: &[String]) -> Result<(), String> {
+    let options = parse_arguments(arguments)?;
+    let rom = loader::read_rom_file(&options.rom).map_err(|error| format!("{}: {}", options.rom, error))?;
+    let (mut chip8, cycles_per_frame) = options.machine.load(&rom)?;
+    let mut coverage = Coverage::new();
+    let rom_range = ROM_START..ROM_START + rom.len();
+
+    for frame in 0..options.frames {
+        if let Err(error) = chip8.emulate_frame_observed(cycles_per_frame, &mut coverage) {
+            eprintln!("Stopped at frame {}: {}", frame, error);
+            break;
+        }
+    }
+
+    print!("{}", coverage.annotated_hex_dump(&chip8.memory.ram, rom_range.clone()));
+
+    for range in coverage.untouched(rom_range.clone()) {
+        println!("never used: {:03X}-{:03X}", range.start(), range.end());
+    }
+
+    if let Some(path) = &options.json {
+        fs::write(path, coverage.to_json(rom_range).to_string()).map_err(|error| format!("{}: {}", path, error))?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(error) = run(&arguments) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
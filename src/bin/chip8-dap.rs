@@ -0,0 +1,9 @@
+// Debug Adapter Protocol server over stdin and stdout, started by the editor.
+use std::process;
+
+fn main() {
+    if let Err(error) = chip8::dap::serve_stdio() {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,33 @@
+// Prints a listing of a ROM, with labels and source lines when a symbol file is given.
+use std::process;
+
+use chip8::loader;
+use chip8::symbols::Symbols;
+
+const USAGE: &str = "usage: chip8-disasm <rom> [--symbols file]";
+const ROM_START: usize = 0x200;
+
+fn run(arguments: &[String]) -> Result<(), String> {
+    let (rom_path, symbols) = match arguments {
+        [rom] => (rom, Symbols::new()),
+        [rom, option, path] if option == "--symbols" => (rom, Symbols::load(path)?),
+        _ => return Err(USAGE.to_string())
+    };
+    let rom = loader::read_rom_file(rom_path).map_err(|error| format!("{}: {}", rom_path, error))?;
+    let mut ram = vec![0; ROM_START];
+
+    ram.extend_from_slice(&rom);
+
+    print!("{}", symbols.disassemble(&ram, ROM_START..ram.len()));
+
+    Ok(())
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(error) = run(&arguments) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,71 @@
+// Serves a ROM to a debugger over the GDB remote serial protocol on localhost.
+use std::net::TcpListener;
+use std::process;
+
+use chip8::gdb_stub::GdbStub;
+use chip8::loader::{self, MachineOptions};
+
+const USAGE: &str = "usage: chip8-gdb <rom> [--port N] [--cycles-per-frame N] [--quirks default|vip|schip] [--database file]";
+
+struct Options {
+    rom: String,
+    port: u16,
+    machine: MachineOptions,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Options, String> {
+    let mut options = Options { rom: String::new(), port: 1234, machine: MachineOptions::new() };
+    let mut positional = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().cloned().ok_or(format!("{} needs a value", argument));
+
+        match argument.as_str() {
+            "--port" => options.port = value()?.parse().map_err(|_| "--port needs a number")?,
+            _ => match options.machine.parse_argument(argument, &mut value)? {
+                true => {}
+                false => positional.push(argument.clone())
+            }
+        }
+    }
+
+    match positional.as_slice() {
+        [rom] => options.rom = rom.clone(),
+        _ => return Err(USAGE.to_string())
+    }
+
+    Ok(options)
+}
+
+fn run(arguments: &[String]) -> Result<(), String> {
+    let options = parse_arguments(arguments)?;
+    let rom = loader::read_rom_file(&options.rom).map_err(|error| format!("{}: {}", options.rom, error))?;
+    let (chip8, cycles_per_frame) = options.machine.load(&rom)?;
+    let mut stub = GdbStub::new(chip8, cycles_per_frame);
+    let listener = TcpListener::bind(("127.0.0.1", options.port)).map_err(|error| error.to_string())?;
+
+    println!("Waiting for a debugger on {}", listener.local_addr().map_err(|error| error.to_string())?);
+
+    // Serve debuggers one after another, the machine keeps its state between connections.
+    for stream in listener.incoming() {
+        let mut stream = stream.map_err(|error| error.to_string())?;
+
+        println!("Debugger attached");
+        if let Err(error) = stub.serve(&mut stream) {
+            eprintln!("Connection lost: {}", error);
+        }
+        println!("Debugger detached at PC {:03X}", stub.chip8().registers.program_counter);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(error) = run(&arguments) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
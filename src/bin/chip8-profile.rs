@@ -0,0 +1,80 @@
+// Runs a ROM for a number of frames and reports where the cycles went.
+use std::fs;
+use std::process;
+
+use chip8::loader::{self, MachineOptions};
+use chip8::profiler::Profiler;
+
+const USAGE: &str = "usage: chip8-profile <rom> [--frames N] [--cycles-per-frame N] [--quirks default|vip|schip] [--database file] [--top N] [--json output] [--folded output]";
+
+struct Options {
+    rom: String,
+    frames: usize,
+    machine: MachineOptions,
+    top: usize,
+    json: Option<String>,
+    folded: Option<String>,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Options, String> {
+    let mut options = Options { rom: String::new(), frames: 600, machine: MachineOptions::new(), top: 20, json: None, folded: None };
+    let mut positional = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().cloned().ok_or(format!("{} needs a value", argument));
+
+        match argument.as_str() {
+            "--frames" => options.frames = value()?.parse().map_err(|_| "--frames needs a number")?,
+            "--top" => options.top = value()?.parse().map_err(|_| "--top needs a number")?,
+            "--json" => options.json = Some(value()?),
+            "--folded" => options.folded = Some(value()?),
+            _ => match options.machine.parse_argument(argument, &mut value)? {
+                true => {}
+                false => positional.push(argument.clone())
+            }
+        }
+    }
+
+    match positional.as_slice() {
+        [rom] => options.rom = rom.clone(),
+        _ => return Err(USAGE.to_string())
+    }
+
+    Ok(options)
+}
+
+fn run(arguments: &[String]) -> Result<(), String> {
+    let options = parse_arguments(arguments)?;
+    let rom = loader::read_rom_file(&options.rom).map_err(|error| format!("{}: {}", options.rom, error))?;
+    let (mut chip8, cycles_per_frame) = options.machine.load(&rom)?;
+    let mut profiler = Profiler::new();
+
+    for frame in 0..options.frames {
+        if let Err(error) = chip8.emulate_frame_observed(cycles_per_frame, &mut profiler) {
+            eprintln!("Stopped at frame {}: {}", frame, error);
+            break;
+        }
+    }
+
+    print!("{}", profiler.report(options.top));
+
+    if let Some(path) = &options.json {
+        fs::write(path, profiler.to_json().to_string()).map_err(|error| format!("{}: {}", path, error))?;
+    }
+
+    if let Some(path) = &options.folded {
+        fs::write(path, profiler.folded_stacks()).map_err(|error| format!("{}: {}", path, error))?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(error) = run(&arguments) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
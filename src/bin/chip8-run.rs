@@ -0,0 +1,272 @@
+// Runs a ROM headless or in the terminal, optionally driven by a Rhai script (see src/scripting.rs).
+//
+// With --check the ROM runs twice side by side, from the same seed and with the same script, and the run stops at
+// the first frame whose state hashes differ. --against runs the second one with another engine.
+//
+// With --cheats the values in a cheat file (see src/cheats.rs) are frozen at the start of every frame, in both runs.
+//
+// With --display the keys typed go through the key map for the ROM, the built-in one or one from --keys.
+use std::io;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::thread;
+
+use chip8::backends::{TerminalDisplay, TerminalInput};
+use chip8::cheats::CheatSet;
+use chip8::cpu::Chip8;
+use chip8::engine::{Engine, Executor};
+use chip8::frontend::{Display, Input, FRAME_DURATION};
+use chip8::keymap::KeyMapConfig;
+use chip8::loader::{self, MachineOptions};
+use chip8::scripting::Script;
+
+const USAGE: &str = "usage: chip8-run <rom> [--frames N] [--cycles-per-frame N] [--quirks default|vip|schip] [--database file] [--engine interpreter|cached|threaded] [--seed N] [--script file] [--cheats file] [--display] [--keys file] [--check] [--against interpreter|cached|threaded]";
+
+struct Options {
+    rom: String,
+    frames: u64,
+    machine: MachineOptions,
+    engine: Engine,
+    seed: Option<u64>,
+    script: Option<String>,
+    cheats: Option<String>,
+    display: bool,
+    keys: KeyMapConfig,
+    check: bool,
+    against: Option<Engine>,
+}
+
+// Passes typed keys on at once and without echoing them while it lives.
+struct RawTerminal;
+
+// A machine with its engine and script, two of them for checking that runs repeat.
+struct Run {
+    chip8: Chip8,
+    executor: Executor,
+    script: Option<Script>,
+    cheats: Option<CheatSet>,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        frames: 600,
+        machine: MachineOptions::new(),
+        engine: Engine::default(),
+        seed: None,
+        script: None,
+        cheats: None,
+        display: false,
+        keys: KeyMapConfig::built_in(),
+        check: false,
+        against: None,
+    };
+    let mut positional = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().cloned().ok_or(format!("{} needs a value", argument));
+
+        match argument.as_str() {
+            "--engine" => {
+                let name = value()?;
+                options.engine = Engine::by_name(&name).ok_or(format!("unknown engine {}", name))?;
+            }
+            "--frames" => options.frames = value()?.parse().map_err(|_| "--frames needs a number")?,
+            "--seed" => options.seed = Some(value()?.parse().map_err(|_| "--seed needs a number")?),
+            "--script" => options.script = Some(value()?),
+            "--cheats" => options.cheats = Some(value()?),
+            "--display" => options.display = true,
+            "--keys" => options.keys = KeyMapConfig::load(&value()?)?,
+            "--check" => options.check = true,
+            "--against" => {
+                let name = value()?;
+                options.against = Some(Engine::by_name(&name).ok_or(format!("unknown engine {}", name))?);
+            }
+            _ => match options.machine.parse_argument(argument, &mut value)? {
+                true => {}
+                false => positional.push(argument.clone())
+            }
+        }
+    }
+
+    match positional.as_slice() {
+        [rom] => options.rom = rom.clone(),
+        _ => return Err(USAGE.to_string())
+    }
+
+    Ok(options)
+}
+
+impl Run {
+    fn frame(&mut self, cycles_per_frame: u32, frame: u64) -> Result<(), String> {
+        let Run { chip8, executor, script, cheats } = self;
+
+        if let Some(written) = cheats.as_ref().and_then(|cheats| cheats.apply(chip8)) {
+            executor.invalidate(written);
+        }
+
+        match script {
+            Some(script) => script.run_frame(chip8, executor, cycles_per_frame, frame),
+            None => executor.emulate_frame(chip8, cycles_per_frame).map_err(|error| error.to_string())
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<String> {
+        self.script.as_mut().map(Script::take_output).unwrap_or_default()
+    }
+
+    fn stopped(&self) -> bool {
+        self.script.as_ref().is_some_and(Script::stopped)
+    }
+}
+
+impl RawTerminal {
+    fn enter() -> RawTerminal {
+        stty(&["-icanon", "-echo", "min", "1"]);
+        RawTerminal
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        stty(&["icanon", "echo"]);
+    }
+}
+
+// Without stty, or with input that is no terminal, keys arrive a line at a time, which still works.
+fn stty(settings: &[&str]) {
+    let _ = Command::new("stty").args(settings).stdin(Stdio::inherit()).stderr(Stdio::null()).status();
+}
+
+// Names the first part of two machines' state that differs, to start looking from.
+fn difference(first: &Chip8, second: &Chip8) -> String {
+    if let Some(address) = (0..first.memory.ram.len()).find(|&address| first.memory.ram[address] != second.memory.ram[address]) {
+        return format!("memory at {:03X} is {:02X} and {:02X}", address, first.memory.ram[address], second.memory.ram[address]);
+    }
+
+    if first.registers != second.registers {
+        return format!("registers are {:?} and {:?}", first.registers, second.registers);
+    }
+
+    let parts = [
+        ("stack", first.stack.stack != second.stack.stack || first.stack.stack_pointer != second.stack.stack_pointer),
+        ("timers", (first.timers.delay_timer, first.timers.sound_timer) != (second.timers.delay_timer, second.timers.sound_timer)),
+        ("screen", first.graphics.gfx[..] != second.graphics.gfx[..]),
+        ("keys", first.keypad.keys != second.keypad.keys),
+        ("random number generator", first.rng != second.rng),
+    ];
+
+    match parts.iter().find(|(_, differs)| *differs) {
+        Some((part, _)) => format!("the {} differs", part),
+        None => "the state is the same".to_string()
+    }
+}
+
+fn run(arguments: &[String]) -> Result<(), String> {
+    let options = parse_arguments(arguments)?;
+    let rom = loader::read_rom_file(&options.rom).map_err(|error| format!("{}: {}", options.rom, error))?;
+    let (mut chip8, cycles_per_frame) = options.machine.load(&rom)?;
+    let mut display = TerminalDisplay::new(io::stdout());
+
+    if let Some(seed) = options.seed {
+        chip8.seed_rng(seed);
+    }
+
+    let cheats = options.cheats.as_deref().map(|path| CheatSet::load(Path::new(path))).transpose()?;
+
+    // A cheat file without a rom line is taken to be for any ROM.
+    if let (Some(path), Some(cheats)) = (&options.cheats, &cheats) {
+        if !cheats.rom_hash.is_empty() && !cheats.matches_rom(&rom) {
+            return Err(format!("{}: the cheats are for another ROM", path));
+        }
+    }
+
+    // The second run starts from a copy of the first machine, random number generator included.
+    let check_engine = options.against.or(options.check.then_some(options.engine));
+    let mut check = match check_engine {
+        Some(engine) => {
+            let script = options.script.as_deref().map(Script::load).transpose()?;
+
+            Some(Run { chip8: chip8.clone(), executor: Executor::new(engine), script, cheats: cheats.clone() })
+        }
+        None => None
+    };
+    let script = options.script.as_deref().map(Script::load).transpose()?;
+    let mut main = Run { chip8, executor: Executor::new(options.engine), script, cheats };
+    let (_raw_terminal, mut input) = match options.display {
+        true => (Some(RawTerminal::enter()), Some(TerminalInput::spawn(options.keys.for_rom(&rom), io::stdin()))),
+        false => (None, None)
+    };
+    let mut frame = 0;
+
+    while frame < options.frames {
+        if let Some(input) = &mut input {
+            input.poll(&mut main.chip8.keypad);
+
+            if let Some(check) = &mut check {
+                check.chip8.keypad = main.chip8.keypad.clone();
+            }
+        }
+
+        let result = main.frame(cycles_per_frame, frame);
+
+        if let Some(check) = &mut check {
+            let check_result = check.frame(cycles_per_frame, frame);
+
+            check.take_output();
+
+            if main.chip8.state_hash() != check.chip8.state_hash() {
+                return Err(format!("Diverged at frame {}: {}", frame, difference(&main.chip8, &check.chip8)));
+            }
+
+            if result != check_result {
+                return Err(format!("Diverged at frame {}: the runs ended with {:?} and {:?}", frame, result, check_result));
+            }
+        }
+
+        if let Err(error) = result {
+            eprintln!("Stopped at frame {}: {}", frame, error);
+            break;
+        }
+
+        if options.display {
+            display.present(&main.chip8.graphics.gfx);
+        }
+
+        for line in main.take_output() {
+            match options.display {
+                true => println!("{:<64}", line),
+                false => println!("frame {}: {}", frame, line)
+            }
+        }
+
+        if main.stopped() {
+            println!("Stopped by the script at frame {}", frame);
+            break;
+        }
+
+        if options.display {
+            thread::sleep(FRAME_DURATION);
+        }
+
+        frame += 1;
+    }
+
+    println!("Ran {} frames, {} instructions", frame, main.chip8.cycle_count);
+
+    if let Some(engine) = check_engine {
+        println!("A second run with the {} engine stayed in step, state hash {:016X}", engine.name(), main.chip8.state_hash());
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(error) = run(&arguments) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
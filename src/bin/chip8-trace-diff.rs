@@ -0,0 +1,105 @@
+// Runs a ROM against a trace from another emulator and reports where the two first disagree.
+// See src/trace_diff.rs for the trace format.
+use std::fs;
+use std::process;
+
+use chip8::cpu::Chip8;
+use chip8::loader::{self, MachineOptions};
+use chip8::symbols::Symbols;
+use chip8::trace_diff::{self, TraceDiff, TraceRecorder};
+
+const USAGE: &str = "usage: chip8-trace-diff <rom> <reference trace> [--quirks default|vip|schip] [--cycles-per-frame N] [--database file] [--symbols file]
+       chip8-trace-diff <rom> --record <output trace> --cycles N [--quirks default|vip|schip] [--cycles-per-frame N] [--database file]";
+
+struct Options {
+    rom: String,
+    reference: Option<String>,
+    record: Option<String>,
+    cycles: usize,
+    machine: MachineOptions,
+    symbols: Symbols,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Options, String> {
+    let mut options = Options { rom: String::new(), reference: None, record: None, cycles: 0, machine: MachineOptions::new(), symbols: Symbols::default() };
+    let mut positional = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().cloned().ok_or(format!("{} needs a value", argument));
+
+        match argument.as_str() {
+            "--cycles" => options.cycles = value()?.parse().map_err(|_| "--cycles needs a number")?,
+            "--record" => options.record = Some(value()?),
+            "--symbols" => options.symbols = Symbols::load(&value()?)?,
+            _ => match options.machine.parse_argument(argument, &mut value)? {
+                true => {}
+                false => positional.push(argument.clone())
+            }
+        }
+    }
+
+    match (positional.as_slice(), &options.record) {
+        ([rom], Some(_)) => options.rom = rom.clone(),
+        ([rom, reference], None) => {
+            options.rom = rom.clone();
+            options.reference = Some(reference.clone());
+        }
+        _ => return Err(USAGE.to_string())
+    }
+
+    Ok(options)
+}
+
+fn record(chip8: &mut Chip8, options: &Options, cycles_per_frame: u32, path: &str) -> Result<(), String> {
+    let mut recorder = TraceRecorder::default();
+
+    for cycle in 0..options.cycles {
+        if let Err(error) = chip8.emulate_cycle_observed(&mut recorder) {
+            eprintln!("Stopped at cycle {}: {}", cycle, error);
+            break;
+        }
+
+        if (cycle as u32 + 1).is_multiple_of(cycles_per_frame) {
+            chip8.tick_timers();
+        }
+    }
+
+    let lines: Vec<String> = recorder.records.iter().map(|record| record.to_string()).collect();
+
+    fs::write(path, lines.join("\n") + "\n").map_err(|error| format!("{}: {}", path, error))
+}
+
+fn run(arguments: &[String]) -> Result<bool, String> {
+    let options = parse_arguments(arguments)?;
+    let rom = loader::read_rom_file(&options.rom).map_err(|error| format!("{}: {}", options.rom, error))?;
+    let (mut chip8, cycles_per_frame) = options.machine.load(&rom)?;
+
+    if let Some(path) = &options.record {
+        record(&mut chip8, &options, cycles_per_frame, path)?;
+
+        return Ok(true);
+    }
+
+    let path = options.reference.as_ref().unwrap();
+    let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let expected = trace_diff::parse_trace(&text).map_err(|error| format!("{}: {}", path, error))?;
+    let diff = trace_diff::compare(&mut chip8, &expected, cycles_per_frame);
+
+    print!("{}", trace_diff::format_report(&diff, &chip8, &options.symbols));
+
+    Ok(matches!(diff, TraceDiff::Matched(_)))
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    match run(&arguments) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+// Searching memory for the bytes a game keeps its state in, and freezing them to fixed values.
+//
+// Cheat files belong to one ROM, identified by its SHA-1, and have one entry per line:
+//
+//     rom 3f2a...          SHA-1 of the ROM, see loader::rom_hash
+//     ram 2F0 03 lives     keep memory at 0x2F0 at 0x03, the rest of the line describes it
+//     v5 09 level          keep V5 at 0x09
+//
+// Values are hexadecimal. Blank lines and lines starting with # are ignored.
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Chip8;
+
+use crate::loader;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchFilter {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+// Narrows down the addresses that could hold a value, comparing memory against the previous snapshot.
+pub struct RamSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatTarget {
+    Memory(u16),
+    Register(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub target: CheatTarget,
+    pub value: u8,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CheatSet {
+    pub rom_hash: String,
+    pub cheats: Vec<Cheat>,
+}
+
+impl SearchFilter {
+    fn keeps(&self, previous: u8, current: u8) -> bool {
+        match *self {
+            SearchFilter::Equal(value) => current == value,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Unchanged => current == previous,
+            SearchFilter::Increased => current > previous,
+            SearchFilter::Decreased => current < previous,
+        }
+    }
+}
+
+impl RamSearch {
+    // Every address is a candidate to begin with.
+    pub fn start(ram: &[u8]) -> RamSearch {
+        RamSearch { snapshot: ram.to_vec(), candidates: (0..ram.len() as u16).collect() }
+    }
+
+    // Keeps the candidates passing the filter and takes a new snapshot to compare the next search against.
+    pub fn filter(&mut self, ram: &[u8], filter: SearchFilter) -> &[u16] {
+        let snapshot = &self.snapshot;
+
+        self.candidates.retain(|&address| match ram.get(address as usize) {
+            Some(&current) => filter.keeps(snapshot[address as usize], current),
+            None => false
+        });
+        self.snapshot = ram.to_vec();
+
+        &self.candidates
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+impl CheatSet {
+    pub fn for_rom(rom: &[u8]) -> CheatSet {
+        CheatSet { rom_hash: loader::rom_hash(rom), cheats: Vec::new() }
+    }
+
+    // Where the cheats for a ROM live in a directory of cheat files.
+    pub fn path_in(directory: &Path, rom: &[u8]) -> PathBuf {
+        directory.join(format!("{}.cht", loader::rom_hash(rom)))
+    }
+
+    pub fn load(path: &Path) -> Result<CheatSet, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+
+        CheatSet::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn parse(text: &str) -> Result<CheatSet, String> {
+        let mut cheat_set = CheatSet::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let invalid = || format!("line {}: can not read {}", index + 1, line);
+            let mut fields = line.split_whitespace();
+
+            let target = match fields.next() {
+                None => continue,
+                Some(comment) if comment.starts_with('#') => continue,
+                Some("rom") => {
+                    cheat_set.rom_hash = fields.next().ok_or_else(invalid)?.to_lowercase();
+                    continue;
+                }
+                Some("ram") => CheatTarget::Memory(fields.next().and_then(|address| u16::from_str_radix(address, 16).ok()).ok_or_else(invalid)?),
+                Some(register) => match register.strip_prefix('v').or_else(|| register.strip_prefix('V')).and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                    Some(x) if x < 16 => CheatTarget::Register(x),
+                    _ => return Err(invalid())
+                }
+            };
+
+            let value = fields.next().and_then(|value| u8::from_str_radix(value, 16).ok()).ok_or_else(invalid)?;
+            let description = fields.collect::<Vec<_>>().join(" ");
+
+            cheat_set.cheats.push(Cheat { target, value, description });
+        }
+
+        Ok(cheat_set)
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == loader::rom_hash(rom)
+    }
+
+    // Replaces any cheat on the same target.
+    pub fn freeze(&mut self, target: CheatTarget, value: u8, description: &str) {
+        self.unfreeze(target);
+        self.cheats.push(Cheat { target, value, description: description.to_string() });
+    }
+
+    pub fn unfreeze(&mut self, target: CheatTarget) {
+        self.cheats.retain(|cheat| cheat.target != target);
+    }
+
+    // Called once per frame to hold the frozen values, out of range addresses are skipped. Returns the memory it
+    // changed, which engines caching decoded code have to forget.
+    pub fn apply(&self, chip8: &mut Chip8) -> Option<Range<usize>> {
+        let mut written: Option<Range<usize>> = None;
+
+        for cheat in &self.cheats {
+            match cheat.target {
+                CheatTarget::Memory(address) => {
+                    let address = address as usize;
+
+                    match chip8.memory.ram.get_mut(address) {
+                        Some(byte) if *byte != cheat.value => *byte = cheat.value,
+                        _ => continue
+                    }
+
+                    written = Some(match written {
+                        Some(written) => written.start.min(address)..written.end.max(address + 1),
+                        None => address..address + 1
+                    });
+                }
+                CheatTarget::Register(x) => chip8.registers.v[(x & 0xF) as usize] = cheat.value,
+            }
+        }
+
+        written
+    }
+}
+
+impl fmt::Display for CheatSet {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(formatter, "rom {}", self.rom_hash)?;
+
+        for cheat in &self.cheats {
+            match cheat.target {
+                CheatTarget::Memory(address) => write!(formatter, "ram {:03X} {:02X}", address, cheat.value)?,
+                CheatTarget::Register(x) => write!(formatter, "v{:X} {:02X}", x, cheat.value)?,
+            }
+
+            match cheat.description.is_empty() {
+                true => writeln!(formatter)?,
+                false => writeln!(formatter, " {}", cheat.description)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheatSet, CheatTarget, RamSearch, SearchFilter};
+    use crate::cpu::Chip8;
+    use crate::loader;
+
+    #[test]
+    fn search_narrows_down_a_decreasing_counter() {
+        let mut ram = [0u8; 64];
+        ram[10] = 3;
+        ram[20] = 3;
+
+        let mut search = RamSearch::start(&ram);
+        assert_eq!(search.filter(&ram, SearchFilter::Equal(3)), &[10, 20]);
+
+        ram[10] = 2;
+        ram[30] = 7;
+        assert_eq!(search.filter(&ram, SearchFilter::Decreased), &[10]);
+        assert_eq!(search.filter(&ram, SearchFilter::Unchanged), &[10]);
+        assert!(search.filter(&ram, SearchFilter::Changed).is_empty());
+    }
+
+    #[test]
+    fn cheat_files_round_trip_and_freeze_values() {
+        let rom = [0x12, 0x00];
+        let mut cheats = CheatSet::for_rom(&rom);
+        let mut chip8 = Chip8::initialize();
+
+        cheats.freeze(CheatTarget::Memory(0x2F0), 0x03, "infinite lives");
+        cheats.freeze(CheatTarget::Register(5), 0x09, "");
+        cheats.freeze(CheatTarget::Memory(0x2F0), 0x05, "more lives");
+        assert_eq!(cheats.apply(&mut chip8), Some(0x2F0..0x2F1));
+        assert_eq!(cheats.apply(&mut chip8), None);
+
+        assert_eq!(chip8.memory.ram[0x2F0], 0x05);
+        assert_eq!(chip8.registers.v[5], 0x09);
+
+        let text = cheats.to_string();
+        assert!(text.ends_with("v5 09\nram 2F0 05 more lives\n"));
+        assert_eq!(CheatSet::parse(&text).unwrap(), cheats);
+        assert!(cheats.matches_rom(&rom));
+        assert!(CheatSet::parse("vG 01").is_err());
+    }
+
+    #[test]
+    fn frozen_maze_cell_in_blinky_holds_while_the_game_runs() {
+        let rom = loader::read_rom_file("roms/BLINKY").unwrap();
+        let mut cheats = CheatSet::for_rom(&rom);
+        cheats.freeze(CheatTarget::Memory(0xB92), 0x05, "pellet");
+
+        // Pressing the direction keys in turn, the pellet at 0xB92 gets eaten around frame 1950.
+        let run = |cheats: Option<&CheatSet>| {
+            let (mut chip8, cycles_per_frame) = loader::load_chip8(&rom).unwrap();
+            let mut values = Vec::new();
+
+            for frame in 0..2400u32 {
+                chip8.keypad.keys = [false; 16];
+                chip8.keypad.keys[[4, 5, 6, 3, 7, 8][(frame / 13 % 6) as usize]] = frame % 2 == 0;
+
+                if let Some(cheats) = cheats {
+                    cheats.apply(&mut chip8);
+                }
+
+                chip8.emulate_frame(cycles_per_frame).unwrap();
+                values.push(chip8.memory.ram[0xB92]);
+            }
+
+            values
+        };
+
+        assert!(run(None).contains(&0x00));
+        assert!(run(Some(&cheats)).iter().all(|&value| value == 0x05));
+    }
+}
@@ -0,0 +1,374 @@
+// Runs whole ROMs through the emulator and checks the screen they leave behind.
+// The IBM logo is the public ROM shipped in roms/. The corax+ opcode, flags, quirks and keypad ROMs of the public
+// CHIP-8 test suite live in tests/roms (see the README there) with the result screen expected for each quirks profile.
+// The small hand assembled programs below test the same things one register at a time, printed as hex digits.
+use crate::cpu::{Chip8, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use crate::loader;
+use crate::quirks::Quirks;
+
+const FONT_GLYPH_SIZE: usize = 5;
+const DIGIT_WIDTH: usize = 5;
+
+fn assemble(op_codes: &[u16]) -> Vec<u8> {
+    let mut rom: Vec<u8> = op_codes.iter().flat_map(|op_code| vec![(op_code >> 8) as u8, *op_code as u8]).collect();
+    let halt_address = 0x200 + rom.len() as u16;
+
+    rom.extend_from_slice(&[0x10 | (halt_address >> 8) as u8, halt_address as u8]);
+
+    rom
+}
+
+// Clears the screen and prints the low nibble of each register as a row of digits along the top.
+// VA and VB hold the cursor so they can not be printed.
+fn print_registers(registers: &[u16]) -> Vec<u16> {
+    let mut op_codes = vec![0x00E0, 0x6A00, 0x6B00];
+
+    for register in registers {
+        op_codes.extend_from_slice(&[0xF029 | register << 8, 0xDAB5, 0x7A00 | DIGIT_WIDTH as u16]);
+    }
+
+    op_codes
+}
+
+fn run_rom(rom: &[u8], quirks: Quirks, cycles: u32) -> Chip8 {
+    let mut chip8 = Chip8::initialize_with_quirks(quirks);
+
+    chip8.load_rom(rom).unwrap();
+
+    for _ in 0..cycles {
+        chip8.emulate_cycle().unwrap();
+    }
+
+    chip8
+}
+
+fn render_screen(chip8: &Chip8) -> String {
+    chip8.graphics.gfx
+        .chunks(SCREEN_WIDTH)
+        .map(|row| row.iter().map(|&pixel| if pixel { '#' } else { '.' }).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Reads back digits printed by print_registers by matching them against the font in memory.
+fn read_printed_digits(chip8: &Chip8, count: usize) -> String {
+    (0..count)
+        .map(|digit| {
+            let glyph: Vec<u8> = (0..FONT_GLYPH_SIZE)
+                .map(|row| {
+                    (0..4).fold(0, |byte, column| {
+                        let pixel = chip8.graphics.gfx[digit * DIGIT_WIDTH + column + row * SCREEN_WIDTH];
+
+                        byte | ((pixel as u8) << (7 - column))
+                    })
+                })
+                .collect();
+
+            chip8.memory.ram[..16 * FONT_GLYPH_SIZE]
+                .chunks(FONT_GLYPH_SIZE)
+                .position(|font_glyph| font_glyph == glyph.as_slice())
+                .map_or('?', |value| std::char::from_digit(value as u32, 16).unwrap().to_ascii_uppercase())
+        })
+        .collect()
+}
+
+#[test]
+fn ibm_logo_rom_draws_logo() {
+    let mut chip8 = loader::load_chip8_from_file("roms/IBM").unwrap();
+
+    for _ in 0..100 {
+        chip8.emulate_cycle().unwrap();
+    }
+
+    let expected = [
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "............########.#########...#####.........#####............",
+        "................................................................",
+        "............########.###########.######.......######............",
+        "................................................................",
+        "..............####.....###...###...#####.....#####..............",
+        "................................................................",
+        "..............####.....#######.....#######.#######..............",
+        "................................................................",
+        "..............####.....#######.....###.#######.###..............",
+        "................................................................",
+        "..............####.....###...###...###..#####..###..............",
+        "................................................................",
+        "............########.###########.#####...###...#####............",
+        "................................................................",
+        "............########.#########...#####....#....#####............",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+        "................................................................",
+    ];
+
+    assert_eq!(expected.len(), SCREEN_HEIGHT);
+    assert_eq!(render_screen(&chip8), expected.join("\n"));
+}
+
+#[test]
+fn hand_assembled_opcode_program_prints_expected_results() {
+    let mut op_codes = vec![
+        0x2206, // 200: call 206
+        0x120A, // 202: jump 20A
+        0x0000, // 204:
+        0x6301, // 206: V3 = 1
+        0x00EE, // 208: return
+        0x6405, // 20A: V4 = 5
+        0x3405, // 20C: skip if V4 == 5
+        0x6400, //      skipped
+        0x4405, //      skip if V4 != 5
+        0x7401, //      V4 = 6
+        0x5340, //      skip if V3 == V4
+        0x7401, //      V4 = 7
+        0x9340, //      skip if V3 != V4
+        0x6400, //      skipped
+        0x6503, //      V5 = 3
+        0x6606, //      V6 = 6
+        0x8561, //      V5 = V5 | V6 = 7
+        0x6C03, //      VC = 3
+        0x86C2, //      V6 = V6 & VC = 2
+        0x6705, //      V7 = 5
+        0x87C3, //      V7 = V7 ^ VC = 6
+        0x8840, //      V8 = V4 = 7
+        0x6C7B, //      VC = 123
+        0xA300, //      I = 300
+        0xFC33, //      BCD of VC at I
+        0xF265, //      V0..V2 = 1, 2, 3
+        0x6E05, //      VE = 5
+        0xFE1E, //      I = I + VE = 305
+        0x6D09, //      VD = 9
+        0xFD55, //      RAM[305..=312] = V0..VD
+        0xA312, //      I = 312
+        0xF065, //      V0 = RAM[312] = VD = 9
+        0x8900, //      V9 = V0
+        0xA302, //      I = 302
+        0xF065, //      V0 = 3
+    ];
+    op_codes.extend(print_registers(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]));
+
+    let chip8 = run_rom(&assemble(&op_codes), Quirks::default(), 200);
+
+    assert_eq!(read_printed_digits(&chip8, 10), "3231772679");
+}
+
+#[test]
+fn hand_assembled_flags_program_prints_expected_results() {
+    let mut op_codes = vec![
+        0x60FF, 0x6101, 0x8014, 0x82F0, // V2 = carry of FF + 01
+        0x6001, 0x6101, 0x8014, 0x83F0, // V3 = carry of 01 + 01
+        0x6001, 0x6102, 0x8015, 0x84F0, // V4 = not borrow of 01 - 02
+        0x6002, 0x6101, 0x8015, 0x85F0, // V5 = not borrow of 02 - 01
+        0x6001, 0x6102, 0x8017, 0x86F0, // V6 = not borrow of 02 - 01 through 8XY7
+        0x6081, 0x8006, 0x87F0,         // V7 = bit shifted out of 81 to the right
+        0x6081, 0x800E, 0x88F0,         // V8 = bit shifted out of 81 to the left
+        0x6FFF, 0x6101, 0x8F14, 0x89F0, // V9 = VF after FF + 01 stored in VF itself
+        0x6F01, 0x6102, 0x8F15, 0x8CF0, // VC = VF after 01 - 02 stored in VF itself
+    ];
+    op_codes.extend(print_registers(&[2, 3, 4, 5, 6, 7, 8, 9, 0xC]));
+
+    let chip8 = run_rom(&assemble(&op_codes), Quirks::default(), 200);
+
+    assert_eq!(read_printed_digits(&chip8, 9), "100111110");
+}
+
+fn quirks_program() -> Vec<u8> {
+    let mut op_codes = vec![
+        0x6000, // 200: V0 = 0
+        0x6204, // 202: V2 = 4
+        0xB20A, // 204: jump to 20A + V0, or 20A + V2 when jumps use VX
+        0x0000, // 206:
+        0x0000, // 208:
+        0x6801, // 20A: V8 = 1
+        0x1212, // 20C: jump 212
+        0x6802, // 20E: V8 = 2
+        0x1212, // 210: jump 212
+        0x6F05, // 212: VF = 5
+        0x8011, //      V0 = V0 | V1
+        0x85F0, //      V5 = VF, 0 when logic ops reset VF
+        0x6000, //      V0 = 0
+        0x6104, //      V1 = 4
+        0x8016, //      V0 = V0 >> 1, or V1 >> 1 when shifts use VY
+        0x8600, //      V6 = V0
+        0x6007, //      V0 = 7
+        0x6109, //      V1 = 9
+        0xA400, //      I = 400
+        0xF155, //      RAM[400..=401] = 7, 9
+        0xF065, //      V0 = RAM[I], 0 when I moved past the stored bytes
+        0x8700, //      V7 = V0
+        0x60FF, //      V0 = FF
+        0x6180, //      V1 = 80
+        0xA410, //      I = 410
+        0xF155, //      RAM[410..=411] = FF, 80
+        0xA410, //      I = 410
+        0x613C, //      V1 = 60
+        0x6200, //      V2 = 0
+        0xD121, //      draw 8 pixels at 60,0 which spill over the right edge
+        0xA411, //      I = 411
+        0x6100, //      V1 = 0
+        0xD121, //      draw a pixel at 0,0
+        0x89F0, //      V9 = VF, 1 when the first sprite wrapped around
+    ];
+    op_codes.extend(print_registers(&[5, 6, 7, 8, 9]));
+
+    assemble(&op_codes)
+}
+
+#[test]
+fn hand_assembled_quirks_program_prints_expected_results_for_each_profile() {
+    let profiles = [
+        (Quirks::cosmac_vip(), "02010"),
+        (Quirks::super_chip(), "50720"),
+        (Quirks::default(), "50711"),
+    ];
+
+    for (quirks, expected) in profiles.iter() {
+        let chip8 = run_rom(&quirks_program(), *quirks, 200);
+
+        assert_eq!(read_printed_digits(&chip8, 5), *expected, "{:?}", quirks);
+    }
+}
+
+#[test]
+fn hand_assembled_keypad_program_waits_for_key_and_prints_it() {
+    let mut op_codes = vec![
+        0xF00A, // V0 = next key pressed
+        0x6100, // V1 = 0
+        0xE09E, // skip if key V0 is pressed
+        0x6101, //      skipped while the key is held
+        0x6200, // V2 = 0
+        0xE0A1, // skip if key V0 is not pressed
+        0x6202, //      V2 = 2 while the key is held
+    ];
+    op_codes.extend(print_registers(&[0, 1, 2]));
+
+    let mut chip8 = run_rom(&assemble(&op_codes), Quirks::default(), 100);
+
+    assert!(chip8.graphics.gfx.iter().all(|&pixel| !pixel));
+    assert_eq!(chip8.registers.program_counter, 0x200);
+
+    chip8.keypad.keys[0xB] = true;
+
+    for _ in 0..100 {
+        chip8.emulate_cycle().unwrap();
+    }
+
+    assert_eq!(read_printed_digits(&chip8, 3), "B02");
+}
+
+// A ROM of the public test suite and how to run it. Poking a value at 1FF picks the platform or the test
+// from the ROM's menu without pressing keys, as the suite documents.
+struct SuiteRom {
+    file: &'static str,
+    menu_choice: fn(&str) -> Option<u8>,
+    frames: u32,
+    // (frame, key) to hold a key from that frame on, key FF letting go of all of them.
+    keys: &'static [(u32, u8)],
+}
+
+const SUITE_DIRECTORY: &str = "tests/roms";
+
+fn suite_profiles() -> [(&'static str, Quirks); 3] {
+    [("cosmac_vip", Quirks::cosmac_vip()), ("super_chip", Quirks::super_chip()), ("default", Quirks::default())]
+}
+
+const SUITE_ROMS: [SuiteRom; 4] = [
+    SuiteRom { file: "3-corax+.ch8", menu_choice: |_| None, frames: 120, keys: &[] },
+    SuiteRom { file: "4-flags.ch8", menu_choice: |_| None, frames: 120, keys: &[] },
+    // 1 for CHIP-8, 2 for SUPER-CHIP. The default profile has every quirk off, which is closest to CHIP-8.
+    SuiteRom {
+        file: "5-quirks.ch8",
+        menu_choice: |profile| match profile {
+            "super_chip" => Some(2),
+            _ => Some(1)
+        },
+        frames: 600,
+        keys: &[],
+    },
+    // The FX0A test, answered with key 5 held and let go.
+    SuiteRom { file: "6-keypad.ch8", menu_choice: |_| Some(3), frames: 120, keys: &[(30, 0x5), (60, 0xFF)] },
+];
+
+fn run_suite_rom(rom: &[u8], suite_rom: &SuiteRom, profile: &str, quirks: Quirks) -> Chip8 {
+    let mut chip8 = Chip8::initialize_with_quirks(quirks);
+
+    chip8.seed_rng(0);
+    chip8.load_rom(rom).unwrap();
+
+    if let Some(choice) = (suite_rom.menu_choice)(profile) {
+        chip8.memory.ram[0x1FF] = choice;
+    }
+
+    for frame in 0..suite_rom.frames {
+        for &(_, key) in suite_rom.keys.iter().filter(|&&(from, _)| from == frame) {
+            chip8.keypad.keys = [false; 16];
+
+            if key < 16 {
+                chip8.keypad.keys[key as usize] = true;
+            }
+        }
+
+        if let Err(error) = chip8.emulate_frame(15) {
+            panic!("{} with {} failed at {:03X}: {}", suite_rom.file, profile, chip8.registers.program_counter, error);
+        }
+    }
+
+    chip8
+}
+
+// Compares each suite ROM's result screen with tests/roms/<ROM>.<profile>.txt, failing for a ROM or screen that is
+// missing. Ignored until scripts/fetch-test-suite.sh has fetched the ROMs, run it with --ignored then.
+// With CHIP8_RECORD_SCREENS set, missing screens are written instead, to be checked against the suite's
+// documentation before committing them.
+#[test]
+#[ignore = "needs the test suite ROMs in tests/roms, see the README there"]
+fn test_suite_roms_show_expected_results_for_each_profile() {
+    let record = std::env::var_os("CHIP8_RECORD_SCREENS").is_some();
+
+    for suite_rom in SUITE_ROMS.iter() {
+        let path = format!("{}/{}", SUITE_DIRECTORY, suite_rom.file);
+        let rom = loader::read_rom_file(&path).unwrap_or_else(|error| panic!("{}: {}, see {}/README.md", path, error, SUITE_DIRECTORY));
+
+        for (profile, quirks) in suite_profiles().iter() {
+            let screen = render_screen(&run_suite_rom(&rom, suite_rom, profile, *quirks));
+            let expected_path = format!("{}.{}.txt", path, profile);
+
+            match (std::fs::read_to_string(&expected_path), record) {
+                (Ok(expected), _) => assert_eq!(screen, expected.trim_end(), "{} with {}", suite_rom.file, profile),
+                (Err(_), true) => std::fs::write(&expected_path, screen + "\n").unwrap(),
+                (Err(_), false) => panic!("{} is missing, the screen was:\n{}", expected_path, screen)
+            }
+        }
+    }
+}
+
+#[test]
+fn roms_in_repository_run_without_errors() {
+    for entry in std::fs::read_dir("roms").unwrap() {
+        let path = entry.unwrap().path();
+        let mut chip8 = loader::load_chip8_from_file(path.to_str().unwrap()).unwrap();
+
+        for frame in 0..600 {
+            chip8.keypad.keys = [false; 16];
+            chip8.keypad.keys[(frame / 7) % 16] = frame % 3 == 0;
+
+            if let Err(error) = chip8.emulate_frame(10) {
+                panic!("{} failed at {:03X}: {}", path.display(), chip8.registers.program_counter, error);
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Records, for every byte of memory, how the ROM used it: fetched as part of an op code,
+// read as data by DXYN or FX65, or written by FX33 and FX55.
+use std::ops::{Range, RangeInclusive};
+
+use serde_json::{json, Value};
+
+use crate::cpu::{Chip8, Cycle, CycleObserver};
+use crate::instruction::Instruction;
+
+pub const EXECUTED: u8 = 0b001;
+pub const READ: u8 = 0b010;
+pub const WRITTEN: u8 = 0b100;
+
+const ADDRESS_SPACE: usize = 4096;
+const BYTES_PER_LINE: usize = 16;
+
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage { flags: vec![0; ADDRESS_SPACE] }
+    }
+
+    // A combination of EXECUTED, READ and WRITTEN.
+    pub fn flags_at(&self, address: u16) -> u8 {
+        self.flags.get(address as usize).cloned().unwrap_or(0)
+    }
+
+    // Runs of consecutive addresses that have all of the given flags.
+    pub fn ranges(&self, flags: u8) -> Vec<RangeInclusive<u16>> {
+        self.runs(|byte_flags| byte_flags & flags == flags)
+    }
+
+    // Runs of addresses within `within` that were never executed, read or written, e.g. dead code.
+    pub fn untouched(&self, within: Range<usize>) -> Vec<RangeInclusive<u16>> {
+        self.runs(|byte_flags| byte_flags == 0)
+            .into_iter()
+            .filter_map(|range| {
+                let start = (*range.start() as usize).max(within.start);
+                let end = (*range.end() as usize + 1).min(within.end);
+                match start < end {
+                    true => Some(start as u16..=(end - 1) as u16),
+                    false => None
+                }
+            })
+            .collect()
+    }
+
+    fn runs<F: Fn(u8) -> bool>(&self, included: F) -> Vec<RangeInclusive<u16>> {
+        let mut runs = Vec::new();
+        let mut start = None;
+
+        for (address, &byte_flags) in self.flags.iter().enumerate() {
+            match (included(byte_flags), start) {
+                (true, None) => start = Some(address),
+                (false, Some(run_start)) => {
+                    runs.push(run_start as u16..=(address - 1) as u16);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(run_start) = start {
+            runs.push(run_start as u16..=(self.flags.len() - 1) as u16);
+        }
+
+        runs
+    }
+
+    // A hex dump of `range` with each byte followed by how it was used:
+    // x executed, r read, w written, * more than one of them, . untouched.
+    pub fn annotated_hex_dump(&self, ram: &[u8], range: Range<usize>) -> String {
+        let end = range.end.min(ram.len()).min(self.flags.len());
+        let mut dump = String::new();
+        let mut line_start = range.start - range.start % BYTES_PER_LINE;
+
+        while line_start < end {
+            dump.push_str(&format!("{:03X} ", line_start));
+
+            let bytes: String = (line_start..line_start + BYTES_PER_LINE)
+                .map(|address| match address >= range.start && address < end {
+                    true => format!(" {:02X}{}", ram[address], usage_marker(self.flags[address])),
+                    false => "    ".to_string()
+                })
+                .collect();
+
+            dump.push_str(&bytes);
+
+            dump.push('\n');
+            line_start += BYTES_PER_LINE;
+        }
+
+        dump
+    }
+
+    // Inclusive address ranges per kind of use, plus byte counts within `rom`.
+    pub fn to_json(&self, rom: Range<usize>) -> Value {
+        let ranges = |flags| self.ranges(flags).into_iter().map(|range| json!([range.start(), range.end()])).collect::<Vec<_>>();
+        let count = |used: &dyn Fn(u8) -> bool| self.flags[rom.clone()].iter().filter(|&&byte_flags| used(byte_flags)).count();
+
+        json!({
+            "rom": { "start": rom.start, "end": rom.end },
+            "executed": ranges(EXECUTED),
+            "read": ranges(READ),
+            "written": ranges(WRITTEN),
+            "untouched": self.untouched(rom.clone()).into_iter().map(|range| json!([range.start(), range.end()])).collect::<Vec<_>>(),
+            "summary": {
+                "executed_bytes": count(&|byte_flags| byte_flags & EXECUTED != 0),
+                "read_bytes": count(&|byte_flags| byte_flags & READ != 0),
+                "written_bytes": count(&|byte_flags| byte_flags & WRITTEN != 0),
+                "untouched_bytes": count(&|byte_flags| byte_flags == 0),
+            },
+        })
+    }
+
+    fn mark(&mut self, start: u16, length: usize, flag: u8) {
+        let start = start as usize;
+        let end = (start + length).min(self.flags.len());
+
+        for byte_flags in self.flags[start.min(end)..end].iter_mut() {
+            *byte_flags |= flag;
+        }
+    }
+}
+
+fn usage_marker(flags: u8) -> char {
+    match flags {
+        0 => '.',
+        EXECUTED => 'x',
+        READ => 'r',
+        WRITTEN => 'w',
+        _ => '*'
+    }
+}
+
+impl CycleObserver for Coverage {
+    fn on_cycle(&mut self, _chip8: &Chip8, cycle: &Cycle) {
+        self.mark(cycle.address, 2, EXECUTED);
+
+        // A failed instruction touches nothing, and I is taken from before the instruction ran.
+        if cycle.result.is_err() {
+            return;
+        }
+
+        let i = cycle.registers.i;
+
+        match Instruction::decode(cycle.op_code) {
+            Ok(Instruction::Draw(_, _, height)) => self.mark(i, height as usize, READ),
+            Ok(Instruction::LoadRegisters(x)) => self.mark(i, x as usize + 1, READ),
+            Ok(Instruction::StoreRegisters(x)) => self.mark(i, x as usize + 1, WRITTEN),
+            Ok(Instruction::StoreBcd(_)) => self.mark(i, 3, WRITTEN),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coverage, EXECUTED, READ, WRITTEN};
+    use crate::cpu::Chip8;
+
+    // LD I, 20C / DRW V0, V0, 2 / LD V1, 01 / FX33 / JP 208, then an unreached JP 208, 2 bytes of sprite data and a dead op code.
+    const ROM: [u8; 16] = [0xA2, 0x0C, 0xD0, 0x02, 0x61, 0x01, 0xF1, 0x33, 0x12, 0x08, 0x12, 0x08, 0xF0, 0x90, 0x00, 0xE0];
+
+    fn cover(cycles: usize) -> (Chip8, Coverage) {
+        let mut chip8 = Chip8::initialize();
+        let mut coverage = Coverage::new();
+
+        chip8.load_rom(&ROM).unwrap();
+
+        for _ in 0..cycles {
+            chip8.emulate_cycle_observed(&mut coverage).unwrap();
+        }
+
+        (chip8, coverage)
+    }
+
+    #[test]
+    fn tracks_executed_read_and_written_bytes() {
+        let (_, coverage) = cover(8);
+
+        assert_eq!(coverage.ranges(EXECUTED), vec![0x200..=0x209]);
+        assert_eq!(coverage.ranges(READ), vec![0x20C..=0x20D]);
+        assert_eq!(coverage.ranges(WRITTEN), vec![0x20C..=0x20E]);
+        assert_eq!(coverage.ranges(READ | WRITTEN), vec![0x20C..=0x20D]);
+        assert_eq!(coverage.untouched(0x200..0x210), vec![0x20A..=0x20B, 0x20F..=0x20F]);
+    }
+
+    #[test]
+    fn exports_hex_dump_and_json() {
+        let (chip8, coverage) = cover(8);
+
+        assert_eq!(
+            coverage.annotated_hex_dump(&chip8.memory.ram, 0x200..0x210),
+            "200  A2x 0Cx D0x 02x 61x 01x F1x 33x 12x 08x 12. 08. 00* 00* 01w E0.\n"
+        );
+
+        let json = coverage.to_json(0x200..0x210);
+        assert_eq!(json["executed"][0][1], 0x209);
+        assert_eq!(json["summary"]["untouched_bytes"], 3);
+    }
+}
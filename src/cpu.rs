@@ -1,48 +1,49 @@
-use rand::prelude::{Rng, SeedableRng};
-use std::fs::File;
-use std::io::prelude::*;
-use std::sync::Mutex;
-
-lazy_static! {
-    pub static ref CHIP8: Mutex<Chip8> = Mutex::new(Chip8 {
-        registers: Registers { program_counter: 0x200, i: 0, v: [0; 16] },
-        memory: Memory { ram: [0; 4096] },
-        graphics: Graphics { gfx: [false; 2048], redraw: false },
-        timers: Timers { sound_timer: 0, delay_timer: 0 },
-        stack: Stack { stack: [0; 16], stack_pointer: 0 },
-        keypad: Keypad { keys: [false; 16] }
-    });
-}
+// The machine itself, free of std and allocation so it also runs on microcontrollers.
+// Reading ROMs from files and everything else a host provides lives in std-only modules such as loader.
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::rng::XorShift;
+
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
 
+#[derive(Clone)]
 pub struct Memory {
     pub ram: [u8; 4096]
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Registers {
     pub v: [u8; 16],
     pub i: u16,
     pub program_counter: u16,
 }
 
+#[derive(Clone)]
 pub struct Graphics {
-    pub gfx: [bool; 64 * 32],
+    pub gfx: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
     pub redraw: bool,
 }
 
+#[derive(Clone)]
 pub struct Timers {
     pub delay_timer: u8,
     pub sound_timer: u8,
 }
 
+#[derive(Clone)]
 pub struct Stack {
     pub stack: [u16; 16],
     pub stack_pointer: u16,
 }
 
+#[derive(Clone)]
 pub struct Keypad {
     pub keys: [bool; 16]
 }
 
+#[derive(Clone)]
 pub struct Chip8 {
     pub memory: Memory,
     pub registers: Registers,
@@ -50,10 +51,30 @@ pub struct Chip8 {
     pub timers: Timers,
     pub stack: Stack,
     pub keypad: Keypad,
+    pub quirks: Quirks,
+    pub cycle_count: u64,
+    pub rng: XorShift,
+}
+
+// Runs a decoded op code, see Chip8::decode.
+pub type Handler = fn(&mut Chip8, u16) -> Result<(), Chip8Error>;
+
+// The state an instruction started from, handed to a CycleObserver once it has run.
+pub struct Cycle {
+    pub number: u64,
+    pub address: u16,
+    pub op_code: u16,
+    pub registers: Registers,
+    pub stack_pointer: u16,
+    pub result: Result<(), Chip8Error>,
 }
 
-pub fn get_pointer_to_gfx() -> *const bool {    
-    CHIP8.lock().unwrap().graphics.gfx.as_ptr()
+// Watches execution without being part of it, for tracing, profiling and the like.
+pub trait CycleObserver {
+    fn on_cycle(&mut self, chip8: &Chip8, cycle: &Cycle);
+
+    // Called by emulate_frame_observed once the timers of a frame have been ticked.
+    fn on_frame(&mut self, _chip8: &Chip8) {}
 }
 
 impl Memory {
@@ -81,12 +102,25 @@ impl Memory {
         self.ram[..80].copy_from_slice(&font_set);
     }
 
-    fn load_rom(&mut self, rom: &[u8]) {
+    fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        if rom.len() > self.ram.len() - 512 {
+            return Err(Chip8Error::RomTooLarge(rom.len()));
+        }
+
         self.ram[512..rom.len() + 512].copy_from_slice(rom);
+
+        Ok(())
+    }
+
+    fn check_range(&self, start: u16, length: usize) -> Result<(), Chip8Error> {
+        match start as usize + length {
+            end if end > self.ram.len() => Err(Chip8Error::MemoryOutOfBounds(self.ram.len().max(start as usize))),
+            _ => Ok(())
+        }
     }
 
-    fn current_pixel_is_on(&self, register_i: u16, axis_x: u8, axis_y: u8) -> bool {
-        self.ram[(register_i + axis_y as u16) as usize] & (0x80 >> axis_x) == 1
+    fn sprite_pixel_is_on(&self, register_i: u16, axis_x: u8, axis_y: u8) -> bool {
+        self.ram[register_i as usize + axis_y as usize] & (0x80 >> axis_x) != 0
     }
 }
 
@@ -95,30 +129,41 @@ impl Registers {
         self.v[0xF] = value;
     }
 
+    #[cfg(test)]
     fn get_register_v_f_value(&self) -> u8 { self.v[0xF] }
 }
 
 impl Graphics {
-    fn current_pixel_is_on(&self, coord_x: u8, axis_x: u8, coord_y: u8, axis_y: u8) -> bool {
-        self.gfx[(coord_x + axis_x + ((coord_y + axis_y) * 64)) as usize]
+    fn pixel_index(coord_x: usize, coord_y: usize) -> usize {
+        coord_x + coord_y * SCREEN_WIDTH
     }
 
-    fn change_pixel_value(&mut self, coord_x: u8, axis_x: u8, coord_y: u8, axis_y: u8) {
-        let pixel = self.gfx[(coord_x + axis_x + ((coord_y + axis_y) * 64)) as usize];
+    fn current_pixel_is_on(&self, coord_x: usize, coord_y: usize) -> bool {
+        self.gfx[Graphics::pixel_index(coord_x, coord_y)]
+    }
+
+    fn change_pixel_value(&mut self, coord_x: usize, coord_y: usize) {
+        let pixel = self.gfx[Graphics::pixel_index(coord_x, coord_y)];
 
-        self.gfx[(coord_x + axis_x + ((coord_y + axis_y) * 64)) as usize] = !pixel;
+        self.gfx[Graphics::pixel_index(coord_x, coord_y)] = !pixel;
     }
 
-    
     fn clear_screen(&mut self) {
-        self.gfx = [false; 2048];
+        self.gfx = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
     } 
 }
 
+impl Timers {
+    fn tick(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+}
+
 trait OpCode {
     fn extract_nibble_value(self, nibble_place: u8) -> u8;
     fn extract_arguments(&self) -> [u16; 3];
-    fn get_argument_sum<T>(self, range: T) -> u16 where T: std::slice::SliceIndex<[u16], Output=[u16]>;
+    fn get_argument_sum<T>(self, range: T) -> u16 where T: core::slice::SliceIndex<[u16], Output=[u16]>;
 }
 
 impl OpCode for u16 {
@@ -140,141 +185,248 @@ impl OpCode for u16 {
         ]
     }
 
-    fn get_argument_sum<T>(self, range: T) -> u16 where T: std::slice::SliceIndex<[u16], Output=[u16]> {
+    fn get_argument_sum<T>(self, range: T) -> u16 where T: core::slice::SliceIndex<[u16], Output=[u16]> {
         self.extract_arguments()[range]
             .iter()
-            .fold(0, |a, &b| a + b)
+            .sum()
     }
 }
 
 impl Chip8 {
     pub fn initialize() -> Chip8 {
+        Chip8::initialize_with_quirks(Quirks::default())
+    }
+
+    pub fn initialize_with_quirks(quirks: Quirks) -> Chip8 {
         let mut chip8 = Chip8 {
             registers: Registers { program_counter: 0x200, i: 0, v: [0; 16] },
             memory: Memory { ram: [0; 4096] },
-            graphics: Graphics { gfx: [false; 2048], redraw: false },
+            graphics: Graphics { gfx: [false; SCREEN_WIDTH * SCREEN_HEIGHT], redraw: false },
             timers: Timers { sound_timer: 0, delay_timer: 0 },
             stack: Stack { stack: [0; 16], stack_pointer: 0 },
-            keypad: Keypad { keys: [false; 16] }
+            keypad: Keypad { keys: [false; 16] },
+            quirks,
+            cycle_count: 0,
+            rng: XorShift::new(initial_seed())
         };
 
         chip8.memory.load_font_set();
 
         chip8
-    }    
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        self.memory.load_rom(rom)
+    }
 
-    fn emulate_cycle(&mut self) {
-        let op_code: u16 = self.fetch_op_code();
+    // CXKK gives the same numbers after the same seed.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = XorShift::new(seed);
+    }
 
-        // Execute Opcode
-        self.execute_op_code(op_code);
+    // A hash of everything deciding how the machine goes on: memory, registers, stack, timers, screen, keys and the
+    // random number generator. Quirks and the cycle count are left out. It is FNV-1a over the bytes in a fixed order,
+    // so it is the same on every platform and build, for comparing runs across machines, engines and replays.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = StateHash::new();
 
-        // Update timers
-        self.registers.program_counter += 2;
+        hash.write(&self.memory.ram);
+        hash.write(&self.registers.v);
+        hash.write_u16(self.registers.i);
+        hash.write_u16(self.registers.program_counter);
+        self.stack.stack.iter().for_each(|&address| hash.write_u16(address));
+        hash.write_u16(self.stack.stack_pointer);
+        hash.write(&[self.timers.delay_timer, self.timers.sound_timer]);
+        self.graphics.gfx.iter().for_each(|&on| hash.write(&[on as u8]));
+        self.keypad.keys.iter().for_each(|&down| hash.write(&[down as u8]));
+        hash.write(&self.rng.state().to_le_bytes());
+        hash.0
     }
 
-    fn fetch_op_code(&self) -> u16 {
-        (self.memory.ram[self.registers.program_counter as usize] as u16)
+    // Runs one 60Hz frame worth of instructions and counts the timers down once.
+    pub fn emulate_frame(&mut self, cycles_per_frame: u32) -> Result<(), Chip8Error> {
+        for _ in 0..cycles_per_frame {
+            self.emulate_cycle()?;
+        }
+
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    // Counts the delay and sound timers down, to be called at 60Hz when running cycles one by one.
+    pub fn tick_timers(&mut self) {
+        self.timers.tick();
+    }
+
+    // On error the program counter is left pointing at the instruction that failed.
+    pub fn emulate_cycle(&mut self) -> Result<(), Chip8Error> {
+        let address = self.registers.program_counter;
+        let op_code: u16 = self.fetch_op_code()?;
+
+        self.registers.program_counter = address.wrapping_add(2);
+
+        let result = self.execute_op_code(op_code);
+
+        match result {
+            Ok(()) => self.cycle_count += 1,
+            Err(_) => self.registers.program_counter = address
+        }
+
+        result
+    }
+
+    pub fn emulate_frame_observed(&mut self, cycles_per_frame: u32, observer: &mut dyn CycleObserver) -> Result<(), Chip8Error> {
+        for _ in 0..cycles_per_frame {
+            self.emulate_cycle_observed(observer)?;
+        }
+
+        self.tick_timers();
+        observer.on_frame(self);
+
+        Ok(())
+    }
+
+    // Observers only hear about instructions that could be fetched.
+    pub fn emulate_cycle_observed(&mut self, observer: &mut dyn CycleObserver) -> Result<(), Chip8Error> {
+        let mut cycle = Cycle {
+            number: self.cycle_count,
+            address: self.registers.program_counter,
+            op_code: self.fetch_op_code()?,
+            registers: self.registers,
+            stack_pointer: self.stack.stack_pointer,
+            result: Ok(()),
+        };
+
+        cycle.result = self.emulate_cycle();
+
+        observer.on_cycle(self, &cycle);
+
+        cycle.result
+    }
+
+    pub(crate) fn fetch_op_code(&self) -> Result<u16, Chip8Error> {
+        self.memory.check_range(self.registers.program_counter, 2)?;
+
+        Ok((self.memory.ram[self.registers.program_counter as usize] as u16)
             << 8
-            | (self.memory.ram[(self.registers.program_counter + 1) as usize] as u16) 
-    }
-
-    fn execute_op_code(&mut self, op_code: u16) {
-        match op_code {
-            0x00EE => self.return_from_subroutine(),
-            0x1000...0x1FFF => self.jump_to_location(op_code),
-            0x2000...0x2FFF => self.call_subroutine(op_code),
-            0x3000...0x3FFF => self.skip_next_if_vx_eq_kk(op_code),
-            0x4000...0x4FFF => self.skip_next_if_vx_neq_kk(op_code),
-            0x5000...0x5FF0 => self.skip_next_if_vx_eq_vy(op_code),
-            0x6000...0x6FFF => self.set_vx_to(op_code),
-            0x7000...0x7FFF => self.add_kk_to_vx(op_code),
-            0x8000...0x8FFF => {
-                match op_code.extract_nibble_value(4) {
-                    0 => self.set_vx_to_vy(op_code),
-                    1 => self.set_vx_to_vx_and_vy_bitwise_or(op_code),
-                    2 => self.set_vx_to_vx_and_vy_bitwise_and(op_code),
-                    3 => self.set_vx_to_vx_and_vy_bitwise_xor(op_code),
-                    4 => self.set_vx_to_vx_and_vy_sum(op_code),
-                    5 => self.set_vx_to_vx_and_vy_difference(op_code),
-                    6 => self.set_vx_to_vx_shift_right(op_code),
-                    7 => self.set_vx_to_vy_and_vx_difference(op_code),
-                    0xE => self.set_vx_to_vx_shift_left(op_code),
-                    _ => return
-                }
-            }
-            0x9000...0x9FFF => self.skip_next_if_vx_neq_vy(op_code),
-            0xA000...0xAFFF => self.set_register_i_address(op_code),
-            0xB000...0xBFFF => self.jump_to_location_plus_v_0(op_code),
-            0xC000...0xCFFF => self.set_vx_to_random_and_kk(op_code),
-            0xD000...0xDFFF => self.draw_sprite(op_code),
-            0xE000...0xEFFF => {
-                match op_code.extract_nibble_value(3) {
-                    9 => self.skip_next_if_key_pressed(op_code),
-                    0xA => self.skip_next_if_key_not_pressed(op_code),
-                    _ => return
-                }
-            },
-            0xF000...0xFFFF => {
-                match op_code.extract_nibble_value(3) {
-                    0 => {
-                        match op_code.extract_nibble_value(4) {
-                            7 => self.set_vx_to_delay_timer(op_code),
-                            0xA => self.await_key_and_store_to_value_vx(op_code),                            
-                            _ => return
-                        }
-                    },
-                    1 => {
-                        match op_code.extract_nibble_value(4) {
-                            5 => self.set_delay_timer_to_vx(op_code),
-                            8 => self.set_sound_timer_to_vx(op_code),
-                            0xE => self.set_i_to_sum_of_i_and_vx(op_code),
-                            _ => return
-                        }
-                    },
-                    2 => self.set_i_to_location_of_sprite_vx(op_code),
-                    3 => self.store_bcd_of_vx_in_i(op_code),
-                    5 => self.store_through_v0_to_vx_in_memory(op_code),
-                    6 => self.store_from_memory_through_v0_to_vx(op_code),                                            
-                    _ => return
+            | (self.memory.ram[self.registers.program_counter as usize + 1] as u16))
+    }
+
+    pub(crate) fn execute_op_code(&mut self, op_code: u16) -> Result<(), Chip8Error> {
+        Chip8::decode(op_code)?(self, op_code)
+    }
+
+    // Finds the method running an op code, so it can be looked up once and run many times.
+    // Decoding goes through Instruction::decode so the disassembler and the core accept the same op codes.
+    // 0NNN calls machine code routines on the original hardware and is ignored.
+    pub fn decode(op_code: u16) -> Result<Handler, Chip8Error> {
+        macro_rules! infallible {
+            ($method:ident) => {
+                |chip8, op_code| {
+                    chip8.$method(op_code);
+                    Ok(())
                 }
-            }
-            _ => return
+            };
         }
+
+        let handler: Handler = match Instruction::decode(op_code)? {
+            Instruction::ClearScreen => |chip8, _| {
+                chip8.clear_screen();
+                Ok(())
+            },
+            Instruction::Return => |chip8, _| chip8.return_from_subroutine(),
+            Instruction::System(_) => |_, _| Ok(()),
+            Instruction::Jump(_) => infallible!(jump_to_location),
+            Instruction::Call(_) => Chip8::call_subroutine,
+            Instruction::SkipIfEqual(..) => infallible!(skip_next_if_vx_eq_kk),
+            Instruction::SkipIfNotEqual(..) => infallible!(skip_next_if_vx_neq_kk),
+            Instruction::SkipIfRegistersEqual(..) => infallible!(skip_next_if_vx_eq_vy),
+            Instruction::Load(..) => infallible!(set_vx_to),
+            Instruction::Add(..) => infallible!(add_kk_to_vx),
+            Instruction::Move(..) => infallible!(set_vx_to_vy),
+            Instruction::Or(..) => infallible!(set_vx_to_vx_and_vy_bitwise_or),
+            Instruction::And(..) => infallible!(set_vx_to_vx_and_vy_bitwise_and),
+            Instruction::Xor(..) => infallible!(set_vx_to_vx_and_vy_bitwise_xor),
+            Instruction::AddRegisters(..) => infallible!(set_vx_to_vx_and_vy_sum),
+            Instruction::Subtract(..) => infallible!(set_vx_to_vx_and_vy_difference),
+            Instruction::ShiftRight(..) => infallible!(set_vx_to_vx_shift_right),
+            Instruction::SubtractReversed(..) => infallible!(set_vx_to_vy_and_vx_difference),
+            Instruction::ShiftLeft(..) => infallible!(set_vx_to_vx_shift_left),
+            Instruction::SkipIfRegistersNotEqual(..) => infallible!(skip_next_if_vx_neq_vy),
+            Instruction::LoadI(_) => infallible!(set_register_i_address),
+            Instruction::JumpWithOffset(_) => infallible!(jump_to_location_plus_v_0),
+            Instruction::Random(..) => infallible!(set_vx_to_random_and_kk),
+            Instruction::Draw(..) => Chip8::draw_sprite,
+            Instruction::SkipIfKeyPressed(_) => infallible!(skip_next_if_key_pressed),
+            Instruction::SkipIfKeyNotPressed(_) => infallible!(skip_next_if_key_not_pressed),
+            Instruction::LoadDelayTimer(_) => infallible!(set_vx_to_delay_timer),
+            Instruction::WaitForKey(_) => infallible!(await_key_and_store_to_value_vx),
+            Instruction::SetDelayTimer(_) => infallible!(set_delay_timer_to_vx),
+            Instruction::SetSoundTimer(_) => infallible!(set_sound_timer_to_vx),
+            Instruction::AddToI(_) => infallible!(set_i_to_sum_of_i_and_vx),
+            Instruction::LoadFontCharacter(_) => infallible!(set_i_to_location_of_sprite_vx),
+            Instruction::StoreBcd(_) => Chip8::store_bcd_of_vx_in_i,
+            Instruction::StoreRegisters(_) => Chip8::store_through_v0_to_vx_in_memory,
+            Instruction::LoadRegisters(_) => Chip8::store_from_memory_through_v0_to_vx,
+        };
+
+        Ok(handler)
     }
 
-    fn return_from_subroutine(&mut self) {
+    fn clear_screen(&mut self) {
+        self.graphics.clear_screen();
+        self.graphics.redraw = true;
+    }
+
+    fn return_from_subroutine(&mut self) -> Result<(), Chip8Error> {
+        if self.stack.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
+
         self.stack.stack_pointer -= 1;
 
         self.registers.program_counter = self.stack.stack[self.stack.stack_pointer as usize];
+
+        Ok(())
     }
 
     fn jump_to_location(&mut self, op_code: u16) {
         self.registers.program_counter = op_code.get_argument_sum(..);
     }
 
-    fn call_subroutine(&mut self, op_code: u16) {
+    fn call_subroutine(&mut self, op_code: u16) -> Result<(), Chip8Error> {
+        if self.stack.stack_pointer as usize >= self.stack.stack.len() {
+            return Err(Chip8Error::StackOverflow);
+        }
+
         self.stack.stack[self.stack.stack_pointer as usize] = self.registers.program_counter;
         self.stack.stack_pointer += 1;
 
         self.registers.program_counter = op_code.get_argument_sum(..);
+
+        Ok(())
+    }
+
+    fn skip_next_instruction(&mut self) {
+        self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
     }
 
     fn skip_next_if_vx_eq_kk(&mut self, op_code: u16) {
         if self.registers.v[op_code.extract_nibble_value(2) as usize] == op_code.get_argument_sum(1..) as u8 {
-            self.registers.program_counter += 2;
+            self.skip_next_instruction();
         }
     }
 
     fn skip_next_if_vx_neq_kk(&mut self, op_code: u16) {
         if self.registers.v[op_code.extract_nibble_value(2) as usize] != op_code.get_argument_sum(1..) as u8 {
-            self.registers.program_counter += 2;
+            self.skip_next_instruction();
         }
     }
 
     fn skip_next_if_vx_eq_vy(&mut self, op_code: u16) {
         if self.registers.v[op_code.extract_nibble_value(2) as usize] == self.registers.v[op_code.extract_nibble_value(3) as usize] {
-            self.registers.program_counter += 2;
+            self.skip_next_instruction();
         }
     }
 
@@ -283,7 +435,9 @@ impl Chip8 {
     }
 
     fn add_kk_to_vx(&mut self, op_code: u16) {
-        self.registers.v[op_code.extract_nibble_value(2) as usize] += op_code.get_argument_sum(1..) as u8;
+        let vx = op_code.extract_nibble_value(2) as usize;
+
+        self.registers.v[vx] = self.registers.v[vx].wrapping_add(op_code.get_argument_sum(1..) as u8);
     }
 
     fn set_vx_to_vy(&mut self, op_code: u16) {
@@ -292,119 +446,150 @@ impl Chip8 {
 
     fn set_vx_to_vx_and_vy_bitwise_or(&mut self, op_code: u16) {
         self.registers.v[op_code.extract_nibble_value(2) as usize] |= self.registers.v[op_code.extract_nibble_value(3) as usize];
+        self.reset_v_f_after_logic_op();
     }
 
     fn set_vx_to_vx_and_vy_bitwise_and(&mut self, op_code: u16) {
         self.registers.v[op_code.extract_nibble_value(2) as usize] &= self.registers.v[op_code.extract_nibble_value(3) as usize];
+        self.reset_v_f_after_logic_op();
     }
 
     fn set_vx_to_vx_and_vy_bitwise_xor(&mut self, op_code: u16) {
         self.registers.v[op_code.extract_nibble_value(2) as usize] ^= self.registers.v[op_code.extract_nibble_value(3) as usize];
+        self.reset_v_f_after_logic_op();
     }
 
+    fn reset_v_f_after_logic_op(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers.set_register_v_f_value(0);
+        }
+    }
+
+    // The flag is written after the result so that VF ends up holding the flag when it is also the target.
     fn set_vx_to_vx_and_vy_sum(&mut self, op_code: u16) {
         let sum = self.registers.v[op_code.extract_nibble_value(2) as usize]
             .overflowing_add(self.registers.v[op_code.extract_nibble_value(3) as usize]);
 
-        match sum.1 {
-            true => self.registers.set_register_v_f_value(1),
-            false => self.registers.set_register_v_f_value(0)
-        }
-
         self.registers.v[op_code.extract_nibble_value(2) as usize] = sum.0;
+        self.registers.set_register_v_f_value(sum.1 as u8);
     }
 
     fn set_vx_to_vx_and_vy_difference(&mut self, op_code: u16) {
         let difference = self.registers.v[op_code.extract_nibble_value(2) as usize]
             .overflowing_sub(self.registers.v[op_code.extract_nibble_value(3) as usize]);
 
-        match difference.1 {
-            true => self.registers.set_register_v_f_value(0),
-            false => self.registers.set_register_v_f_value(1)
-        }
-
         self.registers.v[op_code.extract_nibble_value(2) as usize] = difference.0;
+        self.registers.set_register_v_f_value(!difference.1 as u8);
+    }
+
+    fn shift_source(&self, op_code: u16) -> u8 {
+        match self.quirks.shift_uses_vy {
+            true => self.registers.v[op_code.extract_nibble_value(3) as usize],
+            false => self.registers.v[op_code.extract_nibble_value(2) as usize]
+        }
     }
 
     fn set_vx_to_vx_shift_right(&mut self, op_code: u16) {
-        self.registers.set_register_v_f_value(self.registers.v[op_code.extract_nibble_value(2) as usize] & 1);
-        self.registers.v[op_code.extract_nibble_value(2) as usize] >>= 1;
+        let source = self.shift_source(op_code);
+
+        self.registers.v[op_code.extract_nibble_value(2) as usize] = source >> 1;
+        self.registers.set_register_v_f_value(source & 1);
     }
 
     fn set_vx_to_vy_and_vx_difference(&mut self, op_code: u16) {
         let difference = self.registers.v[op_code.extract_nibble_value(3) as usize]
             .overflowing_sub(self.registers.v[op_code.extract_nibble_value(2) as usize]);
 
-        match difference.1 {
-            true => self.registers.set_register_v_f_value(0),
-            false => self.registers.set_register_v_f_value(1)
-        }
-
         self.registers.v[op_code.extract_nibble_value(2) as usize] = difference.0;
+        self.registers.set_register_v_f_value(!difference.1 as u8);
     }
 
     fn set_vx_to_vx_shift_left(&mut self, op_code: u16) {
-        self.registers.set_register_v_f_value(self.registers.v[op_code.extract_nibble_value(2) as usize] >> 7);
-        self.registers.v[op_code.extract_nibble_value(2) as usize] <<= 1;
+        let source = self.shift_source(op_code);
+
+        self.registers.v[op_code.extract_nibble_value(2) as usize] = source << 1;
+        self.registers.set_register_v_f_value(source >> 7);
     }
 
     fn skip_next_if_vx_neq_vy(&mut self, op_code: u16) {
         if self.registers.v[op_code.extract_nibble_value(2) as usize] != self.registers.v[op_code.extract_nibble_value(3) as usize] {
-            self.registers.program_counter += 2;
+            self.skip_next_instruction();
         }
     }
 
     fn set_register_i_address(&mut self, op_code: u16) {
         self.registers.i = op_code.get_argument_sum(..);
-        self.registers.program_counter += 2;
     }
 
     fn jump_to_location_plus_v_0(&mut self, op_code: u16) {
-        self.registers.program_counter = op_code.get_argument_sum(..) + self.registers.v[0] as u16;
+        let offset_register = match self.quirks.jump_uses_vx {
+            true => op_code.extract_nibble_value(2) as usize,
+            false => 0
+        };
+
+        self.registers.program_counter = op_code.get_argument_sum(..) + self.registers.v[offset_register] as u16;
     }
 
     fn set_vx_to_random_and_kk(&mut self, op_code: u16) {
-        let random_byte: u8 = rand::thread_rng().gen();
+        let random_byte = self.rng.next_u8();
 
         self.registers.v[op_code.extract_nibble_value(2) as usize] = op_code.get_argument_sum(1..) as u8 & random_byte
     }
 
-    fn draw_sprite(&mut self, op_code: u16) {
-        let coord_x: u8 = self.registers.v[op_code.extract_nibble_value(2) as usize];
-        let coord_y: u8 = self.registers.v[op_code.extract_nibble_value(3) as usize];
-        let height: u8 = self.registers.v[op_code.extract_nibble_value(4) as usize];
+    // The starting position always wraps around the screen, the rest of the sprite is clipped or wrapped depending on quirks.
+    fn draw_sprite(&mut self, op_code: u16) -> Result<(), Chip8Error> {
+        let coord_x = self.registers.v[op_code.extract_nibble_value(2) as usize] as usize % SCREEN_WIDTH;
+        let coord_y = self.registers.v[op_code.extract_nibble_value(3) as usize] as usize % SCREEN_HEIGHT;
+        let height: u8 = op_code.extract_nibble_value(4);
+
+        self.memory.check_range(self.registers.i, height as usize)?;
 
         self.registers.set_register_v_f_value(0);
 
         for axis_y in 0..height {
             for axis_x in 0..8 {
-                if self.memory.current_pixel_is_on(self.registers.i, axis_x, axis_y) {
+                if !self.memory.sprite_pixel_is_on(self.registers.i, axis_x, axis_y) {
                     continue;
                 }
-                if self.graphics.current_pixel_is_on(coord_x, axis_x, coord_y, axis_y) {
+
+                let mut pixel_x = coord_x + axis_x as usize;
+                let mut pixel_y = coord_y + axis_y as usize;
+
+                if pixel_x >= SCREEN_WIDTH || pixel_y >= SCREEN_HEIGHT {
+                    if self.quirks.clip_sprites {
+                        continue;
+                    }
+
+                    pixel_x %= SCREEN_WIDTH;
+                    pixel_y %= SCREEN_HEIGHT;
+                }
+
+                if self.graphics.current_pixel_is_on(pixel_x, pixel_y) {
                     self.registers.set_register_v_f_value(1);
                 }
 
-                self.graphics.change_pixel_value(coord_x, axis_x, coord_y, axis_y);
+                self.graphics.change_pixel_value(pixel_x, pixel_y);
             }
         }
 
-        self.registers.program_counter += 2;
+        self.graphics.redraw = true;
+
+        Ok(())
+    }
+
+    fn key_in_vx_is_pressed(&self, op_code: u16) -> bool {
+        self.keypad.keys[(self.registers.v[op_code.extract_nibble_value(2) as usize] & 0xF) as usize]
     }
 
     fn skip_next_if_key_pressed(&mut self, op_code: u16) {
-        if self.keypad.keys[op_code.extract_nibble_value(2) as usize] {
-            self.registers.program_counter += 4;
-        } else {
-            self.registers.program_counter += 2;
+        if self.key_in_vx_is_pressed(op_code) {
+            self.skip_next_instruction();
         }
     }
 
     fn skip_next_if_key_not_pressed(&mut self, op_code: u16) {
-        if !self.keypad.keys[op_code.extract_nibble_value(2) as usize] {
-            self.registers.program_counter += 4;
-        } else {
-            self.registers.program_counter += 2;
+        if !self.key_in_vx_is_pressed(op_code) {
+            self.skip_next_instruction();
         }
     }
 
@@ -412,15 +597,11 @@ impl Chip8 {
         self.registers.v[op_code.extract_nibble_value(2) as usize] = self.timers.delay_timer;
     }
 
+    // Execution does not block the caller, the instruction is repeated until a key is pressed.
     fn await_key_and_store_to_value_vx(&mut self, op_code: u16) {
-        loop {
-            for key in self.keypad.keys.iter() {
-                if *key == true {
-                    self.registers.v[op_code.extract_nibble_value(2) as usize] = 1;
-
-                    return;
-                }
-            }
+        match self.keypad.keys.iter().position(|&key| key) {
+            Some(key) => self.registers.v[op_code.extract_nibble_value(2) as usize] = key as u8,
+            None => self.registers.program_counter = self.registers.program_counter.wrapping_sub(2)
         }
     }
 
@@ -432,55 +613,95 @@ impl Chip8 {
         self.timers.sound_timer = self.registers.v[op_code.extract_nibble_value(2) as usize];
     }
 
-    fn set_i_to_sum_of_i_and_vx(& mut self, op_code: u16) {
-        self.registers.i += self.registers.v[op_code.extract_nibble_value(2) as usize] as u16;
+    fn set_i_to_sum_of_i_and_vx(&mut self, op_code: u16) {
+        self.registers.i = self.registers.i.wrapping_add(self.registers.v[op_code.extract_nibble_value(2) as usize] as u16) & 0x0FFF;
     }
 
     fn set_i_to_location_of_sprite_vx(&mut self, op_code: u16) {
-        self.registers.i = 0x05 * self.registers.v[op_code.extract_nibble_value(2) as usize] as u16;
+        self.registers.i = 0x05 * (self.registers.v[op_code.extract_nibble_value(2) as usize] & 0xF) as u16;
     }
 
-    fn store_bcd_of_vx_in_i(&mut self, op_code: u16) {
+    fn store_bcd_of_vx_in_i(&mut self, op_code: u16) -> Result<(), Chip8Error> {
         let vx_value = self.registers.v[op_code.extract_nibble_value(2) as usize];
+        let i = self.registers.i as usize;
 
-        self.memory.ram[self.registers.i as usize] = vx_value / 100;
-        self.memory.ram[(self.registers.i + 1) as usize] = vx_value / 10 % 10;
-        self.memory.ram[(self.registers.i + 2) as usize] = vx_value % 100 % 10;
+        self.memory.check_range(self.registers.i, 3)?;
+
+        self.memory.ram[i] = vx_value / 100;
+        self.memory.ram[i + 1] = vx_value / 10 % 10;
+        self.memory.ram[i + 2] = vx_value % 10;
+
+        Ok(())
     }
 
-    fn store_through_v0_to_vx_in_memory(&mut self, op_code: u16) {
-        for index in 0..=op_code.extract_nibble_value(2) as u16 {
-            self.memory.ram[(self.registers.i + index) as usize] = self.registers.v[index as usize];
-        }
+    fn store_through_v0_to_vx_in_memory(&mut self, op_code: u16) -> Result<(), Chip8Error> {
+        let last_register = op_code.extract_nibble_value(2) as usize;
+        let i = self.registers.i as usize;
+
+        self.memory.check_range(self.registers.i, last_register + 1)?;
+
+        self.memory.ram[i..=i + last_register].copy_from_slice(&self.registers.v[..=last_register]);
+
+        self.increment_i_after_load_store(op_code);
+
+        Ok(())
+    }
+
+    fn store_from_memory_through_v0_to_vx(&mut self, op_code: u16) -> Result<(), Chip8Error> {
+        let last_register = op_code.extract_nibble_value(2) as usize;
+        let i = self.registers.i as usize;
+
+        self.memory.check_range(self.registers.i, last_register + 1)?;
+
+        self.registers.v[..=last_register].copy_from_slice(&self.memory.ram[i..=i + last_register]);
+
+        self.increment_i_after_load_store(op_code);
+
+        Ok(())
     }
 
-    fn store_from_memory_through_v0_to_vx(&mut self, op_code: u16) {
-        for index in 0..=op_code.extract_nibble_value(2) as u16 {
-            self.registers.v[index as usize] = self.memory.ram[(self.registers.i + index) as usize];
+    fn increment_i_after_load_store(&mut self, op_code: u16) {
+        if self.quirks.load_store_increments_i {
+            self.registers.i += op_code.extract_nibble_value(2) as u16 + 1;
         }
-    } 
+    }
 }
 
-fn start_chip8() -> Result<(), Box<std::error::Error>> {
-    println!("Hello, chip8!");
+// 64 bit FNV-1a.
+struct StateHash(u64);
 
-    let rom = File::open("roms\\UFO")?;
-    let rom: Result<Vec<_>, _> = rom.bytes().collect();
-    let rom = rom?;
-    let rom: &[u8] = &rom;
-
-    let mut chip8 = Chip8::initialize();
+impl StateHash {
+    fn new() -> StateHash {
+        StateHash(0xCBF2_9CE4_8422_2325)
+    }
 
-    chip8.memory.load_rom(rom);
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
 
-    loop {
-        chip8.emulate_cycle();
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
     }
 }
 
+// Every machine plays differently where there is an operating system to ask for a seed.
+#[cfg(feature = "std")]
+fn initial_seed() -> u64 {
+    rand::random()
+}
+
+#[cfg(not(feature = "std"))]
+fn initial_seed() -> u64 {
+    0
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Chip8, OpCode};
+    use crate::error::Chip8Error;
+    use crate::instruction::Instruction;
 
     #[test]
     fn can_extract_nibble_value_correctly() {
@@ -500,6 +721,19 @@ mod tests {
         assert_eq!(test_arguments[0], 0x200)
     }
 
+    //Clear the display.
+    #[test]
+    fn can_process_op_00e0() {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.graphics.gfx[0] = true;
+        chip8.graphics.gfx[2047] = true;
+
+        chip8.execute_op_code(0x00E0).unwrap();
+
+        assert!(chip8.graphics.gfx.iter().all(|&pixel| !pixel));
+    }
+
     //Return from a subroutine.
     //The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
     #[test]
@@ -511,8 +745,8 @@ mod tests {
         chip8.registers.program_counter = current_program_counter;
         chip8.stack.stack_pointer = current_stack_pointer;
 
-        chip8.execute_op_code(0x2001);
-        chip8.execute_op_code(0x00EE);
+        chip8.execute_op_code(0x2001).unwrap();
+        chip8.execute_op_code(0x00EE).unwrap();
 
         assert_eq!(chip8.registers.program_counter, current_program_counter);
         assert_eq!(chip8.stack.stack_pointer, current_stack_pointer);
@@ -525,7 +759,7 @@ mod tests {
         let mut chip8 = Chip8::initialize();
         let target_program_counter = 0x0FFE;
 
-        chip8.execute_op_code(0x1FFE);
+        chip8.execute_op_code(0x1FFE).unwrap();
 
         assert_eq!(chip8.registers.program_counter, target_program_counter);
     }
@@ -542,7 +776,7 @@ mod tests {
         chip8.stack.stack_pointer = current_stack_pointer;
         chip8.registers.program_counter = current_program_counter;
 
-        chip8.execute_op_code(0x2111);
+        chip8.execute_op_code(0x2111).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x111);
         assert_eq!(chip8.stack.stack_pointer, current_stack_pointer + 1);
@@ -561,11 +795,11 @@ mod tests {
         chip8.registers.v[current_v_index] = current_v_value;
         chip8.registers.program_counter = current_program_counter;
 
-        chip8.execute_op_code(0x32AA);
+        chip8.execute_op_code(0x32AA).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x0EE6);
 
-        chip8.execute_op_code(0x32AB);
+        chip8.execute_op_code(0x32AB).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x0EE6);
     }
@@ -582,11 +816,11 @@ mod tests {
         chip8.registers.v[current_v_index] = current_v_value;
         chip8.registers.program_counter = current_program_counter;
 
-        chip8.execute_op_code(0x42AA);
+        chip8.execute_op_code(0x42AA).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x0EE4);
 
-        chip8.execute_op_code(0x42AB);
+        chip8.execute_op_code(0x42AB).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x0EE6);
     }
@@ -606,7 +840,7 @@ mod tests {
         chip8.registers.v[current_v_index_second] = current_v_value_first;
         chip8.registers.program_counter = current_program_counter;
 
-        chip8.execute_op_code(0x5250);
+        chip8.execute_op_code(0x5250).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x0EE6);
 
@@ -623,7 +857,7 @@ mod tests {
         let mut chip8 = Chip8::initialize();
         let current_v_index = 2;
 
-        chip8.execute_op_code(0x62FF);
+        chip8.execute_op_code(0x62FF).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], 0x0FF);
     }
@@ -638,9 +872,14 @@ mod tests {
 
         chip8.registers.v[current_v_index] = current_v_value;
 
-        chip8.execute_op_code(0x7211);
+        chip8.execute_op_code(0x7211).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], 0xBB);
+
+        chip8.execute_op_code(0x7250).unwrap();
+
+        assert_eq!(chip8.registers.v[current_v_index], 0x0B);
+        assert_eq!(chip8.registers.get_register_v_f_value(), 0);
     }
 
     //Set Vx = Vy.
@@ -656,7 +895,7 @@ mod tests {
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
 
-        chip8.execute_op_code(0x8250);
+        chip8.execute_op_code(0x8250).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], current_v_value_second);
         assert_eq!(chip8.registers.v[current_v_index_second], current_v_value_second);
@@ -675,7 +914,7 @@ mod tests {
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
 
-        chip8.execute_op_code(0x8251);
+        chip8.execute_op_code(0x8251).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0xFF);
         assert_eq!(chip8.registers.v[current_v_index_second], current_v_value_second);
@@ -694,7 +933,7 @@ mod tests {
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
 
-        chip8.execute_op_code(0x8252);
+        chip8.execute_op_code(0x8252).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0x1);
         assert_eq!(chip8.registers.v[current_v_index_second], current_v_value_second);
@@ -713,7 +952,7 @@ mod tests {
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
 
-        chip8.execute_op_code(0x8253);
+        chip8.execute_op_code(0x8253).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0x10);
         assert_eq!(chip8.registers.v[current_v_index_second], current_v_value_second);
@@ -734,13 +973,13 @@ mod tests {
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
 
-        chip8.execute_op_code(0x8254);
+        chip8.execute_op_code(0x8254).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0x02);
         assert_eq!(chip8.registers.get_register_v_f_value(), 1);
         assert_eq!(chip8.registers.v[current_v_index_second], current_v_value_second);
 
-        chip8.execute_op_code(0x8254);
+        chip8.execute_op_code(0x8254).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0x05);
         assert_eq!(chip8.registers.get_register_v_f_value(), 0);
@@ -759,13 +998,13 @@ mod tests {
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
 
-        chip8.execute_op_code(0x8255);
+        chip8.execute_op_code(0x8255).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0xEF);
         assert_eq!(chip8.registers.get_register_v_f_value(), 0);
         assert_eq!(chip8.registers.v[current_v_index_second], current_v_value_second);
 
-        chip8.execute_op_code(0x8255);
+        chip8.execute_op_code(0x8255).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0x34);
         assert_eq!(chip8.registers.get_register_v_f_value(), 1);
@@ -780,12 +1019,12 @@ mod tests {
         let current_v_value = 0xA1;
 
         chip8.registers.v[current_v_index] = current_v_value;
-        chip8.execute_op_code(0x8256);
+        chip8.execute_op_code(0x8256).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], 0x50);
         assert_eq!(chip8.registers.get_register_v_f_value(), 1);
 
-        chip8.execute_op_code(0x8256);
+        chip8.execute_op_code(0x8256).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], 0x28);
         assert_eq!(chip8.registers.get_register_v_f_value(), 0);
@@ -803,7 +1042,7 @@ mod tests {
 
         chip8.registers.v[current_v_index_first] = current_v_value_first;
         chip8.registers.v[current_v_index_second] = current_v_value_second;
-        chip8.execute_op_code(0x8257);
+        chip8.execute_op_code(0x8257).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0xEF);
         assert_eq!(chip8.registers.get_register_v_f_value(), 0);
@@ -811,7 +1050,7 @@ mod tests {
 
         chip8.registers.v[current_v_index_first] = 0x11;
 
-        chip8.execute_op_code(0x8257);
+        chip8.execute_op_code(0x8257).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index_first], 0x99);
         assert_eq!(chip8.registers.get_register_v_f_value(), 1);
@@ -826,12 +1065,12 @@ mod tests {
         let current_v_value = 0x7F;
 
         chip8.registers.v[current_v_index] = current_v_value;
-        chip8.execute_op_code(0x825E);
+        chip8.execute_op_code(0x825E).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], 0xFE);
         assert_eq!(chip8.registers.get_register_v_f_value(), 0);
 
-        chip8.execute_op_code(0x825E);
+        chip8.execute_op_code(0x825E).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], 0xFC);
         assert_eq!(chip8.registers.get_register_v_f_value(), 1);
@@ -852,13 +1091,13 @@ mod tests {
         chip8.registers.v[current_v_index_second] = current_v_value_second;
         chip8.registers.program_counter = current_program_counter;
 
-        chip8.execute_op_code(0x9250);
+        chip8.execute_op_code(0x9250).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0xA4);
 
         chip8.registers.v[current_v_index_second] = current_v_value_first;
 
-        chip8.execute_op_code(0x9250);
+        chip8.execute_op_code(0x9250).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0xA4);
     }
@@ -869,7 +1108,7 @@ mod tests {
     fn can_process_op_a_nnn() {
         let mut chip8 = Chip8::initialize();
 
-        chip8.execute_op_code(0xA2F0);
+        chip8.execute_op_code(0xA2F0).unwrap();
 
         assert_eq!(chip8.registers.i, 0x02F0);
     }
@@ -881,7 +1120,7 @@ mod tests {
         let mut chip8 = Chip8::initialize();
         chip8.registers.v[0] = 0xAA;
 
-        chip8.execute_op_code(0xB123);
+        chip8.execute_op_code(0xB123).unwrap();
 
         assert_eq!(chip8.registers.program_counter, 0x01CD);
     }
@@ -896,7 +1135,7 @@ mod tests {
 //
 //        chip8.registers.v[current_v_index] = current_v_value;
 //
-//        chip8.execute_op_code(0xC201);
+//        chip8.execute_op_code(0xC201).unwrap();
 //
 //        assert_eq!(chip8.registers.v[current_v_index], 0x10);
 //    }
@@ -910,9 +1149,46 @@ mod tests {
     fn can_process_op_d_xyn() {
         let mut chip8 = Chip8::initialize();
 
-        chip8.execute_op_code(0xD003);
+        chip8.registers.v[0] = 62;
+        chip8.registers.v[1] = 4;
+        chip8.registers.i = 0x300;
+        chip8.memory.ram[0x300] = 0b1110_0000;
+        chip8.memory.ram[0x301] = 0b1000_0000;
+
+        chip8.execute_op_code(0xD012).unwrap();
+
+        assert!(chip8.graphics.gfx[62 + 4 * 64]);
+        assert!(chip8.graphics.gfx[63 + 4 * 64]);
+        assert!(chip8.graphics.gfx[4 * 64]);
+        assert!(!chip8.graphics.gfx[1 + 4 * 64]);
+        assert!(chip8.graphics.gfx[62 + 5 * 64]);
+        assert!(!chip8.graphics.gfx[63 + 5 * 64]);
+        assert_eq!(chip8.graphics.gfx.iter().filter(|&&pixel| pixel).count(), 4);
+        assert_eq!(chip8.registers.get_register_v_f_value(), 0);
 
-        //assert_eq!(chip8.memory.ram[chip8.registers.i as usize], 0x3C);
+        chip8.execute_op_code(0xD011).unwrap();
+
+        assert!(!chip8.graphics.gfx[62 + 4 * 64]);
+        assert!(chip8.graphics.gfx[62 + 5 * 64]);
+        assert_eq!(chip8.registers.get_register_v_f_value(), 1);
+    }
+
+    #[test]
+    fn can_process_op_d_xyn_with_clipping() {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.quirks.clip_sprites = true;
+        chip8.registers.v[0] = 62;
+        chip8.registers.v[1] = 31;
+        chip8.registers.i = 0x300;
+        chip8.memory.ram[0x300] = 0xFF;
+        chip8.memory.ram[0x301] = 0xFF;
+
+        chip8.execute_op_code(0xD012).unwrap();
+
+        assert!(chip8.graphics.gfx[62 + 31 * 64]);
+        assert!(chip8.graphics.gfx[63 + 31 * 64]);
+        assert_eq!(chip8.graphics.gfx.iter().filter(|&&pixel| pixel).count(), 2);
     }
 
     //Skip next instruction if key with the value of Vx is pressed.
@@ -923,42 +1199,40 @@ mod tests {
         let current_program_counter = 0xA2;
 
         chip8.registers.program_counter = current_program_counter;
+        chip8.registers.v[0x3] = 0x2;
+        chip8.registers.v[0x4] = 0x1;
         chip8.keypad.keys[0x2] = true;
         chip8.keypad.keys[0xF] = true;
 
-        chip8.execute_op_code(0xE29E);
-
-        assert_eq!(chip8.registers.program_counter, 0xA6);
-
-        chip8.execute_op_code(0xE19F);
+        chip8.execute_op_code(0xE39E).unwrap();
 
-        assert_eq!(chip8.registers.program_counter, 0xA8);
+        assert_eq!(chip8.registers.program_counter, 0xA4);
 
-        chip8.execute_op_code(0xEF90);
+        chip8.execute_op_code(0xE49E).unwrap();
 
-        assert_eq!(chip8.registers.program_counter, 0xAC);
+        assert_eq!(chip8.registers.program_counter, 0xA4);
     }
 
+    //Skip next instruction if key with the value of Vx is not pressed.
+    //Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position, PC is increased by 2.
     #[test]
     fn can_process_op_e_x_a1() {
         let mut chip8 = Chip8::initialize();
         let current_program_counter = 0xA2;
 
         chip8.registers.program_counter = current_program_counter;
+        chip8.registers.v[0x3] = 0x2;
+        chip8.registers.v[0x4] = 0x1;
         chip8.keypad.keys[0x2] = true;
         chip8.keypad.keys[0xF] = true;
 
-        chip8.execute_op_code(0xE2AE);
+        chip8.execute_op_code(0xE3A1).unwrap();
 
-        assert_eq!(chip8.registers.program_counter, 0xA4);
+        assert_eq!(chip8.registers.program_counter, 0xA2);
 
-        chip8.execute_op_code(0xE1AF);
+        chip8.execute_op_code(0xE4A1).unwrap();
 
-        assert_eq!(chip8.registers.program_counter, 0xA8);
-
-        chip8.execute_op_code(0xEFA0);
-
-        assert_eq!(chip8.registers.program_counter, 0xAA);
+        assert_eq!(chip8.registers.program_counter, 0xA4);
     }
 
     //Set Vx = delay timer value.
@@ -970,7 +1244,7 @@ mod tests {
 
         chip8.timers.delay_timer = current_delay_timer_value;
 
-        chip8.execute_op_code(0xF507);
+        chip8.execute_op_code(0xF507).unwrap();
 
         assert_eq!(chip8.registers.v[5], current_delay_timer_value);
     }
@@ -980,14 +1254,21 @@ mod tests {
     #[test]
     fn can_process_op_f_x_0a() {
         let mut chip8 = Chip8::initialize();
+        let current_program_counter = 0xA2;
 
-        chip8.keypad.keys[0x2] = true;
+        chip8.registers.program_counter = current_program_counter;
 
-        assert_eq!(chip8.registers.v[5], 0);
+        chip8.execute_op_code(0xF50A).unwrap();
 
-        chip8.execute_op_code(0xF50A);
+        assert_eq!(chip8.registers.program_counter, 0xA0);
 
-        assert_eq!(chip8.registers.v[5], 1);
+        chip8.registers.program_counter = current_program_counter;
+        chip8.keypad.keys[0x2] = true;
+
+        chip8.execute_op_code(0xF50A).unwrap();
+
+        assert_eq!(chip8.registers.v[5], 2);
+        assert_eq!(chip8.registers.program_counter, current_program_counter);
     }
 
     //Set delay timer = Vx.
@@ -1000,7 +1281,7 @@ mod tests {
         chip8.registers.v[current_v_index] = 0xAF; 
         chip8.timers.delay_timer = 0xFA;
 
-        chip8.execute_op_code(0xF515);
+        chip8.execute_op_code(0xF515).unwrap();
 
         assert_eq!(chip8.registers.v[current_v_index], chip8.timers.delay_timer);
     }
@@ -1015,7 +1296,7 @@ mod tests {
 
         chip8.registers.v[current_v_index] = current_v_value;
 
-        chip8.execute_op_code(0xF318);
+        chip8.execute_op_code(0xF318).unwrap();
 
         assert_eq!(chip8.timers.sound_timer, current_v_value);
     }
@@ -1032,17 +1313,24 @@ mod tests {
         chip8.registers.i = i_value;
         chip8.registers.v[v_index] = v_value;
 
-        chip8.execute_op_code(0xF31E);
+        chip8.execute_op_code(0xF31E).unwrap();
 
         assert_eq!(i_value + (v_value as u16), chip8.registers.i);
     }
 
     //Set I = location of sprite for digit Vx.
     //The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx
-    // #[test]
-    // fn can_process_op_f_x_29() {
-    //     let mut chip8 = Chip8::initialize();    
-    // }
+    #[test]
+    fn can_process_op_f_x_29() {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.registers.v[4] = 0xA;
+
+        chip8.execute_op_code(0xF429).unwrap();
+
+        assert_eq!(chip8.registers.i, 0x32);
+        assert_eq!(chip8.memory.ram[chip8.registers.i as usize], 0xF0);
+    }
 
     //Store BCD representation of Vx in memory locations I, I+1, and I+2.
     //The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I,
@@ -1055,7 +1343,7 @@ mod tests {
 
         chip8.registers.v[v_index] = v_value;
 
-        chip8.execute_op_code(0xF633);
+        chip8.execute_op_code(0xF633).unwrap();
 
         assert_eq!(chip8.memory.ram[chip8.registers.i as usize], 2);
         assert_eq!(chip8.memory.ram[(chip8.registers.i + 1) as usize], 5);
@@ -1077,7 +1365,7 @@ mod tests {
         chip8.registers.v[2] = v_two_value;
         chip8.registers.i = i_value;
 
-        chip8.execute_op_code(0xF255);
+        chip8.execute_op_code(0xF255).unwrap();
 
         assert_eq!(chip8.memory.ram[chip8.registers.i as usize], v_zero_value);
         assert_eq!(chip8.memory.ram[(chip8.registers.i + 1) as usize], v_one_value);
@@ -1099,11 +1387,137 @@ mod tests {
         chip8.memory.ram[i_value + 2] = ram_loc_two_value;
         chip8.registers.i = i_value as u16;
 
-        chip8.execute_op_code(0xF265);
+        chip8.execute_op_code(0xF265).unwrap();
 
         assert_eq!(chip8.registers.v[0], ram_loc_zero_value);
         assert_eq!(chip8.registers.v[1], ram_loc_one_value);
         assert_eq!(chip8.registers.v[2], ram_loc_two_value);
     }
+
+    #[test]
+    fn reports_stack_overflow_and_underflow() {
+        let mut chip8 = Chip8::initialize();
+
+        assert_eq!(chip8.execute_op_code(0x00EE), Err(Chip8Error::StackUnderflow));
+
+        for _ in 0..16 {
+            chip8.execute_op_code(0x2300).unwrap();
+        }
+
+        assert_eq!(chip8.execute_op_code(0x2300), Err(Chip8Error::StackOverflow));
+        assert_eq!(chip8.stack.stack_pointer, 16);
+    }
+
+    #[test]
+    fn reports_memory_access_out_of_bounds() {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.registers.i = 0xFFE;
+
+        assert_eq!(chip8.execute_op_code(0xF255), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(chip8.execute_op_code(0xF033), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(chip8.execute_op_code(0xD003), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(chip8.memory.ram[0xFFE], 0);
+
+        chip8.registers.program_counter = 0xFFF;
+
+        assert_eq!(chip8.emulate_cycle(), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+    }
+
+    #[test]
+    fn reports_unknown_op_code_and_keeps_program_counter_on_it() {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.load_rom(&[0x60, 0x01, 0x80, 0x08]).unwrap();
+
+        chip8.emulate_cycle().unwrap();
+
+        assert_eq!(chip8.emulate_cycle(), Err(Chip8Error::UnknownOpCode(0x8008)));
+        assert_eq!(chip8.registers.program_counter, 0x202);
+    }
+
+    #[test]
+    fn core_and_disassembler_accept_the_same_op_codes() {
+        for op_code in 0..=0xFFFF {
+            assert_eq!(Chip8::decode(op_code).err(), Instruction::decode(op_code).err(), "{:04X}", op_code);
+        }
+    }
+
+    #[test]
+    fn rejects_rom_larger_than_memory() {
+        let mut chip8 = Chip8::initialize();
+
+        assert_eq!(chip8.load_rom(&[0; 4096 - 0x200 + 1]), Err(Chip8Error::RomTooLarge(4096 - 0x200 + 1)));
+    }
+
+    #[test]
+    fn state_hash_covers_the_state_and_stays_the_same_everywhere() {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.seed_rng(1);
+
+        let fresh = chip8.state_hash();
+        let changes: [fn(&mut Chip8); 8] = [
+            |chip8| chip8.memory.ram[0xFFF] = 1,
+            |chip8| chip8.registers.v[0xF] = 1,
+            |chip8| chip8.registers.program_counter += 2,
+            |chip8| chip8.stack.stack[15] = 0x200,
+            |chip8| chip8.timers.sound_timer = 1,
+            |chip8| chip8.graphics.gfx[2047] = true,
+            |chip8| chip8.keypad.keys[0xF] = true,
+            |chip8| {
+                chip8.rng.next_u64();
+            },
+        ];
+
+        for change in changes.iter() {
+            let mut changed = chip8.clone();
+
+            change(&mut changed);
+            assert_ne!(changed.state_hash(), fresh);
+        }
+
+        chip8.cycle_count += 1;
+        chip8.graphics.redraw = true;
+
+        assert_eq!(chip8.state_hash(), fresh);
+        // Recorded runs and netplay peers rely on this value, so changing what goes into the hash shows up here.
+        assert_eq!(fresh, 0xFD82_8DF0_727C_D92E);
+    }
 }
 
+// Proptest needs std.
+#[cfg(all(test, feature = "std"))]
+mod proptests {
+    use proptest::prelude::*;
+
+    use super::Chip8;
+    use crate::error::Chip8Error;
+
+    proptest! {
+        #[test]
+        fn arbitrary_roms_never_panic(
+            rom in prop::collection::vec(any::<u8>(), 0..4096),
+            keys in any::<[bool; 16]>(),
+            i in any::<u16>(),
+            program_counter in any::<u16>(),
+        ) {
+            let mut chip8 = Chip8::initialize();
+
+            match chip8.load_rom(&rom) {
+                Ok(()) => prop_assert!(rom.len() <= 4096 - 0x200),
+                Err(error) => prop_assert_eq!(error, Chip8Error::RomTooLarge(rom.len())),
+            }
+
+            chip8.keypad.keys = keys;
+            chip8.registers.i = i;
+            chip8.registers.program_counter = program_counter % 0x1000;
+
+            for _ in 0..1000 {
+                if chip8.emulate_cycle().is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
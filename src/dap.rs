@@ -0,0 +1,764 @@
+// A Debug Adapter Protocol server, so editors can launch and debug ROMs.
+//
+// Messages are framed with a Content-Length header. A reader thread turns the input into a channel
+// of requests, which lets the server keep running the ROM (at 60 frames a second) between requests.
+// Source line breakpoints and labels come from a symbol file given as "symbols" in the launch arguments,
+// see src/symbols.rs, or from a "sourceMap": [{ "address": 516, "path": "game.8o", "line": 37 }, ...].
+// Quirks and cycles per frame come from the ROM database, extended by a "database" file, unless given.
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::cpu::Chip8;
+
+use crate::error::Chip8Error;
+use crate::loader::{self, MachineOptions};
+use crate::symbols::{self, Symbols};
+
+const THREAD_ID: u64 = 1;
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+const REGISTERS_REFERENCE: u64 = 1;
+const TIMERS_REFERENCE: u64 = 2;
+const STACK_REFERENCE: u64 = 3;
+// Requests are small, anything larger is taken for a broken client.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+const RAM_SIZE: usize = 4096;
+// Enough to show all of memory.
+const MAX_DISASSEMBLED_INSTRUCTIONS: u64 = 2048;
+
+// What ends a run, besides breakpoints and errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RunUntil {
+    Breakpoint,
+    // Step over: back at the instruction after a call, at the same stack depth.
+    Return { address: u16, stack_pointer: u16 },
+    // Step out: the stack got shallower than it was.
+    StackBelow(u16),
+}
+
+pub struct DapServer {
+    output: Box<dyn Write + Send>,
+    sequence: u64,
+    chip8: Option<Chip8>,
+    cycles_per_frame: u32,
+    cycles_since_frame: u32,
+    stop_on_entry: bool,
+    symbols: Symbols,
+    // Per source path, as the client sets them one file at a time.
+    source_breakpoints: BTreeMap<String, BTreeSet<u16>>,
+    instruction_breakpoints: BTreeSet<u16>,
+    running: Option<RunUntil>,
+    // A single step to take once the response to the stepping request has gone out.
+    step_pending: bool,
+    finished: bool,
+}
+
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = match content_length {
+        Some(length) if length <= MAX_MESSAGE_SIZE => vec![0; length],
+        Some(length) => return Err(io::Error::new(ErrorKind::InvalidData, format!("message of {} bytes is too large", length))),
+        None => return Err(io::Error::new(ErrorKind::InvalidData, "no Content-Length header"))
+    };
+    reader.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body).map(Some).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+pub fn write_message<W: Write + ?Sized>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+// Reads framed messages on a separate thread until the input ends or can not be parsed.
+pub fn spawn_reader<R: Read + Send + 'static>(input: R) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// Serves one debugging session over stdin and stdout.
+pub fn serve_stdio() -> io::Result<()> {
+    let messages = spawn_reader(io::stdin());
+
+    DapServer::new(Box::new(io::stdout())).run(messages)
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let word = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(ALPHABET[(word >> (18 - 6 * index) & 0x3F) as usize] as char),
+                false => encoded.push('=')
+            }
+        }
+    }
+
+    encoded
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+impl DapServer {
+    pub fn new(output: Box<dyn Write + Send>) -> DapServer {
+        DapServer {
+            output,
+            sequence: 0,
+            chip8: None,
+            cycles_per_frame: 10,
+            cycles_since_frame: 0,
+            stop_on_entry: false,
+            symbols: Symbols::new(),
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            running: None,
+            step_pending: false,
+            finished: false,
+        }
+    }
+
+    // Handles requests until the client disconnects, running the ROM in between while it is not stopped.
+    pub fn run(&mut self, messages: Receiver<Value>) -> io::Result<()> {
+        while !self.finished {
+            let message = match self.running {
+                Some(_) => match messages.recv_timeout(FRAME_DURATION) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(())
+                },
+                None => match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(())
+                }
+            };
+
+            if let Some(message) = message {
+                self.handle(&message)?;
+            }
+
+            if self.running.is_some() {
+                self.run_frame()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsSetVariable": false,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => Ok(self.set_source_breakpoints(arguments)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(arguments)),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stopped_machine().map(|chip8| self.stack_trace(chip8)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS_REFERENCE, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+            ]})),
+            "variables" => self.stopped_machine().map(|chip8| DapServer::variables(chip8, arguments["variablesReference"].as_u64().unwrap_or(0))),
+            "readMemory" => self.read_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            "continue" => self.resume(RunUntil::Breakpoint).map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.step_over(),
+            "stepIn" => self.step_in(),
+            "stepOut" => self.step_out(),
+            "pause" => Ok(json!({})),
+            "disconnect" | "terminate" => {
+                self.finished = true;
+                Ok(json!({}))
+            }
+            _ => Err(format!("unsupported request {}", command))
+        };
+
+        match result {
+            Ok(body) => self.send(json!({ "type": "response", "request_seq": request["seq"], "success": true, "command": command, "body": body }))?,
+            Err(message) => self.send(json!({ "type": "response", "request_seq": request["seq"], "success": false, "command": command, "message": message }))?
+        }
+
+        match command {
+            "initialize" => self.send_event("initialized", json!({})),
+            "configurationDone" if self.stop_on_entry => self.send_stopped("entry", None),
+            "configurationDone" => {
+                self.running = Some(RunUntil::Breakpoint);
+                Ok(())
+            }
+            "pause" if self.running.is_some() => {
+                self.running = None;
+                self.send_stopped("pause", None)
+            }
+            _ if self.step_pending => {
+                self.step_pending = false;
+                self.send_stopped_after_step()
+            }
+            "disconnect" | "terminate" => self.send_event("terminated", json!({})),
+            _ => Ok(())
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.sequence += 1;
+        message["seq"] = json!(self.sequence);
+
+        write_message(&mut self.output, &message)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send_stopped(&mut self, reason: &str, error: Option<Chip8Error>) -> io::Result<()> {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+
+        if let Some(error) = error {
+            let program_counter = self.chip8.as_ref().map_or(0, |chip8| chip8.registers.program_counter);
+
+            body["description"] = json!("Exception");
+            body["text"] = json!(self.symbols.describe_error(&error, program_counter));
+        }
+
+        self.send_event("stopped", body)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"].as_str().ok_or("launch needs a program")?;
+        let mut machine = MachineOptions::new();
+
+        if let Some(path) = arguments["database"].as_str() {
+            machine.add_database(path)?;
+        }
+
+        if let Some(name) = arguments["quirks"].as_str() {
+            machine.set_quirks(name)?;
+        }
+
+        // Settings in the launch configuration win over the ones the database knows for the ROM.
+        machine.cycles_per_frame = arguments["cyclesPerFrame"].as_u64().map(|cycles| cycles as u32);
+
+        let rom = loader::read_rom_file(program).map_err(|error| format!("{}: {}", program, error))?;
+        let (chip8, cycles_per_frame) = machine.load(&rom)?;
+
+        let mut symbols = match arguments["symbols"].as_str() {
+            Some(path) => Symbols::load(path)?,
+            None => Symbols::new()
+        };
+
+        for entry in arguments["sourceMap"].as_array().into_iter().flatten() {
+            symbols.add_source_line(entry).ok_or(format!("can not read source map entry {}", entry))?;
+        }
+
+        self.symbols = symbols;
+        self.cycles_per_frame = cycles_per_frame;
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.chip8 = Some(chip8);
+
+        Ok(json!({}))
+    }
+
+    fn stopped_machine(&self) -> Result<&Chip8, String> {
+        match (&self.chip8, self.running) {
+            (Some(chip8), None) => Ok(chip8),
+            (None, _) => Err("no ROM has been launched".to_string()),
+            (Some(_), Some(_)) => Err("the ROM is running".to_string())
+        }
+    }
+
+    // A breakpoint on a line is placed on the lowest address generated for it.
+    fn set_source_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"].as_str().unwrap_or("").to_string();
+        let mut addresses = BTreeSet::new();
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+            .map(|line| match self.symbols.addresses_for_line(&path, line).first() {
+                Some(&address) => {
+                    addresses.insert(address);
+                    json!({ "verified": true, "line": line, "instructionReference": format!("0x{:03X}", address) })
+                }
+                None => json!({ "verified": false, "line": line, "message": "no code generated for this line" })
+            })
+            .collect();
+
+        self.source_breakpoints.insert(path, addresses);
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Value {
+        self.instruction_breakpoints.clear();
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| breakpoints.iter().map(|breakpoint| {
+                let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+
+                match symbols::parse_json_address(&breakpoint["instructionReference"]).map(|address| (address as i64).checked_add(offset)) {
+                    Some(Some(address)) if (0..RAM_SIZE as i64).contains(&address) => {
+                        let address = address as u16;
+                        self.instruction_breakpoints.insert(address);
+                        json!({ "verified": true, "instructionReference": format!("0x{:03X}", address) })
+                    }
+                    Some(_) => json!({ "verified": false, "message": "outside memory" }),
+                    None => json!({ "verified": false, "message": "not an address" })
+                }
+            }).collect())
+            .unwrap_or_default();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // The current instruction, then the calls on the stack from the innermost out.
+    fn stack_trace(&self, chip8: &Chip8) -> Value {
+        let depth = (chip8.stack.stack_pointer as usize).min(chip8.stack.stack.len());
+        let mut addresses = vec![chip8.registers.program_counter];
+
+        addresses.extend(chip8.stack.stack[..depth].iter().rev().map(|return_address| return_address.wrapping_sub(2)));
+
+        let frames: Vec<Value> = addresses
+            .iter()
+            .enumerate()
+            .map(|(index, &address)| {
+                let ram = &chip8.memory.ram;
+                let op_code = match ram.get(address as usize..address as usize + 2) {
+                    Some(bytes) => self.symbols.mnemonic_with_quirks((bytes[0] as u16) << 8 | bytes[1] as u16, &chip8.quirks),
+                    None => "??".to_string()
+                };
+                let mut frame = json!({
+                    "id": index + 1,
+                    "name": format!("{} {}", self.symbols.label_and_offset(address), op_code),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:03X}", address),
+                });
+
+                if let Some(source_line) = self.symbols.line_at(address) {
+                    frame["source"] = json!({ "path": source_line.path, "name": source_line.path.rsplit(['/', '\\']).next() });
+                    frame["line"] = json!(source_line.line);
+                    frame["column"] = json!(1);
+                }
+
+                frame
+            })
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": addresses.len() })
+    }
+
+    fn variables(chip8: &Chip8, reference: u64) -> Value {
+        let variables: Vec<Value> = match reference {
+            REGISTERS_REFERENCE => {
+                let registers = &chip8.registers;
+                let mut variables: Vec<Value> = registers.v.iter().enumerate().map(|(index, value)| variable(&format!("V{:X}", index), format!("0x{:02X}", value))).collect();
+
+                variables.push(variable("I", format!("0x{:03X}", registers.i)));
+                variables.push(variable("PC", format!("0x{:03X}", registers.program_counter)));
+                variables.push(variable("SP", chip8.stack.stack_pointer.to_string()));
+                variables
+            }
+            TIMERS_REFERENCE => vec![
+                variable("delay", chip8.timers.delay_timer.to_string()),
+                variable("sound", chip8.timers.sound_timer.to_string()),
+            ],
+            STACK_REFERENCE => chip8.stack.stack[..(chip8.stack.stack_pointer as usize).min(chip8.stack.stack.len())]
+                .iter()
+                .enumerate()
+                .map(|(index, address)| variable(&index.to_string(), format!("0x{:03X}", address)))
+                .collect(),
+            _ => Vec::new()
+        };
+
+        json!({ "variables": variables })
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let chip8 = self.chip8.as_ref().ok_or("no ROM has been launched")?;
+        let reference = symbols::parse_json_address(&arguments["memoryReference"]).ok_or("not a memory reference")? as i64;
+        let start = reference.saturating_add(arguments["offset"].as_i64().unwrap_or(0));
+        let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+        let ram = &chip8.memory.ram;
+
+        if start < 0 || start >= ram.len() as i64 {
+            return Ok(json!({ "address": format!("0x{:03X}", start.max(0)), "unreadableBytes": count }));
+        }
+
+        let start = start as usize;
+        let end = start.saturating_add(count).min(ram.len());
+
+        Ok(json!({
+            "address": format!("0x{:03X}", start),
+            "data": encode_base64(&ram[start..end]),
+            "unreadableBytes": count - (end - start),
+        }))
+    }
+
+    // Instructions are two bytes apart from the reference address, the way the ROM was assembled.
+    fn disassemble(&self, arguments: &Value) -> Result<Value, String> {
+        let chip8 = self.chip8.as_ref().ok_or("no ROM has been launched")?;
+        let reference = symbols::parse_json_address(&arguments["memoryReference"]).ok_or("not a memory reference")? as i64;
+        let start = reference
+            .saturating_add(arguments["offset"].as_i64().unwrap_or(0))
+            .saturating_add(arguments["instructionOffset"].as_i64().unwrap_or(0).saturating_mul(2));
+        let count = arguments["instructionCount"].as_u64().unwrap_or(0).min(MAX_DISASSEMBLED_INSTRUCTIONS) as i64;
+        let ram = &chip8.memory.ram;
+
+        let instructions: Vec<Value> = (0..count)
+            .map(|index| start.saturating_add(2 * index))
+            .map(|address| match address >= 0 && (address as usize) + 1 < ram.len() {
+                true => {
+                    let address = address as u16;
+                    let op_code = (ram[address as usize] as u16) << 8 | ram[address as usize + 1] as u16;
+                    let mut instruction = json!({
+                        "address": format!("0x{:03X}", address),
+                        "instructionBytes": format!("{:04X}", op_code),
+                        "instruction": self.symbols.mnemonic_with_quirks(op_code, &chip8.quirks),
+                    });
+
+                    if let Some(label) = self.symbols.label_at(address) {
+                        instruction["symbol"] = json!(label);
+                    }
+                    if let Some(source_line) = self.symbols.line_at(address) {
+                        instruction["location"] = json!({ "path": source_line.path });
+                        instruction["line"] = json!(source_line.line);
+                    }
+
+                    instruction
+                }
+                false => json!({ "address": format!("0x{:X}", address.max(0)), "instruction": "", "presentationHint": "invalid" })
+            })
+            .collect();
+
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn resume(&mut self, until: RunUntil) -> Result<(), String> {
+        self.stopped_machine()?;
+        self.running = Some(until);
+
+        Ok(())
+    }
+
+    fn step_over(&mut self) -> Result<Value, String> {
+        let chip8 = self.stopped_machine()?;
+        let address = chip8.registers.program_counter;
+        let is_call = chip8.memory.ram.get(address as usize).is_some_and(|byte| byte >> 4 == 0x2);
+
+        match is_call {
+            true => self.resume(RunUntil::Return { address: address.wrapping_add(2), stack_pointer: chip8.stack.stack_pointer })?,
+            false => self.step_in().map(|_| ())?
+        }
+
+        Ok(json!({}))
+    }
+
+    fn step_in(&mut self) -> Result<Value, String> {
+        self.stopped_machine()?;
+        self.step_pending = true;
+
+        Ok(json!({}))
+    }
+
+    fn step_out(&mut self) -> Result<Value, String> {
+        let stack_pointer = self.stopped_machine()?.stack.stack_pointer;
+
+        match stack_pointer {
+            0 => self.resume(RunUntil::Breakpoint)?,
+            _ => self.resume(RunUntil::StackBelow(stack_pointer))?
+        }
+
+        Ok(json!({}))
+    }
+
+    fn send_stopped_after_step(&mut self) -> io::Result<()> {
+        match self.emulate_cycle() {
+            Ok(()) => self.send_stopped("step", None),
+            Err(error) => self.send_stopped("exception", Some(error))
+        }
+    }
+
+    fn emulate_cycle(&mut self) -> Result<(), Chip8Error> {
+        let chip8 = self.chip8.as_mut().unwrap();
+
+        chip8.emulate_cycle()?;
+
+        self.cycles_since_frame += 1;
+        if self.cycles_since_frame >= self.cycles_per_frame {
+            self.cycles_since_frame = 0;
+            chip8.tick_timers();
+        }
+
+        Ok(())
+    }
+
+    // Runs up to a frame worth of cycles, stopping early at breakpoints, errors and step targets.
+    fn run_frame(&mut self) -> io::Result<()> {
+        for _ in 0..self.cycles_per_frame.max(1) {
+            let until = match self.running {
+                Some(until) => until,
+                None => return Ok(())
+            };
+
+            if let Err(error) = self.emulate_cycle() {
+                self.running = None;
+                return self.send_stopped("exception", Some(error));
+            }
+
+            let chip8 = self.chip8.as_ref().unwrap();
+            let program_counter = chip8.registers.program_counter;
+            let step_done = match until {
+                RunUntil::Breakpoint => false,
+                RunUntil::Return { address, stack_pointer } => program_counter == address && chip8.stack.stack_pointer == stack_pointer,
+                RunUntil::StackBelow(stack_pointer) => chip8.stack.stack_pointer < stack_pointer,
+            };
+
+            if step_done {
+                self.running = None;
+                return self.send_stopped("step", None);
+            }
+
+            let source_breakpoint = self.source_breakpoints.values().any(|addresses| addresses.contains(&program_counter));
+
+            if source_breakpoint || self.instruction_breakpoints.contains(&program_counter) {
+                self.running = None;
+                return self.send_stopped("breakpoint", None);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use serde_json::{json, Value};
+
+    use super::{encode_base64, read_message, write_message, DapServer};
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Sends requests to a server on another thread and reads back what it wrote.
+    struct Client {
+        requests: mpsc::Sender<Value>,
+        output: Arc<Mutex<Vec<u8>>>,
+        read: usize,
+        sequence: u64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.sequence += 1;
+            self.requests.send(json!({ "seq": self.sequence, "type": "request", "command": command, "arguments": arguments })).unwrap();
+
+            let sequence = self.sequence;
+            self.wait_for(|message| message["type"] == "response" && message["request_seq"] == sequence)
+        }
+
+        fn wait_for<F: Fn(&Value) -> bool>(&mut self, wanted: F) -> Value {
+            let deadline = Instant::now() + Duration::from_secs(5);
+
+            loop {
+                let output = self.output.lock().unwrap().clone();
+                let mut reader = Cursor::new(&output[self.read..]);
+
+                while let Ok(Some(message)) = read_message(&mut reader) {
+                    self.read += reader.position() as usize;
+                    reader = Cursor::new(&output[self.read..]);
+
+                    if wanted(&message) {
+                        return message;
+                    }
+                }
+
+                assert!(Instant::now() < deadline, "timed out waiting for a message");
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+    }
+
+    fn start(rom: &[u8]) -> (Client, String) {
+        let path = std::env::temp_dir().join(format!("chip8-dap-{:?}.ch8", thread::current().id()));
+        std::fs::write(&path, rom).unwrap();
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (requests, messages) = mpsc::channel();
+        let mut server = DapServer::new(Box::new(SharedBuffer(output.clone())));
+
+        thread::spawn(move || server.run(messages).unwrap());
+
+        (Client { requests, output, read: 0, sequence: 0 }, path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn frames_messages_and_encodes_memory() {
+        let mut buffer = Vec::new();
+
+        write_message(&mut buffer, &json!({ "seq": 1 })).unwrap();
+        assert_eq!(buffer, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+        assert_eq!(read_message(&mut Cursor::new(buffer)).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(encode_base64(b"Man"), "TWFu");
+        assert_eq!(encode_base64(&[0x60, 0x05]), "YAU=");
+        assert_eq!(encode_base64(&[0xFF]), "/w==");
+
+        let huge = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+
+        assert!(read_message(&mut Cursor::new(huge.into_bytes())).is_err());
+    }
+
+    #[test]
+    fn out_of_range_requests_are_answered() {
+        let (mut client, program) = start(&[0x60, 0x05, 0x12, 0x02]);
+
+        client.request("initialize", json!({ "adapterID": "chip8" }));
+        client.request("launch", json!({ "program": program, "stopOnEntry": true }));
+
+        let memory = client.request("readMemory", json!({ "memoryReference": "0x200", "count": u64::MAX }))["body"].clone();
+        assert_eq!(memory["data"].as_str().unwrap().len(), (4096 - 0x200usize).div_ceil(3) * 4);
+        assert_eq!(memory["unreadableBytes"], u64::MAX - (4096 - 0x200));
+        assert_eq!(client.request("readMemory", json!({ "memoryReference": 0x200, "offset": i64::MAX, "count": 4 }))["body"]["unreadableBytes"], 4);
+
+        let instructions = client.request("disassemble", json!({ "memoryReference": "0x200", "instructionOffset": i64::MIN, "instructionCount": u64::MAX }));
+        assert_eq!(instructions["body"]["instructions"].as_array().unwrap().len(), 2048);
+
+        let breakpoints = client.request(
+            "setInstructionBreakpoints",
+            json!({ "breakpoints": [{ "instructionReference": "0x202" }, { "instructionReference": "0xFFE", "offset": 2 }, { "instructionReference": 70000 }] }),
+        )["body"]["breakpoints"]
+            .clone();
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1], json!({ "verified": false, "message": "outside memory" }));
+        assert_eq!(breakpoints[2]["verified"], false);
+
+        client.request("disconnect", json!({}));
+    }
+
+    #[test]
+    fn scripted_session_breaks_on_source_line_and_inspects_state() {
+        // 200: LD V0, 05 / 202: CALL 208 / 204: JP 204 / 206: unused / 208: ADD V0, 01 / 20A: RET
+        let (mut client, program) = start(&[0x60, 0x05, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE]);
+        let symbols = std::env::temp_dir().join(format!("chip8-dap-{:?}.sym", thread::current().id()));
+        let source_map = json!([
+            { "address": 0x200, "path": "game.8o", "line": 3 },
+            { "address": 0x202, "path": "game.8o", "line": 4 },
+            { "address": "0x20A", "path": "game.8o", "line": 10 },
+        ]);
+
+        std::fs::write(&symbols, "label add_one 208\nline 208 game.8o:9\n").unwrap();
+
+        assert_eq!(client.request("initialize", json!({ "adapterID": "chip8" }))["body"]["supportsReadMemoryRequest"], true);
+        client.wait_for(|message| message["event"] == "initialized");
+        assert_eq!(client.request("launch", json!({ "program": program, "symbols": symbols, "sourceMap": source_map }))["success"], true);
+
+        let breakpoints = client.request("setBreakpoints", json!({ "source": { "path": "game.8o" }, "breakpoints": [{ "line": 9 }, { "line": 5 }] }));
+        assert_eq!(breakpoints["body"]["breakpoints"][0]["verified"], true);
+        assert_eq!(breakpoints["body"]["breakpoints"][1]["verified"], false);
+
+        client.request("configurationDone", json!({}));
+        assert_eq!(client.wait_for(|message| message["event"] == "stopped")["body"]["reason"], "breakpoint");
+
+        let frames = client.request("stackTrace", json!({ "threadId": 1 }))["body"]["stackFrames"].clone();
+        assert_eq!(frames[0]["line"], 9);
+        assert_eq!(frames[0]["name"], "add_one ADD V0, 01");
+        assert_eq!(frames[1]["instructionPointerReference"], "0x202");
+
+        let registers = client.request("variables", json!({ "variablesReference": 1 }))["body"]["variables"].clone();
+        assert_eq!(registers[0], json!({ "name": "V0", "value": "0x05", "variablesReference": 0 }));
+        assert_eq!(registers[17]["value"], "0x208");
+
+        assert_eq!(client.request("readMemory", json!({ "memoryReference": "0x200", "count": 2 }))["body"]["data"], "YAU=");
+
+        let instructions = client.request("disassemble", json!({ "memoryReference": "0x202", "instructionCount": 2 }))["body"]["instructions"].clone();
+        assert_eq!(instructions[0]["instruction"], "CALL add_one");
+        assert_eq!(instructions[0]["line"], 4);
+
+        client.request("stepIn", json!({ "threadId": 1 }));
+        assert_eq!(client.wait_for(|message| message["event"] == "stopped")["body"]["reason"], "step");
+        client.request("stepOut", json!({ "threadId": 1 }));
+        client.wait_for(|message| message["event"] == "stopped");
+
+        let registers = client.request("variables", json!({ "variablesReference": 1 }))["body"]["variables"].clone();
+        assert_eq!(registers[0]["value"], "0x06");
+        assert_eq!(registers[17]["value"], "0x204");
+
+        client.request("continue", json!({ "threadId": 1 }));
+        assert_eq!(client.request("stackTrace", json!({ "threadId": 1 }))["success"], false);
+        client.request("pause", json!({ "threadId": 1 }));
+        assert_eq!(client.wait_for(|message| message["event"] == "stopped")["body"]["reason"], "pause");
+
+        client.request("disconnect", json!({}));
+        client.wait_for(|message| message["event"] == "terminated");
+    }
+}
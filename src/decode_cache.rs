@@ -0,0 +1,158 @@
+// Decoded instructions by address, so long headless runs fetch and decode each instruction once.
+//
+// Chip8::emulate_cycle_cached forgets what FX33 and FX55 overwrite, which covers self-modifying ROMs.
+// Anything else writing to memory, such as loading a ROM or a debugger poke, has to call invalidate or clear.
+use core::ops::Range;
+
+use crate::cpu::{Chip8, Handler};
+use crate::error::Chip8Error;
+
+const MEMORY_SIZE: usize = 4096;
+
+pub struct DecodeCache {
+    // The op code at each address, with the method running it.
+    entries: [Option<(u16, Handler)>; MEMORY_SIZE],
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache { entries: [None; MEMORY_SIZE], hits: 0, misses: 0 }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = [None; MEMORY_SIZE];
+    }
+
+    // Forgets the instructions overlapping written bytes, including one starting the byte before.
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        let start = written.start.saturating_sub(1).min(MEMORY_SIZE);
+        let end = written.end.min(MEMORY_SIZE);
+
+        for entry in &mut self.entries[start..end.max(start)] {
+            *entry = None;
+        }
+    }
+
+    fn lookup(&mut self, chip8: &Chip8, address: u16) -> Result<(u16, Handler), Chip8Error> {
+        if let Some(&Some(entry)) = self.entries.get(address as usize) {
+            self.hits += 1;
+            return Ok(entry);
+        }
+
+        let op_code = chip8.fetch_op_code()?;
+        let entry = (op_code, Chip8::decode(op_code)?);
+
+        self.misses += 1;
+        self.entries[address as usize] = Some(entry);
+
+        Ok(entry)
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> DecodeCache {
+        DecodeCache::new()
+    }
+}
+
+impl Chip8 {
+    // Behaves like emulate_cycle, taking the instruction from the cache when it has been decoded before.
+    pub fn emulate_cycle_cached(&mut self, cache: &mut DecodeCache) -> Result<(), Chip8Error> {
+        let address = self.registers.program_counter;
+        let (op_code, handler) = cache.lookup(self, address)?;
+        let i = self.registers.i as usize;
+        let written = match op_code & 0xF0FF {
+            0xF033 => Some(i..i + 3),
+            0xF055 => Some(i..i + ((op_code >> 8) & 0xF) as usize + 1),
+            _ => None
+        };
+
+        self.registers.program_counter = address.wrapping_add(2);
+
+        let result = handler(self, op_code);
+
+        match result {
+            Ok(()) => self.cycle_count += 1,
+            Err(_) => self.registers.program_counter = address
+        }
+
+        if let (Ok(()), Some(written)) = (result, written) {
+            cache.invalidate(written);
+        }
+
+        result
+    }
+
+    pub fn emulate_frame_cached(&mut self, cycles_per_frame: u32, cache: &mut DecodeCache) -> Result<(), Chip8Error> {
+        for _ in 0..cycles_per_frame {
+            self.emulate_cycle_cached(cache)?;
+        }
+
+        self.tick_timers();
+
+        Ok(())
+    }
+}
+
+// The tests read ROMs from files, which needs std.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::DecodeCache;
+    use crate::cpu::Chip8;
+    use crate::loader;
+
+    #[test]
+    fn cached_runs_match_the_interpreter() {
+        for name in &["BRIX", "INVADERS", "TETRIS", "BLINKY", "PONG2"] {
+            let rom = loader::read_rom_file(&format!("roms/{}", name)).unwrap();
+            let mut interpreted = Chip8::initialize();
+            let mut cached = Chip8::initialize();
+            let mut cache = DecodeCache::new();
+
+            interpreted.seed_rng(7);
+            cached.seed_rng(7);
+            interpreted.load_rom(&rom).unwrap();
+            cached.load_rom(&rom).unwrap();
+
+            for frame in 0..600 {
+                interpreted.keypad.keys[frame / 30 % 16] = frame % 3 == 0;
+                cached.keypad.keys = interpreted.keypad.keys;
+
+                assert_eq!(interpreted.emulate_frame(10), cached.emulate_frame_cached(10, &mut cache), "{}", name);
+            }
+
+            assert_eq!(interpreted.registers, cached.registers, "{}", name);
+            assert_eq!(&interpreted.memory.ram[..], &cached.memory.ram[..], "{}", name);
+            assert_eq!(&interpreted.graphics.gfx[..], &cached.graphics.gfx[..], "{}", name);
+            assert!(cache.hits > cache.misses, "{}", name);
+        }
+    }
+
+    #[test]
+    fn self_modifying_stores_invalidate_decoded_instructions() {
+        // 200: LD V2, 05 is overwritten by LD [I], V1 at 208 with 62 09, LD V2, 09, before jumping back to it.
+        let rom = [0x62, 0x05, 0x60, 0x62, 0x61, 0x09, 0xA2, 0x00, 0xF1, 0x55, 0x12, 0x00];
+        let mut chip8 = Chip8::initialize();
+        let mut cache = DecodeCache::new();
+
+        chip8.load_rom(&rom).unwrap();
+
+        for _ in 0..7 {
+            chip8.emulate_cycle_cached(&mut cache).unwrap();
+        }
+
+        assert_eq!(chip8.registers.v[2], 0x09);
+        assert_eq!((cache.hits, cache.misses), (0, 7));
+
+        chip8.emulate_cycle_cached(&mut cache).unwrap();
+        assert_eq!(cache.hits, 1);
+
+        chip8.memory.ram[0x204] = 0x00;
+        chip8.memory.ram[0x205] = 0xE0;
+        cache.invalidate(0x204..0x206);
+        chip8.emulate_cycle_cached(&mut cache).unwrap();
+        assert!(chip8.graphics.redraw);
+    }
+}
@@ -0,0 +1,139 @@
+// The ways of running instructions, picked at run time by name. All of them give the same machine state,
+// the cached ones trade memory and care about writes to code for speed.
+use std::ops::Range;
+
+use crate::cpu::Chip8;
+use crate::decode_cache::DecodeCache;
+use crate::error::Chip8Error;
+use crate::threaded::ThreadedCode;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Engine {
+    // Fetches and decodes every instruction, the reference for the others.
+    #[default]
+    Interpreter,
+    // Decodes each address once.
+    Cached,
+    // Decodes straight-line blocks once and runs them without looking up each instruction.
+    Threaded,
+}
+
+pub enum Executor {
+    Interpreter,
+    Cached(Box<DecodeCache>),
+    Threaded(ThreadedCode),
+}
+
+impl Engine {
+    pub const ALL: [Engine; 3] = [Engine::Interpreter, Engine::Cached, Engine::Threaded];
+
+    pub fn by_name(name: &str) -> Option<Engine> {
+        Engine::ALL.iter().cloned().find(|engine| engine.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Interpreter => "interpreter",
+            Engine::Cached => "cached",
+            Engine::Threaded => "threaded"
+        }
+    }
+}
+
+impl Executor {
+    pub fn new(engine: Engine) -> Executor {
+        match engine {
+            Engine::Interpreter => Executor::Interpreter,
+            Engine::Cached => Executor::Cached(Box::default()),
+            Engine::Threaded => Executor::Threaded(ThreadedCode::new())
+        }
+    }
+
+    pub fn engine(&self) -> Engine {
+        match self {
+            Executor::Interpreter => Engine::Interpreter,
+            Executor::Cached(_) => Engine::Cached,
+            Executor::Threaded(_) => Engine::Threaded
+        }
+    }
+
+    pub fn emulate_frame(&mut self, chip8: &mut Chip8, cycles_per_frame: u32) -> Result<(), Chip8Error> {
+        match self {
+            Executor::Interpreter => chip8.emulate_frame(cycles_per_frame),
+            Executor::Cached(cache) => chip8.emulate_frame_cached(cycles_per_frame, cache),
+            Executor::Threaded(code) => chip8.emulate_frame_threaded(cycles_per_frame, code)
+        }
+    }
+
+    pub fn emulate_cycle(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        match self {
+            Executor::Interpreter => chip8.emulate_cycle(),
+            Executor::Cached(cache) => chip8.emulate_cycle_cached(cache),
+            Executor::Threaded(code) => chip8.emulate_cycle_threaded(code)
+        }
+    }
+
+    // To be called after writing to memory other than through the instructions, such as loading a ROM.
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        match self {
+            Executor::Interpreter => {}
+            Executor::Cached(cache) => cache.invalidate(written),
+            Executor::Threaded(code) => code.invalidate(written)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Engine, Executor};
+    use crate::cpu::Chip8;
+    use crate::loader;
+
+    #[test]
+    fn engines_are_picked_by_name_and_poked_memory_is_seen() {
+        assert_eq!(Engine::by_name("Threaded"), Some(Engine::Threaded));
+        assert_eq!(Engine::by_name("jit"), None);
+
+        for &engine in Engine::ALL.iter() {
+            let mut executor = Executor::new(engine);
+            let mut chip8 = Chip8::initialize();
+
+            // ADD V0, 01; JP 200
+            chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+            executor.emulate_frame(&mut chip8, 10).unwrap();
+
+            // ADD V0, 10
+            chip8.memory.ram[0x201] = 0x10;
+            executor.invalidate(0x201..0x202);
+            executor.emulate_frame(&mut chip8, 10).unwrap();
+
+            assert_eq!(executor.engine(), engine);
+            assert_eq!(chip8.registers.v[0], 5 + 5 * 0x10, "{}", engine.name());
+        }
+    }
+
+    #[test]
+    fn engines_keep_the_same_state_hash_frame_by_frame() {
+        let rom = loader::read_rom_file("roms/BRIX").unwrap();
+        let hashes = |engine| {
+            let mut executor = Executor::new(engine);
+            let mut chip8 = Chip8::initialize();
+
+            chip8.seed_rng(11);
+            chip8.load_rom(&rom).unwrap();
+
+            (0..600u64)
+                .map(|frame| {
+                    chip8.keypad.keys[4] = frame % 40 < 20;
+                    chip8.keypad.keys[6] = frame % 40 >= 20;
+                    executor.emulate_frame(&mut chip8, 10).unwrap();
+                    chip8.state_hash()
+                })
+                .collect::<Vec<u64>>()
+        };
+        let interpreted = hashes(Engine::Interpreter);
+
+        assert_eq!(hashes(Engine::Cached), interpreted);
+        assert_eq!(hashes(Engine::Threaded), interpreted);
+    }
+}
@@ -0,0 +1,325 @@
+// A reinforcement learning environment around one machine, in the reset/step shape of Gym.
+//
+// An action is a mask of the keys held for the step, bit 0 for key 0. A step holds them for frame_skip frames,
+// except that with sticky actions each frame keeps the previous action instead with some probability, which makes
+// memorizing a sequence of actions useless. Observations are the screen, one byte per pixel with 1 for pixels that
+// are on, shrunk by downsample in each direction (a pixel is on if any in its square is) and the last frame_stack
+// of them one after the other, oldest first.
+//
+// Rewards and the end of an episode come from readers of the machine's state, built in for a few ROMs.
+use crate::cpu::{Chip8, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::engine::{Engine, Executor};
+use crate::error::Chip8Error;
+use crate::loader;
+use crate::quirks::Quirks;
+use crate::rng::XorShift;
+
+// Reads a value like the score out of the machine, the reward being how much it went up over a step.
+pub type RewardReader = fn(&Chip8) -> f32;
+
+pub type DoneReader = fn(&Chip8) -> bool;
+
+#[derive(Clone, Copy)]
+pub struct RomReaders {
+    pub reward: RewardReader,
+    pub done: DoneReader,
+    // The actions that make sense in the game, for agents picking from a few.
+    pub actions: &'static [u16],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnvOptions {
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+    pub engine: Engine,
+    pub frame_skip: u32,
+    pub sticky_action_probability: f32,
+    pub downsample: usize,
+    pub frame_stack: usize,
+    // Ends episodes after this many frames, for games that never end by themselves.
+    pub max_frames: Option<u64>,
+}
+
+pub struct Chip8Env {
+    rom: Vec<u8>,
+    options: EnvOptions,
+    readers: Option<RomReaders>,
+    chip8: Chip8,
+    executor: Executor,
+    sticky_rng: XorShift,
+    previous_action: u16,
+    value: f32,
+    frames: u64,
+    done: bool,
+    error: Option<Chip8Error>,
+    observation: Vec<u8>,
+}
+
+// BRIX shows the score from the BCD digits it stores at 314, and waits at 2DE once the bricks or balls run out.
+fn brix_score(chip8: &Chip8) -> f32 {
+    bcd_value(&chip8.memory.ram[0x314..0x317]) as f32
+}
+
+fn brix_done(chip8: &Chip8) -> bool {
+    chip8.registers.program_counter == 0x2DE
+}
+
+// PONG keeps both scores in one register, the left player's tens and the right player's ones, with its BCD digits
+// at 2F2. The reward is for the left player. The game never ends.
+fn pong_score(chip8: &Chip8) -> f32 {
+    let scores = bcd_value(&chip8.memory.ram[0x2F2..0x2F5]);
+
+    (scores / 10) as f32 - (scores % 10) as f32
+}
+
+fn never_done(_chip8: &Chip8) -> bool {
+    false
+}
+
+fn bcd_value(digits: &[u8]) -> u32 {
+    digits.iter().fold(0, |value, &digit| value * 10 + digit as u32)
+}
+
+// Readers for the ROMs in the roms directory that have a score, by SHA-1.
+pub fn built_in_readers(hash: &str) -> Option<RomReaders> {
+    match hash {
+        "f13766c14aeb02ad8d4d103cb5eadd282d20cddc" => Some(RomReaders { reward: brix_score, done: brix_done, actions: &[0, 1 << 4, 1 << 6] }),
+        "b232ef880bd6060fb45fa6effed7edf0ae95670e" => Some(RomReaders { reward: pong_score, done: never_done, actions: &[0, 1 << 1, 1 << 4] }),
+        _ => None
+    }
+}
+
+impl Default for EnvOptions {
+    fn default() -> EnvOptions {
+        EnvOptions {
+            quirks: Quirks::default(),
+            cycles_per_frame: 10,
+            engine: Engine::Threaded,
+            frame_skip: 4,
+            sticky_action_probability: 0.0,
+            downsample: 1,
+            frame_stack: 1,
+            max_frames: None,
+        }
+    }
+}
+
+impl Chip8Env {
+    // Uses the built-in readers for the ROM if there are any. Without readers rewards are 0 and episodes only end
+    // at max_frames or errors.
+    pub fn new(rom: &[u8], options: EnvOptions) -> Result<Chip8Env, Chip8Error> {
+        let readers = built_in_readers(&loader::rom_hash(rom));
+
+        Chip8Env::with_readers(rom, options, readers)
+    }
+
+    pub fn with_readers(rom: &[u8], options: EnvOptions, readers: Option<RomReaders>) -> Result<Chip8Env, Chip8Error> {
+        assert!(options.downsample > 0 && options.frame_stack > 0, "downsample and frame_stack are at least 1");
+
+        let mut env = Chip8Env {
+            rom: rom.to_vec(),
+            options,
+            readers,
+            chip8: Chip8::initialize_with_quirks(options.quirks),
+            executor: Executor::new(options.engine),
+            sticky_rng: XorShift::new(0),
+            previous_action: 0,
+            value: 0.0,
+            frames: 0,
+            done: false,
+            error: None,
+            observation: Vec::new(),
+        };
+
+        env.chip8.load_rom(rom)?;
+        env.reset(0);
+
+        Ok(env)
+    }
+
+    // Starts an episode over, with the seed deciding random numbers and sticky actions, and returns the observation.
+    pub fn reset(&mut self, seed: u64) -> &[u8] {
+        self.chip8 = Chip8::initialize_with_quirks(self.options.quirks);
+        self.chip8.seed_rng(seed);
+        self.chip8.load_rom(&self.rom).expect("the ROM loaded when the environment was made");
+        self.executor = Executor::new(self.options.engine);
+        self.sticky_rng = XorShift::new(seed ^ 0x5EED);
+        self.previous_action = 0;
+        self.value = self.read_reward();
+        self.frames = 0;
+        self.done = false;
+        self.error = None;
+
+        let screen = self.screen();
+
+        self.observation = screen.repeat(self.options.frame_stack);
+        &self.observation
+    }
+
+    // Holds the keys in the action for frame_skip frames and returns the observation, the reward since the last step
+    // and whether the episode is over. Steps after the end do nothing.
+    pub fn step(&mut self, action: u16) -> (&[u8], f32, bool) {
+        if self.done {
+            return (&self.observation, 0.0, true);
+        }
+
+        for _ in 0..self.options.frame_skip.max(1) {
+            let action = match self.sticks() {
+                true => self.previous_action,
+                false => action
+            };
+
+            for (key, pressed) in self.chip8.keypad.keys.iter_mut().enumerate() {
+                *pressed = action & (1 << key) != 0;
+            }
+
+            self.previous_action = action;
+            self.frames += 1;
+
+            if let Err(error) = self.executor.emulate_frame(&mut self.chip8, self.options.cycles_per_frame) {
+                self.error = Some(error);
+            }
+
+            self.done = self.error.is_some()
+                || self.readers.is_some_and(|readers| (readers.done)(&self.chip8))
+                || self.options.max_frames.is_some_and(|max_frames| self.frames >= max_frames);
+
+            if self.done {
+                break;
+            }
+        }
+
+        let value = self.read_reward();
+        let reward = value - self.value;
+        let screen = self.screen();
+
+        self.value = value;
+        self.observation.drain(..screen.len());
+        self.observation.extend(screen);
+
+        (&self.observation, reward, self.done)
+    }
+
+    // The actions the readers suggest, or every combination of keys.
+    pub fn actions(&self) -> Vec<u16> {
+        match self.readers {
+            Some(readers) => readers.actions.to_vec(),
+            None => (0..=u16::MAX).collect()
+        }
+    }
+
+    pub fn observation(&self) -> &[u8] {
+        &self.observation
+    }
+
+    // The width and height of one screen in the observation.
+    pub fn screen_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH / self.options.downsample, SCREEN_HEIGHT / self.options.downsample)
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    // The error that ended the episode, if one did.
+    pub fn error(&self) -> Option<Chip8Error> {
+        self.error
+    }
+
+    fn sticks(&mut self) -> bool {
+        let chance = (self.sticky_rng.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+
+        chance < self.options.sticky_action_probability
+    }
+
+    fn read_reward(&self) -> f32 {
+        self.readers.map_or(0.0, |readers| (readers.reward)(&self.chip8))
+    }
+
+    fn screen(&self) -> Vec<u8> {
+        let factor = self.options.downsample;
+        let (width, height) = self.screen_size();
+        let mut screen = vec![0; width * height];
+
+        for (index, pixel) in screen.iter_mut().enumerate() {
+            let (x, y) = (index % width * factor, index / width * factor);
+            let on = (y..y + factor).any(|y| (x..x + factor).any(|x| self.chip8.graphics.gfx[y * SCREEN_WIDTH + x]));
+
+            *pixel = on as u8;
+        }
+
+        screen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chip8Env, EnvOptions};
+    use crate::loader;
+
+    #[test]
+    fn brix_scores_and_ends() {
+        let rom = loader::read_rom_file("roms/BRIX").unwrap();
+        let options = EnvOptions { downsample: 2, frame_stack: 3, ..EnvOptions::default() };
+        let mut env = Chip8Env::new(&rom, options).unwrap();
+
+        assert_eq!(env.reset(3).len(), 3 * 32 * 16);
+        assert_eq!(env.actions(), vec![0, 1 << 4, 1 << 6]);
+
+        let (mut score, mut steps, mut done) = (0.0, 0, false);
+
+        // Nobody moves the paddle, so the balls run out after a few bricks.
+        while !done {
+            let (observation, reward, finished) = env.step(0);
+
+            assert_eq!(observation.len(), 3 * 32 * 16);
+            score += reward;
+            steps += 1;
+            done = finished;
+        }
+
+        assert!(score > 0.0 && steps < 5_000, "{} after {} steps", score, steps);
+        assert_eq!(score, env.chip8().registers.v[5] as f32);
+
+        let last = env.observation().to_vec();
+
+        assert_eq!(env.step(1 << 4), (&last[..], 0.0, true));
+        assert_eq!(env.error(), None);
+
+        env.reset(3);
+
+        let (first, _, _) = env.step(1 << 6);
+
+        assert!(first.contains(&1));
+    }
+
+    #[test]
+    fn seeds_repeat_episodes_and_sticky_actions() {
+        let rom = loader::read_rom_file("roms/PONG").unwrap();
+        let options = EnvOptions { sticky_action_probability: 0.25, max_frames: Some(400), ..EnvOptions::default() };
+        let mut env = Chip8Env::new(&rom, options).unwrap();
+        let mut run = |seed| {
+            let mut steps = Vec::new();
+
+            env.reset(seed);
+
+            for step in 0..200u16 {
+                let action = match step % 7 < 3 {
+                    true => 1 << 1,
+                    false => 1 << 4
+                };
+                let (observation, reward, done) = env.step(action);
+
+                steps.push((observation.to_vec(), reward, done));
+            }
+
+            steps
+        };
+
+        let first = run(5);
+
+        assert_eq!(first, run(5));
+        assert_ne!(first, run(6));
+        assert_eq!(first.iter().filter(|(_, _, done)| *done).count(), 101);
+    }
+}
@@ -0,0 +1,26 @@
+use core::fmt;
+
+// Everything a ROM can do wrong. The instruction that caused it has no effect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip8Error {
+    UnknownOpCode(u16),
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+    RomTooLarge(usize),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpCode(op_code) => write!(formatter, "unknown op code {:04X}", op_code),
+            Chip8Error::StackOverflow => write!(formatter, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(formatter, "return with an empty stack"),
+            Chip8Error::MemoryOutOfBounds(address) => write!(formatter, "memory access out of bounds at {:#05X}", address),
+            Chip8Error::RomTooLarge(size) => write!(formatter, "ROM of {} bytes does not fit in memory", size),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Chip8Error {}
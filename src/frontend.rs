@@ -0,0 +1,138 @@
+// What a frontend plugs into the emulator: a screen, a buzzer, keys and a 60Hz clock.
+// The browser, the terminal and tests each bring their own, and Frontend drives the machine with them.
+// Part of the core, backends needing std are in the backends module.
+use core::time::Duration;
+
+use crate::cpu::{Chip8, Keypad};
+use crate::error::Chip8Error;
+
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Frames run at most at once to catch up, so a host that stalled does not fast-forward the game.
+pub const MAX_FRAMES_BEHIND: u32 = 4;
+
+pub trait Display {
+    // Called after frames that changed the screen, with SCREEN_WIDTH * SCREEN_HEIGHT pixels row by row.
+    fn present(&mut self, pixels: &[bool]);
+}
+
+pub trait Audio {
+    // Called every frame, on while the sound timer is running.
+    fn set_buzzer(&mut self, on: bool);
+}
+
+pub trait Input {
+    // Called before every frame to press and release keys.
+    fn poll(&mut self, keypad: &mut Keypad);
+}
+
+pub trait Clock {
+    // The number of frames that became due since the last call.
+    fn frames_due(&mut self) -> u32;
+}
+
+pub struct Frontend<'a> {
+    pub display: &'a mut dyn Display,
+    pub audio: &'a mut dyn Audio,
+    pub input: &'a mut dyn Input,
+    pub clock: &'a mut dyn Clock,
+}
+
+impl<'a> Frontend<'a> {
+    // Polls the keys, runs a frame, then presents the screen if it changed and sets the buzzer.
+    pub fn run_frame(&mut self, chip8: &mut Chip8, cycles_per_frame: u32) -> Result<(), Chip8Error> {
+        self.input.poll(&mut chip8.keypad);
+
+        let result = chip8.emulate_frame(cycles_per_frame);
+
+        if chip8.graphics.redraw {
+            self.display.present(&chip8.graphics.gfx);
+            chip8.graphics.redraw = false;
+        }
+
+        self.audio.set_buzzer(chip8.timers.sound_timer > 0);
+
+        result
+    }
+
+    // Runs the frames the clock has due, up to MAX_FRAMES_BEHIND, and returns how many ran.
+    pub fn run_due_frames(&mut self, chip8: &mut Chip8, cycles_per_frame: u32) -> Result<u32, Chip8Error> {
+        let frames = self.clock.frames_due().min(MAX_FRAMES_BEHIND);
+
+        for _ in 0..frames {
+            self.run_frame(chip8, cycles_per_frame)?;
+        }
+
+        Ok(frames)
+    }
+}
+
+// Has exactly one frame due on every call, to run as fast as the host allows.
+pub struct FixedClock;
+
+impl Clock for FixedClock {
+    fn frames_due(&mut self) -> u32 {
+        1
+    }
+}
+
+// The tests drive frontends with the std backends.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{Clock, Display, FixedClock, Frontend, MAX_FRAMES_BEHIND};
+    use crate::backends::{HeadlessAudio, HeadlessDisplay, ScriptedInput, TerminalDisplay};
+    use crate::cpu::Chip8;
+
+    struct LateClock;
+
+    impl Clock for LateClock {
+        fn frames_due(&mut self) -> u32 {
+            100
+        }
+    }
+
+    // Waits for a key, then sounds the buzzer for 2 frames and draws the font sprite for the key at the top left.
+    const ROM: [u8; 12] = [0xF0, 0x0A, 0x61, 0x02, 0xF1, 0x18, 0xF0, 0x29, 0xD2, 0x25, 0x12, 0x0A];
+
+    #[test]
+    fn drives_the_machine_through_headless_backends() {
+        let mut chip8 = Chip8::initialize();
+        let mut display = HeadlessDisplay::default();
+        let mut audio = HeadlessAudio::default();
+        let mut input = ScriptedInput::new(vec![(3, 5, true), (4, 5, false)]);
+        let mut clock = FixedClock;
+        let mut frontend = Frontend { display: &mut display, audio: &mut audio, input: &mut input, clock: &mut clock };
+
+        chip8.load_rom(&ROM).unwrap();
+
+        for _ in 0..10 {
+            assert_eq!(frontend.run_due_frames(&mut chip8, 10), Ok(1));
+        }
+
+        assert_eq!(display.frames_presented, 1);
+        assert_eq!(&display.pixels[..4], &[true, true, true, true]);
+        assert_eq!(audio.changes, vec![true, false]);
+    }
+
+    #[test]
+    fn catches_up_a_few_frames_and_draws_to_terminals() {
+        let mut chip8 = Chip8::initialize();
+        let (mut display, mut audio, mut input, mut clock) = (HeadlessDisplay::default(), HeadlessAudio::default(), ScriptedInput::default(), LateClock);
+        let mut frontend = Frontend { display: &mut display, audio: &mut audio, input: &mut input, clock: &mut clock };
+
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        assert_eq!(frontend.run_due_frames(&mut chip8, 10), Ok(MAX_FRAMES_BEHIND));
+
+        let mut terminal = TerminalDisplay::new(Vec::new());
+        let mut pixels = [false; 64 * 32];
+
+        pixels[0] = true;
+        pixels[64 + 1] = true;
+        terminal.present(&pixels);
+
+        let text = String::from_utf8(terminal.into_inner()).unwrap();
+
+        assert!(text.starts_with("\x1b[H▀▄ "));
+        assert_eq!(text.lines().count(), 16);
+    }
+}
@@ -0,0 +1,384 @@
+// Gamepads on the CHIP-8 keypad, fed with button and axis events by whichever frontend reads the controller.
+//
+// Buttons follow the standard mapping of the browser Gamepad API, so its button and axis indexes
+// translate directly. Profiles bind buttons and stick directions to CHIP-8 keys, one entry per line:
+//
+//     deadzone 0.3          sticks closer to the centre than this count as centred
+//     bind Up 2             D-pad up presses 2
+//     bind LeftY- 2         so does the left stick pushed up, LeftY+ is down
+//     turbo South 5 3       South presses 5 for 3 frames, releases it for 3 and so on while held
+//     rom b232ef88...       the entries after this one only apply to the ROM with this SHA-1
+//
+// Blank lines and lines starting with # are ignored, names are compared without case.
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::cpu::Keypad;
+
+use crate::loader;
+
+pub const DEFAULT_DEADZONE: f32 = 0.25;
+
+// Directions on the D-pad and the left stick press 2/8/4/6 and South 5, which most ROMs use for moving and firing.
+const BUILT_IN: &str = "
+bind Up 2
+bind Down 8
+bind Left 4
+bind Right 6
+bind LeftY- 2
+bind LeftY+ 8
+bind LeftX- 4
+bind LeftX+ 6
+bind South 5
+
+# PONG and PONG2, the left paddle
+rom b232ef880bd6060fb45fa6effed7edf0ae95670e
+bind Up 1
+bind Down 4
+bind LeftY- 1
+bind LeftY+ 4
+
+rom a60611339661e3ab2d8af024ad1da5880a6f8665
+bind Up 1
+bind Down 4
+bind LeftY- 1
+bind LeftY+ 4
+
+# TETRIS, 4 rotates, 5 and 6 move and 1 drops
+rom 5f518084744bf3cb8733f6e5454dfd1634320563
+bind Left 5
+bind Right 6
+bind LeftX- 5
+bind LeftX+ 6
+bind Down 1
+bind LeftY+ 1
+bind South 4
+
+# UFO fires left, up and right
+rom bdb92475acfe11bc7814a2f5eade13fcd09b756a
+bind West 4
+bind North 5
+bind East 6
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Button {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Button(Button, bool),
+    // From -1 (left or up) to 1 (right or down).
+    Axis(Axis, f32),
+}
+
+// Something on the gamepad that can be held, a button or a stick pushed one way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Input {
+    Button(Button),
+    AxisNegative(Axis),
+    AxisPositive(Axis),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Binding {
+    pub key: u8,
+    // Frames the key stays pressed, then released, while the input is held.
+    pub turbo: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GamepadProfile {
+    pub bindings: BTreeMap<Input, Binding>,
+    pub deadzone: Option<f32>,
+}
+
+// The built-in profiles with the changes from profile files, overall and per ROM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadConfig {
+    default: GamepadProfile,
+    roms: BTreeMap<String, GamepadProfile>,
+}
+
+// Tracks what is held on one gamepad and presses the keys it is bound to.
+pub struct Gamepad {
+    profile: GamepadProfile,
+    // Inputs held, with the number of frames they have been held for.
+    held: BTreeMap<Input, u32>,
+}
+
+const BUTTONS: [Button; 17] = [
+    Button::South, Button::East, Button::West, Button::North, Button::LeftShoulder, Button::RightShoulder,
+    Button::LeftTrigger, Button::RightTrigger, Button::Select, Button::Start, Button::LeftStick, Button::RightStick,
+    Button::Up, Button::Down, Button::Left, Button::Right, Button::Home,
+];
+
+const AXES: [Axis; 4] = [Axis::LeftX, Axis::LeftY, Axis::RightX, Axis::RightY];
+
+impl Button {
+    // The button at an index of the Gamepad API standard mapping.
+    pub fn from_index(index: usize) -> Option<Button> {
+        BUTTONS.get(index).cloned()
+    }
+
+    pub fn by_name(name: &str) -> Option<Button> {
+        BUTTONS.iter().find(|button| format!("{:?}", button).eq_ignore_ascii_case(name)).cloned()
+    }
+}
+
+impl Axis {
+    pub fn from_index(index: usize) -> Option<Axis> {
+        AXES.get(index).cloned()
+    }
+
+    pub fn by_name(name: &str) -> Option<Axis> {
+        AXES.iter().find(|axis| format!("{:?}", axis).eq_ignore_ascii_case(name)).cloned()
+    }
+}
+
+impl Input {
+    // A button name, or an axis name followed by - or + for the direction.
+    pub fn by_name(name: &str) -> Option<Input> {
+        if let Some(axis) = name.strip_suffix('-') {
+            return Axis::by_name(axis).map(Input::AxisNegative);
+        }
+
+        if let Some(axis) = name.strip_suffix('+') {
+            return Axis::by_name(axis).map(Input::AxisPositive);
+        }
+
+        Button::by_name(name).map(Input::Button)
+    }
+}
+
+impl GamepadProfile {
+    pub fn new() -> GamepadProfile {
+        GamepadProfile::default()
+    }
+
+    pub fn bind(&mut self, input: Input, key: u8) {
+        self.bindings.insert(input, Binding { key: key & 0xF, turbo: None });
+    }
+
+    pub fn bind_turbo(&mut self, input: Input, key: u8, frames: u32) {
+        self.bindings.insert(input, Binding { key: key & 0xF, turbo: Some(frames.max(1)) });
+    }
+
+    // The bindings and deadzone of `other` win over these.
+    pub fn extend(&mut self, other: &GamepadProfile) {
+        self.bindings.extend(other.bindings.clone());
+        self.deadzone = other.deadzone.or(self.deadzone);
+    }
+}
+
+impl GamepadConfig {
+    pub fn built_in() -> GamepadConfig {
+        let mut config = GamepadConfig { default: GamepadProfile::new(), roms: BTreeMap::new() };
+
+        config.read(BUILT_IN).expect("the built-in gamepad profiles are valid");
+        config
+    }
+
+    // The built-in profiles with the entries of a profile file on top.
+    pub fn load(path: &str) -> Result<GamepadConfig, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+
+        GamepadConfig::parse(&text).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse(text: &str) -> Result<GamepadConfig, String> {
+        let mut config = GamepadConfig::built_in();
+
+        config.read(text)?;
+        Ok(config)
+    }
+
+    fn read(&mut self, text: &str) -> Result<(), String> {
+        let mut rom_hash: Option<String> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("line {}: can not read {}", index + 1, line.trim());
+            let key = |key: &str| u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or_else(invalid);
+            let input = |name: &str| Input::by_name(name).ok_or_else(invalid);
+
+            if let ["rom", hash] = fields.as_slice() {
+                rom_hash = Some(hash.to_lowercase());
+                continue;
+            }
+
+            let profile = match &rom_hash {
+                Some(hash) => self.roms.entry(hash.clone()).or_default(),
+                None => &mut self.default
+            };
+
+            match fields.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["deadzone", deadzone] => profile.deadzone = Some(deadzone.parse().map_err(|_| invalid())?),
+                ["bind", name, bound] => profile.bind(input(name)?, key(bound)?),
+                ["turbo", name, bound, frames] => profile.bind_turbo(input(name)?, key(bound)?, frames.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid())
+            }
+        }
+
+        Ok(())
+    }
+
+    // The default profile with the overrides for the ROM with this SHA-1.
+    pub fn for_hash(&self, hash: &str) -> GamepadProfile {
+        let mut profile = self.default.clone();
+
+        if let Some(overrides) = self.roms.get(&hash.to_lowercase()) {
+            profile.extend(overrides);
+        }
+
+        profile
+    }
+
+    pub fn for_rom(&self, rom: &[u8]) -> GamepadProfile {
+        self.for_hash(&loader::rom_hash(rom))
+    }
+}
+
+impl Gamepad {
+    pub fn new(profile: GamepadProfile) -> Gamepad {
+        Gamepad { profile, held: BTreeMap::new() }
+    }
+
+    pub fn profile(&self) -> &GamepadProfile {
+        &self.profile
+    }
+
+    pub fn handle(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::Button(button, true) => self.hold(Input::Button(button)),
+            GamepadEvent::Button(button, false) => self.release(Input::Button(button)),
+            GamepadEvent::Axis(axis, value) => {
+                let deadzone = self.profile.deadzone.unwrap_or(DEFAULT_DEADZONE);
+
+                match value <= -deadzone {
+                    true => self.hold(Input::AxisNegative(axis)),
+                    false => self.release(Input::AxisNegative(axis))
+                }
+
+                match value >= deadzone {
+                    true => self.hold(Input::AxisPositive(axis)),
+                    false => self.release(Input::AxisPositive(axis))
+                }
+            }
+        }
+    }
+
+    fn hold(&mut self, input: Input) {
+        self.held.entry(input).or_insert(0);
+    }
+
+    fn release(&mut self, input: Input) {
+        self.held.remove(&input);
+    }
+
+    // Called once per frame. Only touches the keys the profile binds, so a keyboard can press the others.
+    pub fn update(&mut self, keypad: &mut Keypad) {
+        let mut pressed = [false; 16];
+
+        for (input, frames_held) in self.held.iter_mut() {
+            if let Some(binding) = self.profile.bindings.get(input) {
+                pressed[binding.key as usize] |= match binding.turbo {
+                    Some(frames) => (*frames_held / frames).is_multiple_of(2),
+                    None => true
+                };
+            }
+
+            *frames_held += 1;
+        }
+
+        for binding in self.profile.bindings.values() {
+            keypad.keys[binding.key as usize] = pressed[binding.key as usize];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Axis, Button, Gamepad, GamepadConfig, GamepadEvent, Input};
+    use crate::cpu::Chip8;
+
+    fn pressed_keys(chip8: &Chip8) -> Vec<usize> {
+        (0..16).filter(|&key| chip8.keypad.keys[key]).collect()
+    }
+
+    #[test]
+    fn sticks_and_buttons_press_bound_keys() {
+        let mut gamepad = Gamepad::new(GamepadConfig::built_in().for_hash("b232ef880bd6060fb45fa6effed7edf0ae95670e"));
+        let mut chip8 = Chip8::initialize();
+
+        chip8.keypad.keys[0xA] = true;
+        gamepad.handle(GamepadEvent::Axis(Axis::LeftY, 0.1));
+        gamepad.handle(GamepadEvent::Button(Button::South, true));
+        gamepad.update(&mut chip8.keypad);
+        assert_eq!(pressed_keys(&chip8), vec![0x5, 0xA]);
+
+        gamepad.handle(GamepadEvent::Axis(Axis::LeftY, 0.9));
+        gamepad.handle(GamepadEvent::Button(Button::South, false));
+        gamepad.update(&mut chip8.keypad);
+        assert_eq!(pressed_keys(&chip8), vec![0x4, 0xA]);
+
+        gamepad.handle(GamepadEvent::Axis(Axis::LeftY, -0.5));
+        gamepad.update(&mut chip8.keypad);
+        assert_eq!(pressed_keys(&chip8), vec![0x1, 0xA]);
+
+        assert_eq!(Button::from_index(12), Some(Button::Up));
+        assert_eq!(Input::by_name("lefty+"), Some(Input::AxisPositive(Axis::LeftY)));
+    }
+
+    #[test]
+    fn turbo_toggles_and_profiles_set_the_deadzone() {
+        let config = GamepadConfig::parse("turbo South 5 2\nrom 00aa\ndeadzone 0.6\nbind RightX+ c\n").unwrap();
+        let mut gamepad = Gamepad::new(config.for_hash("00AA"));
+        let mut chip8 = Chip8::initialize();
+        let mut turbo = Vec::new();
+
+        gamepad.handle(GamepadEvent::Button(Button::South, true));
+        gamepad.handle(GamepadEvent::Axis(Axis::RightX, 0.5));
+
+        for _ in 0..6 {
+            gamepad.update(&mut chip8.keypad);
+            turbo.push(chip8.keypad.keys[0x5]);
+        }
+
+        assert_eq!(turbo, vec![true, true, false, false, true, true]);
+        assert!(!chip8.keypad.keys[0xC]);
+
+        gamepad.handle(GamepadEvent::Axis(Axis::RightX, 0.7));
+        gamepad.update(&mut chip8.keypad);
+        assert!(chip8.keypad.keys[0xC]);
+        assert_eq!(config.for_hash("ffff").deadzone, None);
+        assert!(GamepadConfig::parse("bind Trigger 5").unwrap_err().starts_with("line 1"));
+    }
+}
@@ -0,0 +1,582 @@
+// A GDB remote serial protocol stub, so debuggers that speak RSP can attach to the emulator over TCP.
+//
+// Registers are numbered V0-VF (0-15, 8 bits), I (16), PC (17) and SP (18), the 16 bit ones little endian.
+// The layout is also described by the target.xml served through qXfer:features:read.
+// Supported packets: ? g G p P m M Z0 z0 s c D k, qSupported, qXfer, QStartNoAckMode and Ctrl-C while running.
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+use crate::cpu::Chip8;
+use crate::error::Chip8Error;
+
+const REGISTER_COUNT: usize = 19;
+const INTERRUPT: u8 = 0x03;
+const INTERRUPT_CHECK_CYCLES: u64 = 1000;
+// The PacketSize advertised in qSupported, larger packets drop the connection.
+const MAX_PACKET_SIZE: usize = 0x1000;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><feature name="org.chip8.core">"#,
+    r#"<reg name="v0" bitsize="8" regnum="0"/><reg name="v1" bitsize="8"/><reg name="v2" bitsize="8"/><reg name="v3" bitsize="8"/>"#,
+    r#"<reg name="v4" bitsize="8"/><reg name="v5" bitsize="8"/><reg name="v6" bitsize="8"/><reg name="v7" bitsize="8"/>"#,
+    r#"<reg name="v8" bitsize="8"/><reg name="v9" bitsize="8"/><reg name="va" bitsize="8"/><reg name="vb" bitsize="8"/>"#,
+    r#"<reg name="vc" bitsize="8"/><reg name="vd" bitsize="8"/><reg name="ve" bitsize="8"/><reg name="vf" bitsize="8"/>"#,
+    r#"<reg name="i" bitsize="16" type="data_ptr"/><reg name="pc" bitsize="16" type="code_ptr"/><reg name="sp" bitsize="16"/>"#,
+    r#"</feature></target>"#
+);
+
+// Why execution stopped, reported to the debugger as a signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+    Interrupted,
+    Error(Chip8Error),
+}
+
+// What the server loop should do after a packet has been handled.
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Reply(String),
+    // Start running until a breakpoint, an error or an interrupt, then send the stop reply.
+    Continue,
+    // Reply and close the connection.
+    Close(String),
+}
+
+pub struct GdbStub {
+    chip8: Chip8,
+    breakpoints: BTreeSet<u16>,
+    cycles_per_frame: u32,
+    cycles_since_frame: u32,
+    no_ack: bool,
+}
+
+impl StopReason {
+    // The reply telling the debugger the target stopped, with a POSIX signal number.
+    pub fn stop_reply(&self) -> String {
+        let signal = match self {
+            StopReason::Breakpoint | StopReason::Step => 5,
+            StopReason::Interrupted => 2,
+            StopReason::Error(Chip8Error::UnknownOpCode(_)) => 4,
+            StopReason::Error(Chip8Error::MemoryOutOfBounds(_)) => 11,
+            StopReason::Error(_) => 6,
+        };
+
+        format!("S{:02x}", signal)
+    }
+}
+
+impl GdbStub {
+    pub fn new(chip8: Chip8, cycles_per_frame: u32) -> GdbStub {
+        GdbStub { chip8, breakpoints: BTreeSet::new(), cycles_per_frame, cycles_since_frame: 0, no_ack: false }
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    // Handles the contents of one packet, without the $ and checksum.
+    pub fn handle_packet(&mut self, packet: &str) -> Response {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match command {
+            "?" => StopReason::Breakpoint.stop_reply(),
+            "g" => (0..REGISTER_COUNT).map(|register| self.read_register(register).unwrap()).collect(),
+            "G" => self.write_registers(arguments),
+            "p" => usize::from_str_radix(arguments, 16).ok().and_then(|register| self.read_register(register)).unwrap_or_else(|| "E01".to_string()),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "Z" | "z" => self.change_breakpoint(command == "Z", arguments),
+            "s" => self.step().stop_reply(),
+            "c" => return Response::Continue,
+            "D" => return Response::Close("OK".to_string()),
+            "k" => return Response::Close(String::new()),
+            "H" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new()
+        };
+
+        Response::Reply(reply)
+    }
+
+    // Executes one instruction, keeping the timers running at the usual rate.
+    pub fn step(&mut self) -> StopReason {
+        if let Err(error) = self.chip8.emulate_cycle() {
+            return StopReason::Error(error);
+        }
+
+        self.cycles_since_frame += 1;
+        if self.cycles_since_frame >= self.cycles_per_frame {
+            self.cycles_since_frame = 0;
+            self.chip8.tick_timers();
+        }
+
+        StopReason::Step
+    }
+
+    // Runs until a breakpoint is reached or `interrupted` returns true, which is asked every few cycles.
+    pub fn run(&mut self, interrupted: &mut dyn FnMut() -> bool) -> StopReason {
+        let mut cycles = 0u64;
+
+        loop {
+            if let StopReason::Error(error) = self.step() {
+                return StopReason::Error(error);
+            }
+
+            if self.breakpoints.contains(&self.chip8.registers.program_counter) {
+                return StopReason::Breakpoint;
+            }
+
+            cycles += 1;
+            if cycles.is_multiple_of(INTERRUPT_CHECK_CYCLES) && interrupted() {
+                return StopReason::Interrupted;
+            }
+        }
+    }
+
+    // Serves one debugger connection until it detaches or disconnects.
+    pub fn serve(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        // Bytes that arrived with a Ctrl-C check while running, read before the stream.
+        let mut pending = VecDeque::new();
+
+        self.no_ack = false;
+        stream.set_nodelay(true)?;
+
+        loop {
+            let packet = match read_packet(stream, &mut pending)? {
+                Some(Incoming::Packet(packet)) => packet,
+                Some(Incoming::Interrupt) => {
+                    write_packet(stream, &StopReason::Interrupted.stop_reply())?;
+                    continue;
+                }
+                Some(Incoming::Corrupted) => {
+                    stream.write_all(b"-")?;
+                    continue;
+                }
+                None => return Ok(())
+            };
+
+            if !self.no_ack {
+                stream.write_all(b"+")?;
+            }
+
+            match self.handle_packet(&packet) {
+                Response::Reply(reply) => write_packet(stream, &reply)?,
+                Response::Close(reply) => {
+                    write_packet(stream, &reply)?;
+                    return Ok(());
+                }
+                Response::Continue => {
+                    let reason = self.run(&mut || interrupt_pending(stream, &mut pending));
+                    write_packet(stream, &reason.stop_reply())?;
+                }
+            }
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_address_and_length(range).and_then(|(offset, length)| Some((offset, offset.checked_add(length)?))) {
+                Some((offset, end)) => {
+                    let start = offset.min(TARGET_XML.len());
+                    let end = end.min(TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, &TARGET_XML[start..end])
+                }
+                None => "E01".to_string()
+            };
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new()
+        }
+    }
+
+    fn read_register(&self, register: usize) -> Option<String> {
+        let registers = &self.chip8.registers;
+
+        match register {
+            0..=15 => Some(format!("{:02x}", registers.v[register])),
+            16 => Some(hex_u16(registers.i)),
+            17 => Some(hex_u16(registers.program_counter)),
+            18 => Some(hex_u16(self.chip8.stack.stack_pointer)),
+            _ => None
+        }
+    }
+
+    fn set_register(&mut self, register: usize, value: u16) -> bool {
+        match register {
+            0..=15 if value <= 0xFF => self.chip8.registers.v[register] = value as u8,
+            16 => self.chip8.registers.i = value,
+            17 => self.chip8.registers.program_counter = value,
+            18 if value as usize <= self.chip8.stack.stack.len() => self.chip8.stack.stack_pointer = value,
+            _ => return false
+        }
+
+        true
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        let bytes = match decode_hex(arguments) {
+            Some(bytes) if bytes.len() == 22 => bytes,
+            _ => return "E01".to_string()
+        };
+
+        let saved = (self.chip8.registers, self.chip8.stack.stack_pointer);
+
+        for register in 0..REGISTER_COUNT {
+            let value = match register {
+                0..=15 => bytes[register] as u16,
+                _ => u16::from_le_bytes([bytes[16 + (register - 16) * 2], bytes[17 + (register - 16) * 2]])
+            };
+
+            if !self.set_register(register, value) {
+                self.chip8.registers = saved.0;
+                self.chip8.stack.stack_pointer = saved.1;
+                return "E02".to_string();
+            }
+        }
+
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let mut parts = arguments.splitn(2, '=');
+        let register = parts.next().and_then(|register| usize::from_str_radix(register, 16).ok());
+        // Values wider than the register are refused rather than cut down.
+        let value = parts.next().and_then(decode_hex).and_then(|bytes| match bytes.as_slice() {
+            [low] => Some(*low as u16),
+            [low, high] => Some(u16::from_le_bytes([*low, *high])),
+            _ => None
+        });
+
+        match (register, value) {
+            (Some(register), Some(value)) if self.set_register(register, value) => "OK".to_string(),
+            _ => "E01".to_string()
+        }
+    }
+
+    fn read_memory(&self, arguments: &str) -> String {
+        let ram = &self.chip8.memory.ram;
+
+        match parse_address_and_length(arguments) {
+            // Reads running past the end are cut short, lengths beyond all of memory are refused.
+            Some((address, length)) if address < ram.len() && length <= ram.len() => {
+                ram[address..(address + length).min(ram.len())].iter().map(|byte| format!("{:02x}", byte)).collect()
+            }
+            _ => "E01".to_string()
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let mut parts = arguments.splitn(2, ':');
+        let target = parts.next().and_then(parse_address_and_length);
+        let bytes = parts.next().and_then(decode_hex);
+
+        match (target, bytes) {
+            (Some((address, length)), Some(bytes))
+                if bytes.len() == length && address.checked_add(length).is_some_and(|end| end <= self.chip8.memory.ram.len()) =>
+            {
+                self.chip8.memory.ram[address..address + length].copy_from_slice(&bytes);
+                "OK".to_string()
+            }
+            _ => "E01".to_string()
+        }
+    }
+
+    // Only software breakpoints (type 0) are supported, every address can hold one.
+    fn change_breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut parts = arguments.split(',');
+
+        if parts.next() != Some("0") {
+            return String::new();
+        }
+
+        match parts.next().and_then(|address| u16::from_str_radix(address, 16).ok()) {
+            Some(address) => {
+                match insert {
+                    true => self.breakpoints.insert(address),
+                    false => self.breakpoints.remove(&address)
+                };
+                "OK".to_string()
+            }
+            None => "E01".to_string()
+        }
+    }
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    Corrupted,
+}
+
+fn hex_u16(value: u16) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok()).collect()
+}
+
+// Parses the "addr,length" part of m, M and qXfer packets.
+fn parse_address_and_length(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let address = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let length = usize::from_str_radix(parts.next()?, 16).ok()?;
+
+    Some((address, length))
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", data, checksum(data))?;
+    stream.flush()
+}
+
+fn read_byte(stream: &mut TcpStream, pending: &mut VecDeque<u8>) -> io::Result<Option<u8>> {
+    if let Some(byte) = pending.pop_front() {
+        return Ok(Some(byte));
+    }
+
+    let mut byte = [0u8];
+
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0]))
+    }
+}
+
+// Returns None once the debugger disconnects. Acknowledgements from the debugger are skipped.
+fn read_packet(stream: &mut TcpStream, pending: &mut VecDeque<u8>) -> io::Result<Option<Incoming>> {
+    loop {
+        match read_byte(stream, pending)? {
+            None => return Ok(None),
+            Some(INTERRUPT) => return Ok(Some(Incoming::Interrupt)),
+            Some(b'$') => break,
+            Some(_) => {}
+        }
+    }
+
+    let mut data = Vec::new();
+
+    loop {
+        match read_byte(stream, pending)? {
+            None => return Ok(None),
+            Some(b'#') => break,
+            Some(_) if data.len() == MAX_PACKET_SIZE => {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("packet larger than {} bytes", MAX_PACKET_SIZE)));
+            }
+            Some(byte) => data.push(byte)
+        }
+    }
+
+    let mut sum = [0u8; 2];
+
+    for digit in sum.iter_mut() {
+        *digit = read_byte(stream, pending)?.ok_or(ErrorKind::UnexpectedEof)?;
+    }
+
+    let data = String::from_utf8_lossy(&data).into_owned();
+    let expected = std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+    match expected == Some(checksum(&data)) {
+        true => Ok(Some(Incoming::Packet(data))),
+        false => Ok(Some(Incoming::Corrupted))
+    }
+}
+
+// Checks without blocking whether the debugger sent Ctrl-C, a disconnect counts as one too.
+// Other bytes are kept in pending for the next read_packet.
+fn interrupt_pending(stream: &mut TcpStream, pending: &mut VecDeque<u8>) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let mut buffer = [0u8; 64];
+    let interrupted = match stream.read(&mut buffer) {
+        Ok(0) => true,
+        Ok(read) => {
+            let interrupt = buffer[..read].iter().position(|&byte| byte == INTERRUPT);
+
+            pending.extend(buffer[..read].iter().enumerate().filter(|&(index, _)| Some(index) != interrupt).map(|(_, &byte)| byte));
+            interrupt.is_some()
+        }
+        Err(ref error) if error.kind() == ErrorKind::WouldBlock => false,
+        Err(_) => true
+    };
+
+    stream.set_nonblocking(false).is_ok() && interrupted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{checksum, GdbStub, Response};
+    use crate::cpu::Chip8;
+
+    // LD V0, 05 / ADD V0, 01 / JP 202
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    fn stub() -> GdbStub {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.load_rom(&ROM).unwrap();
+
+        GdbStub::new(chip8, 10)
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle_packet(packet) {
+            Response::Reply(reply) => reply,
+            response => panic!("unexpected {:?}", response)
+        }
+    }
+
+    struct Client(TcpStream);
+
+    impl Client {
+        fn send(&mut self, data: &str) -> String {
+            write!(self.0, "${}#{:02x}", data, checksum(data)).unwrap();
+            self.receive()
+        }
+
+        fn receive(&mut self) -> String {
+            let mut received = Vec::new();
+            let mut byte = [0u8];
+
+            while !received.ends_with(b"#") {
+                self.0.read_exact(&mut byte).unwrap();
+                received.push(byte[0]);
+            }
+            let mut sum = [0u8; 2];
+            self.0.read_exact(&mut sum).unwrap();
+
+            let received = String::from_utf8(received).unwrap();
+            let data = received.trim_start_matches('+').trim_start_matches('$').trim_end_matches('#').to_string();
+
+            assert_eq!(u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap(), checksum(&data));
+            data
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_registers_and_memory() {
+        let mut stub = stub();
+
+        assert_eq!(reply(&mut stub, "g"), format!("{}000000020000", "00".repeat(16)));
+        assert_eq!(reply(&mut stub, "m200,4"), "60057001");
+        assert_eq!(reply(&mut stub, "M300,2:abcd"), "OK");
+        assert_eq!(stub.chip8().memory.ram[0x301], 0xCD);
+        assert_eq!(reply(&mut stub, "P3=2a"), "OK");
+        assert_eq!(reply(&mut stub, "P10=2403"), "OK");
+        assert_eq!(reply(&mut stub, "p3"), "2a");
+        assert_eq!(reply(&mut stub, "p10"), "2403");
+        assert_eq!(stub.chip8().registers.i, 0x324);
+        assert_eq!(reply(&mut stub, "P12=1100"), "E01");
+        assert_eq!(reply(&mut stub, "mfff,2"), "00");
+        assert_eq!(reply(&mut stub, "M1000,1:00"), "E01");
+        assert_eq!(reply(&mut stub, "P3=2a01"), "E01");
+        assert_eq!(reply(&mut stub, "P10=240300"), "E01");
+        assert_eq!(reply(&mut stub, "p3"), "2a");
+        assert_eq!(reply(&mut stub, "vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    fn huge_addresses_and_lengths_are_refused() {
+        let mut stub = stub();
+
+        assert_eq!(reply(&mut stub, "m0,ffffffffffffffff"), "E01");
+        assert_eq!(reply(&mut stub, "m200,ffffffffffffffff"), "E01");
+        assert_eq!(reply(&mut stub, "mffffffffffffffff,1"), "E01");
+        assert_eq!(reply(&mut stub, "Mffffffffffffffff,1:00"), "E01");
+        assert_eq!(reply(&mut stub, "M200,ffffffffffffffff:00"), "E01");
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:1,ffffffffffffffff"), "E01");
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:ffffffffffffffff,1"), "E01");
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:ffff,1"), "l");
+        assert_eq!(reply(&mut stub, "m200,2"), "6005");
+    }
+
+    #[test]
+    fn scripted_client_sets_breakpoint_steps_and_continues() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut stub = stub();
+            let (mut stream, _) = listener.accept().unwrap();
+
+            stub.serve(&mut stream).unwrap();
+            stub.chip8().registers.v[0]
+        });
+        let mut client = Client(TcpStream::connect(address).unwrap());
+
+        assert!(client.send("qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert!(client.send("qXfer:features:read:target.xml:0,20").starts_with("m<?xml"));
+        assert_eq!(client.send("?"), "S05");
+        assert_eq!(client.send("s"), "S05");
+        assert_eq!(client.send("p11"), "0202");
+        assert_eq!(client.send("Z0,204,2"), "OK");
+        assert_eq!(client.send("c"), "S05");
+        assert_eq!(client.send("p11"), "0402");
+        assert_eq!(client.send("p0"), "06");
+        assert_eq!(client.send("c"), "S05");
+        assert_eq!(client.send("p0"), "07");
+        assert_eq!(client.send("z0,204,2"), "OK");
+        assert_eq!(client.send("D"), "OK");
+
+        assert_eq!(server.join().unwrap(), 7);
+    }
+
+    #[test]
+    fn packets_sent_while_running_are_kept_and_huge_ones_drop_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut stub = stub();
+            let (mut stream, _) = listener.accept().unwrap();
+
+            stub.serve(&mut stream).unwrap_err().to_string()
+        });
+        let mut client = Client(TcpStream::connect(address).unwrap());
+
+        assert_eq!(client.send("QStartNoAckMode"), "OK");
+        write!(client.0, "$c#63").unwrap();
+        write!(client.0, "$p11#{:02x}\x03", checksum("p11")).unwrap();
+
+        assert_eq!(client.receive(), "S02");
+        assert_eq!(client.receive().len(), 4);
+
+        client.0.write_all(format!("${}", "0".repeat(0x1001)).as_bytes()).unwrap();
+
+        assert!(server.join().unwrap().contains("packet larger than"));
+    }
+}
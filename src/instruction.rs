@@ -0,0 +1,294 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use core::str::FromStr;
+
+use crate::error::Chip8Error;
+#[cfg(feature = "std")]
+use crate::quirks::Quirks;
+
+// A decoded op code. X and Y are register indexes, the rest are the immediate values of the op code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    ClearScreen,
+    Return,
+    System(u16),
+    Jump(u16),
+    Call(u16),
+    SkipIfEqual(u8, u8),
+    SkipIfNotEqual(u8, u8),
+    SkipIfRegistersEqual(u8, u8),
+    Load(u8, u8),
+    Add(u8, u8),
+    Move(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddRegisters(u8, u8),
+    Subtract(u8, u8),
+    ShiftRight(u8, u8),
+    SubtractReversed(u8, u8),
+    ShiftLeft(u8, u8),
+    SkipIfRegistersNotEqual(u8, u8),
+    LoadI(u16),
+    JumpWithOffset(u16),
+    Random(u8, u8),
+    Draw(u8, u8, u8),
+    SkipIfKeyPressed(u8),
+    SkipIfKeyNotPressed(u8),
+    LoadDelayTimer(u8),
+    WaitForKey(u8),
+    SetDelayTimer(u8),
+    SetSoundTimer(u8),
+    AddToI(u8),
+    LoadFontCharacter(u8),
+    StoreBcd(u8),
+    StoreRegisters(u8),
+    LoadRegisters(u8),
+}
+
+// Coarse grouping of instructions, used to filter traces and aggregate profiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InstructionClass {
+    Flow,
+    Skip,
+    Arithmetic,
+    Memory,
+    Display,
+    Input,
+    Timer,
+    Random,
+    System,
+}
+
+impl Instruction {
+    pub fn decode(op_code: u16) -> Result<Instruction, Chip8Error> {
+        let x = ((op_code & 0x0F00) >> 8) as u8;
+        let y = ((op_code & 0x00F0) >> 4) as u8;
+        let n = (op_code & 0x000F) as u8;
+        let kk = (op_code & 0x00FF) as u8;
+        let nnn = op_code & 0x0FFF;
+
+        let instruction = match (op_code >> 12, n) {
+            (0x0, _) => match op_code {
+                0x00E0 => Instruction::ClearScreen,
+                0x00EE => Instruction::Return,
+                _ => Instruction::System(nnn)
+            },
+            (0x1, _) => Instruction::Jump(nnn),
+            (0x2, _) => Instruction::Call(nnn),
+            (0x3, _) => Instruction::SkipIfEqual(x, kk),
+            (0x4, _) => Instruction::SkipIfNotEqual(x, kk),
+            (0x5, 0x0) => Instruction::SkipIfRegistersEqual(x, y),
+            (0x6, _) => Instruction::Load(x, kk),
+            (0x7, _) => Instruction::Add(x, kk),
+            (0x8, 0x0) => Instruction::Move(x, y),
+            (0x8, 0x1) => Instruction::Or(x, y),
+            (0x8, 0x2) => Instruction::And(x, y),
+            (0x8, 0x3) => Instruction::Xor(x, y),
+            (0x8, 0x4) => Instruction::AddRegisters(x, y),
+            (0x8, 0x5) => Instruction::Subtract(x, y),
+            (0x8, 0x6) => Instruction::ShiftRight(x, y),
+            (0x8, 0x7) => Instruction::SubtractReversed(x, y),
+            (0x8, 0xE) => Instruction::ShiftLeft(x, y),
+            (0x9, 0x0) => Instruction::SkipIfRegistersNotEqual(x, y),
+            (0xA, _) => Instruction::LoadI(nnn),
+            (0xB, _) => Instruction::JumpWithOffset(nnn),
+            (0xC, _) => Instruction::Random(x, kk),
+            (0xD, _) => Instruction::Draw(x, y, n),
+            (0xE, _) if kk == 0x9E => Instruction::SkipIfKeyPressed(x),
+            (0xE, _) if kk == 0xA1 => Instruction::SkipIfKeyNotPressed(x),
+            (0xF, _) => match kk {
+                0x07 => Instruction::LoadDelayTimer(x),
+                0x0A => Instruction::WaitForKey(x),
+                0x15 => Instruction::SetDelayTimer(x),
+                0x18 => Instruction::SetSoundTimer(x),
+                0x1E => Instruction::AddToI(x),
+                0x29 => Instruction::LoadFontCharacter(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                _ => return Err(Chip8Error::UnknownOpCode(op_code))
+            },
+            _ => return Err(Chip8Error::UnknownOpCode(op_code))
+        };
+
+        Ok(instruction)
+    }
+
+    pub fn class(&self) -> InstructionClass {
+        match self {
+            Instruction::Return | Instruction::Jump(_) | Instruction::Call(_) | Instruction::JumpWithOffset(_) => InstructionClass::Flow,
+            Instruction::SkipIfEqual(..) | Instruction::SkipIfNotEqual(..)
+            | Instruction::SkipIfRegistersEqual(..) | Instruction::SkipIfRegistersNotEqual(..) => InstructionClass::Skip,
+            Instruction::Load(..) | Instruction::Add(..) | Instruction::Move(..) | Instruction::Or(..) | Instruction::And(..)
+            | Instruction::Xor(..) | Instruction::AddRegisters(..) | Instruction::Subtract(..) | Instruction::ShiftRight(..)
+            | Instruction::SubtractReversed(..) | Instruction::ShiftLeft(..) => InstructionClass::Arithmetic,
+            Instruction::LoadI(_) | Instruction::AddToI(_) | Instruction::LoadFontCharacter(_) | Instruction::StoreBcd(_)
+            | Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_) => InstructionClass::Memory,
+            Instruction::ClearScreen | Instruction::Draw(..) => InstructionClass::Display,
+            Instruction::SkipIfKeyPressed(_) | Instruction::SkipIfKeyNotPressed(_) | Instruction::WaitForKey(_) => InstructionClass::Input,
+            Instruction::LoadDelayTimer(_) | Instruction::SetDelayTimer(_) | Instruction::SetSoundTimer(_) => InstructionClass::Timer,
+            Instruction::Random(..) => InstructionClass::Random,
+            Instruction::System(_) => InstructionClass::System,
+        }
+    }
+
+    // The op code with its operands replaced by placeholders, e.g. DXYN, used to group instructions of the same kind.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::ClearScreen => "00E0",
+            Instruction::Return => "00EE",
+            Instruction::System(_) => "0NNN",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipIfEqual(..) => "3XKK",
+            Instruction::SkipIfNotEqual(..) => "4XKK",
+            Instruction::SkipIfRegistersEqual(..) => "5XY0",
+            Instruction::Load(..) => "6XKK",
+            Instruction::Add(..) => "7XKK",
+            Instruction::Move(..) => "8XY0",
+            Instruction::Or(..) => "8XY1",
+            Instruction::And(..) => "8XY2",
+            Instruction::Xor(..) => "8XY3",
+            Instruction::AddRegisters(..) => "8XY4",
+            Instruction::Subtract(..) => "8XY5",
+            Instruction::ShiftRight(..) => "8XY6",
+            Instruction::SubtractReversed(..) => "8XY7",
+            Instruction::ShiftLeft(..) => "8XYE",
+            Instruction::SkipIfRegistersNotEqual(..) => "9XY0",
+            Instruction::LoadI(_) => "ANNN",
+            Instruction::JumpWithOffset(_) => "BNNN",
+            Instruction::Random(..) => "CXKK",
+            Instruction::Draw(..) => "DXYN",
+            Instruction::SkipIfKeyPressed(_) => "EX9E",
+            Instruction::SkipIfKeyNotPressed(_) => "EXA1",
+            Instruction::LoadDelayTimer(_) => "FX07",
+            Instruction::WaitForKey(_) => "FX0A",
+            Instruction::SetDelayTimer(_) => "FX15",
+            Instruction::SetSoundTimer(_) => "FX18",
+            Instruction::AddToI(_) => "FX1E",
+            Instruction::LoadFontCharacter(_) => "FX29",
+            Instruction::StoreBcd(_) => "FX33",
+            Instruction::StoreRegisters(_) => "FX55",
+            Instruction::LoadRegisters(_) => "FX65",
+        }
+    }
+}
+
+// Mnemonics follow Cowgod's Chip-8 technical reference.
+impl fmt::Display for Instruction {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(formatter, "CLS"),
+            Instruction::Return => write!(formatter, "RET"),
+            Instruction::System(nnn) => write!(formatter, "SYS {:03X}", nnn),
+            Instruction::Jump(nnn) => write!(formatter, "JP {:03X}", nnn),
+            Instruction::Call(nnn) => write!(formatter, "CALL {:03X}", nnn),
+            Instruction::SkipIfEqual(x, kk) => write!(formatter, "SE V{:X}, {:02X}", x, kk),
+            Instruction::SkipIfNotEqual(x, kk) => write!(formatter, "SNE V{:X}, {:02X}", x, kk),
+            Instruction::SkipIfRegistersEqual(x, y) => write!(formatter, "SE V{:X}, V{:X}", x, y),
+            Instruction::Load(x, kk) => write!(formatter, "LD V{:X}, {:02X}", x, kk),
+            Instruction::Add(x, kk) => write!(formatter, "ADD V{:X}, {:02X}", x, kk),
+            Instruction::Move(x, y) => write!(formatter, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(formatter, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(formatter, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(formatter, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegisters(x, y) => write!(formatter, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Subtract(x, y) => write!(formatter, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(formatter, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubtractReversed(x, y) => write!(formatter, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(formatter, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfRegistersNotEqual(x, y) => write!(formatter, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(formatter, "LD I, {:03X}", nnn),
+            Instruction::JumpWithOffset(nnn) => write!(formatter, "JP V0, {:03X}", nnn),
+            Instruction::Random(x, kk) => write!(formatter, "RND V{:X}, {:02X}", x, kk),
+            Instruction::Draw(x, y, n) => write!(formatter, "DRW V{:X}, V{:X}, {:X}", x, y, n),
+            Instruction::SkipIfKeyPressed(x) => write!(formatter, "SKP V{:X}", x),
+            Instruction::SkipIfKeyNotPressed(x) => write!(formatter, "SKNP V{:X}", x),
+            Instruction::LoadDelayTimer(x) => write!(formatter, "LD V{:X}, DT", x),
+            Instruction::WaitForKey(x) => write!(formatter, "LD V{:X}, K", x),
+            Instruction::SetDelayTimer(x) => write!(formatter, "LD DT, V{:X}", x),
+            Instruction::SetSoundTimer(x) => write!(formatter, "LD ST, V{:X}", x),
+            Instruction::AddToI(x) => write!(formatter, "ADD I, V{:X}", x),
+            Instruction::LoadFontCharacter(x) => write!(formatter, "LD F, V{:X}", x),
+            Instruction::StoreBcd(x) => write!(formatter, "LD B, V{:X}", x),
+            Instruction::StoreRegisters(x) => write!(formatter, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters(x) => write!(formatter, "LD V{:X}, [I]", x),
+        }
+    }
+}
+
+// Unknown op codes are shown as raw data, the way disassemblers usually do.
+#[cfg(feature = "std")]
+pub fn mnemonic(op_code: u16) -> String {
+    match Instruction::decode(op_code) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => format!("DW {:04X}", op_code)
+    }
+}
+
+// Like mnemonic, naming the register BNNN adds with the quirks the machine runs with.
+#[cfg(feature = "std")]
+pub fn mnemonic_with_quirks(op_code: u16, quirks: &Quirks) -> String {
+    match (Instruction::decode(op_code), quirks.jump_uses_vx) {
+        (Ok(Instruction::JumpWithOffset(nnn)), true) => format!("JP V{:X}, {:03X}", nnn >> 8, nnn),
+        _ => mnemonic(op_code)
+    }
+}
+
+#[cfg(feature = "std")]
+impl FromStr for InstructionClass {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<InstructionClass, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "flow" => Ok(InstructionClass::Flow),
+            "skip" => Ok(InstructionClass::Skip),
+            "arithmetic" => Ok(InstructionClass::Arithmetic),
+            "memory" => Ok(InstructionClass::Memory),
+            "display" => Ok(InstructionClass::Display),
+            "input" => Ok(InstructionClass::Input),
+            "timer" => Ok(InstructionClass::Timer),
+            "random" => Ok(InstructionClass::Random),
+            "system" => Ok(InstructionClass::System),
+            _ => Err(format!("unknown instruction class {}", name))
+        }
+    }
+}
+
+// The tests use the mnemonics and class names, which need std.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{mnemonic, mnemonic_with_quirks, Instruction, InstructionClass};
+    use crate::error::Chip8Error;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn can_decode_op_codes() {
+        assert_eq!(Instruction::decode(0x00E0), Ok(Instruction::ClearScreen));
+        assert_eq!(Instruction::decode(0x0123), Ok(Instruction::System(0x123)));
+        assert_eq!(Instruction::decode(0x8AB4), Ok(Instruction::AddRegisters(0xA, 0xB)));
+        assert_eq!(Instruction::decode(0xD125), Ok(Instruction::Draw(1, 2, 5)));
+        assert_eq!(Instruction::decode(0xF265), Ok(Instruction::LoadRegisters(2)));
+        assert_eq!(Instruction::decode(0x5121), Err(Chip8Error::UnknownOpCode(0x5121)));
+        assert_eq!(Instruction::decode(0xE1A2), Err(Chip8Error::UnknownOpCode(0xE1A2)));
+    }
+
+    #[test]
+    fn can_format_mnemonics() {
+        assert_eq!(mnemonic(0x22A4), "CALL 2A4");
+        assert_eq!(mnemonic(0x3A0F), "SE VA, 0F");
+        assert_eq!(mnemonic(0xD01F), "DRW V0, V1, F");
+        assert_eq!(mnemonic(0xF055), "LD [I], V0");
+        assert_eq!(mnemonic(0xFFFF), "DW FFFF");
+        assert_eq!(mnemonic(0xB2A0), "JP V0, 2A0");
+        assert_eq!(mnemonic_with_quirks(0xB2A0, &Quirks::super_chip()), "JP V2, 2A0");
+        assert_eq!(mnemonic_with_quirks(0xB2A0, &Quirks::cosmac_vip()), "JP V0, 2A0");
+    }
+
+    #[test]
+    fn can_parse_instruction_class() {
+        assert_eq!("Display".parse(), Ok(InstructionClass::Display));
+        assert!("graphics".parse::<InstructionClass>().is_err());
+    }
+}
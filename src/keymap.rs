@@ -0,0 +1,224 @@
+// Which host keys press which of the 16 CHIP-8 keys, shared by the browser and terminal frontends.
+//
+// The standard layout puts the COSMAC VIP keypad on the left of a QWERTY keyboard:
+//
+//     1 2 3 4        1 2 3 C
+//     Q W E R   ->   4 5 6 D
+//     A S D F        7 8 9 E
+//     Z X C V        A 0 B F
+//
+// Key map files change it, one entry per line, with CHIP-8 keys in hexadecimal:
+//
+//     key Space 5          Space presses 5 as well
+//     rom b232ef88...      the entries after this one only apply to the ROM with this SHA-1
+//     key Up C
+//
+// Blank lines and lines starting with # are ignored. Host keys are named as browsers do
+// (`KeyW`, `Digit1`, `ArrowUp`, or the key itself) and compared without case, so `w`, `W` and `KeyW` are one key.
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::cpu::Keypad;
+
+use crate::loader;
+
+// The CHIP-8 keys as laid out on the keypad, row by row, for drawing it on screen.
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+const STANDARD_KEYS: [[&str; 4]; 4] = [["1", "2", "3", "4"], ["Q", "W", "E", "R"], ["A", "S", "D", "F"], ["Z", "X", "C", "V"]];
+
+// PONG and PONG2 move the left paddle with 1/4 and the right one with C/D, which the standard layout scatters.
+const BUILT_IN: &str = "
+rom b232ef880bd6060fb45fa6effed7edf0ae95670e
+key W 1
+key S 4
+key Up C
+key Down D
+
+rom a60611339661e3ab2d8af024ad1da5880a6f8665
+key W 1
+key S 4
+key Up C
+key Down D
+";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyMap {
+    bindings: BTreeMap<String, u8>,
+}
+
+// The standard layout with the changes from key map files, overall and per ROM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMapConfig {
+    default: KeyMap,
+    roms: BTreeMap<String, KeyMap>,
+}
+
+// `KeyW`, `Digit1` and `ArrowUp` become `W`, `1` and `UP`, a lone space `SPACE`, everything else upper case.
+pub fn normalize_key_name(name: &str) -> String {
+    let name = match name {
+        " " => "Space",
+        _ => name
+    };
+    let name = ["Key", "Digit", "Arrow"]
+        .iter()
+        .filter_map(|prefix| name.strip_prefix(prefix))
+        .find(|rest| !rest.is_empty())
+        .unwrap_or(name);
+
+    name.to_uppercase()
+}
+
+impl KeyMap {
+    // No keys bound at all.
+    pub fn new() -> KeyMap {
+        KeyMap::default()
+    }
+
+    pub fn standard() -> KeyMap {
+        let mut key_map = KeyMap::new();
+
+        for (host_row, keypad_row) in STANDARD_KEYS.iter().zip(KEYPAD_LAYOUT.iter()) {
+            for (host_key, &key) in host_row.iter().zip(keypad_row) {
+                key_map.bind(host_key, key);
+            }
+        }
+
+        key_map
+    }
+
+    // Replaces what the host key pressed before. Several host keys can press the same CHIP-8 key.
+    pub fn bind(&mut self, host_key: &str, key: u8) {
+        self.bindings.insert(normalize_key_name(host_key), key & 0xF);
+    }
+
+    pub fn unbind(&mut self, host_key: &str) {
+        self.bindings.remove(&normalize_key_name(host_key));
+    }
+
+    pub fn key_for(&self, host_key: &str) -> Option<u8> {
+        self.bindings.get(&normalize_key_name(host_key)).cloned()
+    }
+
+    // The host keys pressing a CHIP-8 key, to label an on-screen keypad.
+    pub fn host_keys_for(&self, key: u8) -> Vec<&str> {
+        self.bindings.iter().filter(|(_, &bound)| bound == key).map(|(host_key, _)| host_key.as_str()).collect()
+    }
+
+    // Returns false for host keys without a binding, which the frontend is free to use otherwise.
+    pub fn set_key(&self, keypad: &mut Keypad, host_key: &str, pressed: bool) -> bool {
+        match self.key_for(host_key) {
+            Some(key) => {
+                keypad.keys[key as usize] = pressed;
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl KeyMapConfig {
+    // The standard layout with the overrides for the ROMs in the roms directory.
+    pub fn built_in() -> KeyMapConfig {
+        let mut config = KeyMapConfig { default: KeyMap::standard(), roms: BTreeMap::new() };
+
+        config.read(BUILT_IN).expect("the built-in key maps are valid");
+        config
+    }
+
+    // The built-in key maps with the entries of a key map file on top.
+    pub fn load(path: &str) -> Result<KeyMapConfig, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+
+        KeyMapConfig::parse(&text).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse(text: &str) -> Result<KeyMapConfig, String> {
+        let mut config = KeyMapConfig::built_in();
+
+        config.read(text)?;
+        Ok(config)
+    }
+
+    fn read(&mut self, text: &str) -> Result<(), String> {
+        let mut rom_hash: Option<String> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("line {}: can not read {}", index + 1, line.trim());
+
+            match fields.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["rom", hash] => rom_hash = Some(hash.to_lowercase()),
+                ["key", host_key, key] => {
+                    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or_else(invalid)?;
+                    let key_map = match &rom_hash {
+                        Some(hash) => self.roms.entry(hash.clone()).or_default(),
+                        None => &mut self.default
+                    };
+
+                    key_map.bind(host_key, key);
+                }
+                _ => return Err(invalid())
+            }
+        }
+
+        Ok(())
+    }
+
+    // The default key map with the overrides for the ROM with this SHA-1.
+    pub fn for_hash(&self, hash: &str) -> KeyMap {
+        let mut key_map = self.default.clone();
+
+        if let Some(overrides) = self.roms.get(&hash.to_lowercase()) {
+            key_map.bindings.extend(overrides.bindings.clone());
+        }
+
+        key_map
+    }
+
+    pub fn for_rom(&self, rom: &[u8]) -> KeyMap {
+        self.for_hash(&loader::rom_hash(rom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_key_name, KeyMap, KeyMapConfig};
+    use crate::cpu::Chip8;
+    use crate::loader;
+
+    #[test]
+    fn standard_layout_presses_keypad_keys() {
+        let key_map = KeyMap::standard();
+        let mut chip8 = Chip8::initialize();
+
+        assert!(key_map.set_key(&mut chip8.keypad, "KeyV", true));
+        assert!(key_map.set_key(&mut chip8.keypad, "x", true));
+        assert!(!key_map.set_key(&mut chip8.keypad, "Enter", true));
+        assert_eq!(chip8.keypad.keys.iter().filter(|&&pressed| pressed).count(), 2);
+        assert!(chip8.keypad.keys[0xF] && chip8.keypad.keys[0x0]);
+
+        assert_eq!(key_map.key_for("Digit4"), Some(0xC));
+        assert_eq!(key_map.host_keys_for(0xD), vec!["R"]);
+        assert_eq!(normalize_key_name("ArrowUp"), "UP");
+        assert_eq!(normalize_key_name("Key"), "KEY");
+        assert_eq!(normalize_key_name(" "), "SPACE");
+    }
+
+    #[test]
+    fn roms_override_the_default_layout() {
+        let config = KeyMapConfig::parse("# mine\nkey Space 5\nrom 0011AA\nkey j 4\n").unwrap();
+        let pong = config.for_rom(&loader::read_rom_file("roms/PONG").unwrap());
+        let other = config.for_hash("0011aa");
+
+        assert_eq!(pong.key_for("ArrowDown"), Some(0xD));
+        assert_eq!(pong.key_for("w"), Some(0x1));
+        assert_eq!(pong.key_for("Space"), Some(0x5));
+        assert_eq!(other.key_for("J"), Some(0x4));
+        assert_eq!(other.key_for("W"), Some(0x5));
+        assert_eq!(config.for_hash("ffff").key_for("J"), None);
+        assert!(KeyMapConfig::parse("key Q 10").unwrap_err().starts_with("line 1"));
+    }
+}
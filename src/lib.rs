@@ -1,5 +1,65 @@
+// The core builds without std for microcontrollers, everything that needs a host is behind the std feature.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "wasm")]
 #[macro_use]
 extern crate lazy_static;
 
 pub mod cpu;
-pub mod wasm_mediator;
\ No newline at end of file
+pub mod decode_cache;
+pub mod error;
+pub mod frontend;
+pub mod instruction;
+pub mod quirks;
+pub mod rng;
+
+#[cfg(feature = "std")]
+pub mod backends;
+#[cfg(feature = "batch")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod dap;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod environment;
+#[cfg(feature = "std")]
+pub mod gamepad;
+#[cfg(feature = "std")]
+pub mod gdb_stub;
+#[cfg(feature = "std")]
+pub mod keymap;
+#[cfg(feature = "std")]
+pub mod loader;
+#[cfg(feature = "std")]
+pub mod memory_watch;
+#[cfg(feature = "std")]
+pub mod netplay;
+#[cfg(feature = "std")]
+pub mod players;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "std")]
+pub mod rom_database;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "std")]
+pub mod threaded;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod trace_diff;
+#[cfg(feature = "wasm")]
+pub mod wasm_mediator;
+
+#[cfg(all(test, feature = "std"))]
+mod conformance;
+
+#[cfg(all(test, feature = "std"))]
+mod reference_interpreter;
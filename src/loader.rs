@@ -0,0 +1,163 @@
+// Reading ROMs from files and identifying them, for hosts with std.
+use std::fs::File;
+use std::io::prelude::*;
+
+use sha1::{Digest, Sha1};
+
+use crate::cpu::Chip8;
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+use crate::rom_database::{RomDatabase, RomInfo};
+
+const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+
+// The machine settings the tools share: --quirks, --cycles-per-frame and --database on the command line,
+// "quirks", "cyclesPerFrame" and "database" in a debugger launch configuration.
+pub struct MachineOptions {
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<u32>,
+    pub database: RomDatabase,
+}
+
+pub fn read_rom_file(path: &str) -> std::io::Result<Vec<u8>> {
+    let mut rom = Vec::new();
+
+    File::open(path)?.read_to_end(&mut rom)?;
+
+    Ok(rom)
+}
+
+// SHA-1 of a ROM in lowercase hexadecimal, the key cheat files and ROM databases use.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Loads a ROM into a new machine with the quirks the built-in database knows for it, and returns the cycles per frame
+// it plays best with, 10 for ROMs it does not know.
+pub fn load_chip8(rom: &[u8]) -> Result<(Chip8, u32), Chip8Error> {
+    let mut chip8 = Chip8::initialize();
+    let database = RomDatabase::built_in();
+    let info = chip8.load_rom_from_database(rom, &database)?;
+
+    Ok((chip8, info.map_or(DEFAULT_CYCLES_PER_FRAME, |info| info.cycles_per_frame)))
+}
+
+// Like load_chip8, for tools that pick the cycles per frame themselves.
+pub fn load_chip8_from_file(path: &str) -> Result<Chip8, Box<dyn std::error::Error>> {
+    let (chip8, _) = load_chip8(&read_rom_file(path)?)?;
+
+    Ok(chip8)
+}
+
+impl Default for MachineOptions {
+    fn default() -> MachineOptions {
+        MachineOptions::new()
+    }
+}
+
+impl MachineOptions {
+    pub fn new() -> MachineOptions {
+        MachineOptions { quirks: None, cycles_per_frame: None, database: RomDatabase::built_in() }
+    }
+
+    pub fn set_quirks(&mut self, name: &str) -> Result<(), String> {
+        self.quirks = Some(Quirks::by_name(name).ok_or(format!("unknown quirks profile {}", name))?);
+
+        Ok(())
+    }
+
+    // Adds the ROMs of a database file to the built-in ones.
+    pub fn add_database(&mut self, path: &str) -> Result<(), String> {
+        self.database.extend(RomDatabase::load(path)?);
+
+        Ok(())
+    }
+
+    // Takes one of the shared command line options, reading its value with `value`.
+    // Returns false for arguments that are not, leaving them to the tool.
+    pub fn parse_argument(&mut self, argument: &str, mut value: impl FnMut() -> Result<String, String>) -> Result<bool, String> {
+        match argument {
+            "--quirks" => self.set_quirks(&value()?)?,
+            "--cycles-per-frame" => self.cycles_per_frame = Some(value()?.parse().map_err(|_| "--cycles-per-frame needs a number")?),
+            "--database" => self.add_database(&value()?)?,
+            _ => return Ok(false)
+        }
+
+        Ok(true)
+    }
+
+    // Loads a ROM into a new machine and returns it with the cycles per frame to run it at.
+    // Settings given here win over the ones the database knows for the ROM.
+    pub fn load(&self, rom: &[u8]) -> Result<(Chip8, u32), String> {
+        let mut chip8 = Chip8::initialize();
+        let info = chip8.load_rom_from_database(rom, &self.database).map_err(|error| error.to_string())?;
+        let cycles_per_frame = self.cycles_per_frame.or(info.map(|info| info.cycles_per_frame)).unwrap_or(DEFAULT_CYCLES_PER_FRAME);
+
+        if let Some(quirks) = self.quirks {
+            chip8.quirks = quirks;
+        }
+
+        Ok((chip8, cycles_per_frame))
+    }
+}
+
+impl Chip8 {
+    // Loads a ROM and switches to the quirks the database recommends for it, if it knows the ROM.
+    pub fn load_rom_from_database<'a>(&mut self, rom: &[u8], database: &'a RomDatabase) -> Result<Option<&'a RomInfo>, Chip8Error> {
+        self.load_rom(rom)?;
+
+        let info = database.identify(rom);
+
+        if let Some(info) = info {
+            self.quirks = info.quirks;
+        }
+
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_chip8, read_rom_file, MachineOptions};
+    use crate::quirks::Quirks;
+    use crate::rom_database::RomDatabase;
+
+    #[test]
+    fn plain_loads_pick_up_database_settings() {
+        let database = RomDatabase::built_in();
+
+        for name in &["BLINKY", "SYZYGY", "INVADERS"] {
+            let rom = read_rom_file(&format!("roms/{}", name)).unwrap();
+            let info = database.identify(&rom).unwrap();
+            let (chip8, cycles_per_frame) = load_chip8(&rom).unwrap();
+
+            assert_eq!((chip8.quirks, cycles_per_frame), (info.quirks, info.cycles_per_frame), "{}", name);
+        }
+
+        assert_eq!(load_chip8(&read_rom_file("roms/BLINKY").unwrap()).unwrap().1, 20);
+        assert_eq!(load_chip8(&[0x12, 0x00]).unwrap().1, 10);
+    }
+
+    #[test]
+    fn options_given_win_over_the_database() {
+        let rom = read_rom_file("roms/BLINKY").unwrap();
+        let mut options = MachineOptions::new();
+        let mut values = vec!["7".to_string(), "vip".to_string()].into_iter();
+
+        options.database.extend(
+            RomDatabase::parse_json(r#"[{"hash": "d40abc54374e4343639f993e897e00904ddf85d9", "platform": "superchip", "cycles_per_frame": 30}]"#).unwrap(),
+        );
+
+        let (chip8, cycles_per_frame) = options.load(&rom).unwrap();
+
+        assert_eq!((chip8.quirks, cycles_per_frame), (Quirks::super_chip(), 30));
+        assert_eq!(options.parse_argument("--cycles-per-frame", || values.next().ok_or("no value".to_string())), Ok(true));
+        assert_eq!(options.parse_argument("--quirks", || values.next().ok_or("no value".to_string())), Ok(true));
+        assert_eq!(options.parse_argument("--frames", || unreachable!()), Ok(false));
+        assert!(options.parse_argument("--quirks", || Ok("amiga".to_string())).is_err());
+
+        let (chip8, cycles_per_frame) = options.load(&rom).unwrap();
+
+        assert_eq!((chip8.quirks, cycles_per_frame), (Quirks::cosmac_vip(), 7));
+    }
+}
@@ -0,0 +1,60 @@
+// Finds the parts of memory that changed since the last time it was asked, by comparing against a snapshot.
+// Catches every write, whether from FX33/FX55, a loaded ROM or a debugger poke, without hooks in the core.
+use std::ops::Range;
+
+pub struct DirtyTracker {
+    snapshot: Vec<u8>,
+}
+
+impl DirtyTracker {
+    // Starts out with everything in `ram` considered seen.
+    pub fn new(ram: &[u8]) -> DirtyTracker {
+        DirtyTracker { snapshot: ram.to_vec() }
+    }
+
+    // Runs of bytes that differ from the previous call, which become the new snapshot.
+    pub fn take_dirty_ranges(&mut self, ram: &[u8]) -> Vec<Range<usize>> {
+        if self.snapshot.len() != ram.len() {
+            self.snapshot = ram.to_vec();
+            return std::iter::once(0..ram.len()).collect();
+        }
+
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+
+        for (address, (seen, &current)) in self.snapshot.iter_mut().zip(ram).enumerate() {
+            if *seen == current {
+                continue;
+            }
+
+            *seen = current;
+
+            match ranges.last_mut() {
+                Some(range) if range.end == address => range.end += 1,
+                _ => ranges.push(address..address + 1)
+            }
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirtyTracker;
+
+    #[test]
+    fn reports_changed_runs_once() {
+        let mut ram = [0u8; 16];
+        let mut tracker = DirtyTracker::new(&ram);
+
+        assert!(tracker.take_dirty_ranges(&ram).is_empty());
+
+        ram[2] = 1;
+        ram[3] = 1;
+        ram[9] = 5;
+
+        assert_eq!(tracker.take_dirty_ranges(&ram), vec![2..4, 9..10]);
+        assert!(tracker.take_dirty_ranges(&ram).is_empty());
+        assert_eq!(tracker.take_dirty_ranges(&[0u8; 4]), vec![0..4]);
+    }
+}
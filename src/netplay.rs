@@ -0,0 +1,681 @@
+// Two players on two machines running the same ROM, exchanging their keys over UDP.
+//
+// Every frame runs at once with the keys the other player is guessed to hold, the last ones received. When their
+// real keys for a frame arrive and differ from the guess, the session rolls back: it restores the snapshot taken
+// before that frame and runs the frames since again with what is known now. Both machines start from the same seed
+// and run the same keys, so they stay in step; every CHECKSUM_INTERVAL frames they compare the state_hash of a frame
+// both have the real keys for, and a difference marks the session as desynced.
+//
+// A spectator asks a player for the keys of both players once they are known for sure and runs those, without
+// guessing or rolling back. Each request is answered once, so a spectator keeps asking, and one that stops is
+// forgotten.
+//
+// Keys are masks, bit 0 for key 0, and the machine sees the keys of both players together.
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::cpu::Chip8;
+use crate::error::Chip8Error;
+
+// Frames run ahead of the other player's keys at most, after which advance waits for them.
+pub const MAX_PREDICTION: u32 = 8;
+
+pub const CHECKSUM_INTERVAL: u32 = 30;
+
+// Keys sent at most in one message, of those the other side has not confirmed yet.
+const MAX_INPUTS_PER_MESSAGE: usize = 64;
+
+// Frames of both players' keys sent to a spectator at most in one message.
+const MAX_SPECTATOR_FRAMES_PER_MESSAGE: usize = 256;
+
+// Spectators a player serves at most, requests from others are ignored.
+const MAX_SPECTATORS: usize = 8;
+
+// Advances a spectator's request waits for keys to send before it is dropped.
+const SPECTATOR_TIMEOUT: u32 = 120;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // The keys of a player from a frame on, with how many frames of the receiver's keys the sender has.
+    Inputs { start: u32, inputs: Vec<u16>, ack: u32 },
+    // The state hash before a frame.
+    Checksum { frame: u32, hash: u64 },
+    // From spectators, asking for both players' keys from a frame on.
+    Spectate { next_frame: u32 },
+    Confirmed { start: u32, inputs: Vec<[u16; 2]> },
+}
+
+pub struct RollbackSession {
+    chip8: Chip8,
+    cycles_per_frame: u32,
+    local_player: usize,
+    // The frames run so far.
+    frame: u32,
+    // The keys of each player by frame, as far as they are known for sure.
+    inputs: [Vec<u16>; 2],
+    // The guesses at the other player's keys for frames they are not known for yet.
+    predicted: BTreeMap<u32, u16>,
+    // The state before each frame from snapshot_start on.
+    snapshots: VecDeque<Chip8>,
+    snapshot_start: u32,
+    rollback_from: Option<u32>,
+    // How many frames of local keys the other player has.
+    remote_ack: u32,
+    next_checksum: u32,
+    local_checksums: BTreeMap<u32, u64>,
+    remote_checksums: BTreeMap<u32, u64>,
+    outbox: Vec<Message>,
+    pub desync: Option<u32>,
+    pub rollbacks: u32,
+    pub frames_resimulated: u64,
+    pub error: Option<Chip8Error>,
+}
+
+pub struct Spectator {
+    chip8: Chip8,
+    cycles_per_frame: u32,
+    inputs: Vec<[u16; 2]>,
+    frame: u32,
+    pub error: Option<Chip8Error>,
+}
+
+fn run_frame(chip8: &mut Chip8, cycles_per_frame: u32, inputs: [u16; 2]) -> Option<Chip8Error> {
+    let keys = inputs[0] | inputs[1];
+
+    for (key, pressed) in chip8.keypad.keys.iter_mut().enumerate() {
+        *pressed = keys & (1 << key) != 0;
+    }
+
+    chip8.emulate_frame(cycles_per_frame).err()
+}
+
+impl RollbackSession {
+    // Both players make their session from the same machine, with the ROM loaded and the same seed.
+    pub fn new(chip8: Chip8, cycles_per_frame: u32, local_player: usize) -> RollbackSession {
+        assert!(local_player < 2, "players are 0 and 1");
+
+        RollbackSession {
+            chip8,
+            cycles_per_frame,
+            local_player,
+            frame: 0,
+            inputs: [Vec::new(), Vec::new()],
+            predicted: BTreeMap::new(),
+            snapshots: VecDeque::new(),
+            snapshot_start: 0,
+            rollback_from: None,
+            remote_ack: 0,
+            next_checksum: CHECKSUM_INTERVAL,
+            local_checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            outbox: Vec::new(),
+            desync: None,
+            rollbacks: 0,
+            frames_resimulated: 0,
+            error: None,
+        }
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // The frames both players' keys are known for.
+    pub fn confirmed_frames(&self) -> u32 {
+        self.inputs[0].len().min(self.inputs[1].len()) as u32
+    }
+
+    // Both players' keys for the confirmed frames from a frame on, for spectators.
+    pub fn confirmed_inputs(&self, start: u32, limit: usize) -> Vec<[u16; 2]> {
+        (start..self.confirmed_frames()).take(limit).map(|frame| [self.inputs[0][frame as usize], self.inputs[1][frame as usize]]).collect()
+    }
+
+    // The messages for the other player since the last call.
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub fn receive(&mut self, message: Message) {
+        match message {
+            Message::Inputs { start, inputs, ack } => {
+                self.remote_ack = self.remote_ack.max(ack);
+                self.receive_inputs(start, &inputs);
+            }
+            Message::Checksum { frame, hash } => {
+                self.remote_checksums.insert(frame, hash);
+                self.compare_checksums();
+            }
+            Message::Spectate { .. } | Message::Confirmed { .. } => {}
+        }
+    }
+
+    // Runs a frame with the local player's keys, unless it would run more than MAX_PREDICTION frames ahead of the
+    // other player's keys. Returns whether it ran.
+    pub fn advance(&mut self, local_input: u16) -> bool {
+        if let Some(frame) = self.rollback_from.take() {
+            self.roll_back(frame);
+        }
+
+        self.send_checksums();
+        self.drop_confirmed_snapshots();
+
+        let ran = self.frame - self.confirmed_frames() < MAX_PREDICTION;
+
+        if ran {
+            self.inputs[self.local_player].push(local_input);
+            self.snapshots.push_back(self.chip8.clone());
+            self.simulate(self.frame);
+            self.frame += 1;
+        }
+
+        // Sent even when waiting, in case the last message was lost.
+        let start = self.remote_ack as usize;
+        let local_inputs = &self.inputs[self.local_player];
+        let end = local_inputs.len().min(start + MAX_INPUTS_PER_MESSAGE);
+
+        self.outbox.push(Message::Inputs {
+            start: start as u32,
+            inputs: local_inputs[start.min(end)..end].to_vec(),
+            ack: self.inputs[1 - self.local_player].len() as u32,
+        });
+
+        ran
+    }
+
+    fn receive_inputs(&mut self, start: u32, inputs: &[u16]) {
+        let remote = 1 - self.local_player;
+        let known = self.inputs[remote].len() as u32;
+
+        if start > known {
+            return;
+        }
+
+        for (frame, &input) in (start..).zip(inputs).skip((known - start) as usize) {
+            if let Some(predicted) = self.predicted.remove(&frame) {
+                if predicted != input {
+                    self.rollback_from = Some(self.rollback_from.map_or(frame, |from| from.min(frame)));
+                }
+            }
+
+            self.inputs[remote].push(input);
+        }
+    }
+
+    // Runs a frame with the keys known or guessed, remembering guesses.
+    fn simulate(&mut self, frame: u32) {
+        let remote = 1 - self.local_player;
+        let remote_input = match self.inputs[remote].get(frame as usize) {
+            Some(&input) => input,
+            None => {
+                let guess = self.inputs[remote].last().cloned().unwrap_or(0);
+
+                self.predicted.insert(frame, guess);
+                guess
+            }
+        };
+        let mut inputs = [0; 2];
+
+        inputs[self.local_player] = self.inputs[self.local_player][frame as usize];
+        inputs[remote] = remote_input;
+        self.error = run_frame(&mut self.chip8, self.cycles_per_frame, inputs);
+    }
+
+    fn roll_back(&mut self, from: u32) {
+        let index = (from - self.snapshot_start) as usize;
+
+        self.chip8 = self.snapshots[index].clone();
+        self.snapshots.truncate(index);
+        self.rollbacks += 1;
+
+        for frame in from..self.frame {
+            self.predicted.remove(&frame);
+            self.snapshots.push_back(self.chip8.clone());
+            self.simulate(frame);
+            self.frames_resimulated += 1;
+        }
+    }
+
+    // Hashes the state before checksum frames once every frame before them has run with the real keys.
+    fn send_checksums(&mut self) {
+        while self.next_checksum <= self.confirmed_frames().min(self.frame) {
+            let frame = self.next_checksum;
+            let hash = match frame == self.frame {
+                true => self.chip8.state_hash(),
+                false => self.snapshots[(frame - self.snapshot_start) as usize].state_hash()
+            };
+
+            self.local_checksums.insert(frame, hash);
+            self.outbox.push(Message::Checksum { frame, hash });
+            self.next_checksum += CHECKSUM_INTERVAL;
+        }
+
+        self.compare_checksums();
+    }
+
+    fn compare_checksums(&mut self) {
+        for (frame, hash) in &self.remote_checksums {
+            match self.local_checksums.get(frame) {
+                Some(local) if local != hash => {
+                    self.desync = Some(self.desync.map_or(*frame, |desync| desync.min(*frame)));
+                }
+                _ => {}
+            }
+        }
+
+        let compared: Vec<u32> = self.remote_checksums.keys().filter(|frame| self.local_checksums.contains_key(frame)).cloned().collect();
+
+        for frame in compared {
+            self.remote_checksums.remove(&frame);
+            self.local_checksums.remove(&frame);
+        }
+    }
+
+    // Frames before the confirmed ones never roll back.
+    fn drop_confirmed_snapshots(&mut self) {
+        while self.snapshot_start < self.confirmed_frames() && !self.snapshots.is_empty() {
+            self.snapshots.pop_front();
+            self.snapshot_start += 1;
+        }
+    }
+}
+
+impl Spectator {
+    pub fn new(chip8: Chip8, cycles_per_frame: u32) -> Spectator {
+        Spectator { chip8, cycles_per_frame, inputs: Vec::new(), frame: 0, error: None }
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Asks for the keys after those received.
+    pub fn request(&self) -> Message {
+        Message::Spectate { next_frame: self.inputs.len() as u32 }
+    }
+
+    pub fn receive(&mut self, message: Message) {
+        if let Message::Confirmed { start, inputs } = message {
+            let known = self.inputs.len();
+
+            if start as usize <= known {
+                self.inputs.extend(inputs.into_iter().skip(known - start as usize));
+            }
+        }
+    }
+
+    // Runs the frames received so far and returns how many ran.
+    pub fn catch_up(&mut self) -> u32 {
+        let start = self.frame;
+
+        while (self.frame as usize) < self.inputs.len() {
+            self.error = run_frame(&mut self.chip8, self.cycles_per_frame, self.inputs[self.frame as usize]);
+            self.frame += 1;
+        }
+
+        self.frame - start
+    }
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match self {
+            Message::Inputs { start, inputs, ack } => {
+                bytes.push(0);
+                bytes.extend_from_slice(&start.to_be_bytes());
+                bytes.extend_from_slice(&ack.to_be_bytes());
+                inputs.iter().for_each(|input| bytes.extend_from_slice(&input.to_be_bytes()));
+            }
+            Message::Checksum { frame, hash } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&frame.to_be_bytes());
+                bytes.extend_from_slice(&hash.to_be_bytes());
+            }
+            Message::Spectate { next_frame } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&next_frame.to_be_bytes());
+            }
+            Message::Confirmed { start, inputs } => {
+                bytes.push(3);
+                bytes.extend_from_slice(&start.to_be_bytes());
+                inputs.iter().flatten().for_each(|input| bytes.extend_from_slice(&input.to_be_bytes()));
+            }
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Message, String> {
+        let invalid = || format!("can not read a message of {} bytes", bytes.len());
+        let u32_at = |at: usize| bytes.get(at..at + 4).map(|field| u32::from_be_bytes([field[0], field[1], field[2], field[3]])).ok_or_else(invalid);
+        let u16s_from = |at: usize| match bytes.len() >= at && (bytes.len() - at).is_multiple_of(2) {
+            true => Ok(bytes[at..].chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect::<Vec<u16>>()),
+            false => Err(invalid())
+        };
+
+        match bytes.first() {
+            Some(0) => Ok(Message::Inputs { start: u32_at(1)?, ack: u32_at(5)?, inputs: u16s_from(9)? }),
+            Some(1) if bytes.len() == 13 => {
+                let mut hash = [0; 8];
+
+                hash.copy_from_slice(&bytes[5..13]);
+                Ok(Message::Checksum { frame: u32_at(1)?, hash: u64::from_be_bytes(hash) })
+            }
+            Some(2) => Ok(Message::Spectate { next_frame: u32_at(1)? }),
+            Some(3) => {
+                let inputs = u16s_from(5)?;
+
+                match inputs.len() % 2 {
+                    0 => Ok(Message::Confirmed { start: u32_at(1)?, inputs: inputs.chunks(2).map(|pair| [pair[0], pair[1]]).collect() }),
+                    _ => Err(invalid())
+                }
+            }
+            _ => Err(invalid())
+        }
+    }
+}
+
+// A player's session on a UDP socket, also serving spectators.
+pub struct NetplayPeer {
+    socket: UdpSocket,
+    remote: SocketAddr,
+    spectators: BTreeMap<SocketAddr, SpectatorRequest>,
+    pub session: RollbackSession,
+}
+
+// A spectator's request not answered yet.
+struct SpectatorRequest {
+    next_frame: u32,
+    // Advances since it arrived.
+    waiting: u32,
+}
+
+// A spectator's machine on a UDP socket, watching through one of the players.
+pub struct SpectatorPeer {
+    socket: UdpSocket,
+    host: SocketAddr,
+    pub spectator: Spectator,
+}
+
+fn bind_nonblocking(local: &str) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(local)?;
+
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+// Reads every datagram waiting on a socket, skipping those that are not messages.
+fn receive_all(socket: &UdpSocket) -> io::Result<Vec<(SocketAddr, Message)>> {
+    let mut buffer = [0; 2048];
+    let mut messages = Vec::new();
+
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((length, from)) => {
+                if let Ok(message) = Message::decode(&buffer[..length]) {
+                    messages.push((from, message));
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(messages),
+            Err(error) => return Err(error)
+        }
+    }
+}
+
+impl NetplayPeer {
+    pub fn bind(local: &str, remote: SocketAddr, session: RollbackSession) -> io::Result<NetplayPeer> {
+        Ok(NetplayPeer { socket: bind_nonblocking(local)?, remote, spectators: BTreeMap::new(), session })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Takes in what arrived, runs a frame if the other player is not too far behind, and sends what is new.
+    pub fn advance(&mut self, local_input: u16) -> io::Result<bool> {
+        for (from, message) in receive_all(&self.socket)? {
+            match message {
+                Message::Spectate { next_frame } if self.spectators.contains_key(&from) || self.spectators.len() < MAX_SPECTATORS => {
+                    self.spectators.insert(from, SpectatorRequest { next_frame, waiting: 0 });
+                }
+                Message::Spectate { .. } => {}
+                _ if from == self.remote => self.session.receive(message),
+                _ => {}
+            }
+        }
+
+        let ran = self.session.advance(local_input);
+
+        for message in self.session.take_messages() {
+            self.send(&message, self.remote)?;
+        }
+
+        let session = &self.session;
+        let mut replies = Vec::new();
+
+        self.spectators.retain(|&spectator, request| {
+            let inputs = session.confirmed_inputs(request.next_frame, MAX_SPECTATOR_FRAMES_PER_MESSAGE);
+
+            match inputs.is_empty() {
+                true => {
+                    request.waiting += 1;
+                    request.waiting < SPECTATOR_TIMEOUT
+                }
+                false => {
+                    replies.push((spectator, Message::Confirmed { start: request.next_frame, inputs }));
+                    false
+                }
+            }
+        });
+
+        for (spectator, message) in replies {
+            self.send(&message, spectator)?;
+        }
+
+        Ok(ran)
+    }
+
+    fn send(&self, message: &Message, to: SocketAddr) -> io::Result<()> {
+        match self.socket.send_to(&message.encode(), to) {
+            // A peer that went away refuses datagrams on some systems, which is no reason to stop.
+            Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            result => result.map(|_| ())
+        }
+    }
+}
+
+impl SpectatorPeer {
+    pub fn bind(local: &str, host: SocketAddr, spectator: Spectator) -> io::Result<SpectatorPeer> {
+        Ok(SpectatorPeer { socket: bind_nonblocking(local)?, host, spectator })
+    }
+
+    // Takes in the keys that arrived, runs them and asks for more. Returns the frames run.
+    pub fn poll(&mut self) -> io::Result<u32> {
+        for (from, message) in receive_all(&self.socket)? {
+            if from == self.host {
+                self.spectator.receive(message);
+            }
+        }
+
+        let frames = self.spectator.catch_up();
+
+        self.socket.send_to(&self.spectator.request().encode(), self.host)?;
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::{Message, NetplayPeer, RollbackSession, Spectator, SpectatorPeer, MAX_SPECTATORS, SPECTATOR_TIMEOUT};
+    use crate::cpu::Chip8;
+    use crate::loader;
+
+    fn pong2() -> Chip8 {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.seed_rng(2);
+        chip8.load_rom(&loader::read_rom_file("roms/PONG2").unwrap()).unwrap();
+        chip8
+    }
+
+    // Player 0 moves the left paddle with 1 and 4, player 1 the right one with C and D.
+    fn keys(player: usize, frame: u32) -> u16 {
+        let phase = (frame / (7 + 5 * player as u32)) % 3;
+
+        match (player, phase) {
+            (0, 0) => 1 << 0x1,
+            (0, 1) => 1 << 0x4,
+            (1, 0) => 1 << 0xC,
+            (1, 1) => 1 << 0xD,
+            _ => 0
+        }
+    }
+
+    #[test]
+    fn rollback_with_late_inputs_matches_running_with_all_inputs() {
+        let mut reference = pong2();
+        let mut players = [RollbackSession::new(pong2(), 10, 0), RollbackSession::new(pong2(), 10, 1)];
+        // Messages arrive 3 advances after they are sent, except that every tenth one is lost.
+        let mut in_flight: Vec<(u32, usize, Message)> = Vec::new();
+
+        for step in 0..400 {
+            for (player, session) in players.iter_mut().enumerate() {
+                let frame = session.frame();
+
+                session.advance(keys(player, frame));
+
+                for (index, message) in session.take_messages().into_iter().enumerate() {
+                    if (step + index) % 10 != 9 {
+                        in_flight.push((step as u32 + 3, 1 - player, message));
+                    }
+                }
+            }
+
+            let (arrived, late): (Vec<_>, Vec<_>) = in_flight.into_iter().partition(|(due, _, _)| *due <= step as u32);
+
+            in_flight = late;
+            arrived.into_iter().for_each(|(_, to, message)| players[to].receive(message));
+        }
+
+        // The oldest snapshot each player keeps is from before a frame both players' keys were known for.
+        let mut order = [0, 1];
+        let mut frame = 0;
+
+        order.sort_by_key(|&player| players[player].snapshot_start);
+
+        for &player in &order {
+            while frame < players[player].snapshot_start {
+                super::run_frame(&mut reference, 10, [keys(0, frame), keys(1, frame)]);
+                frame += 1;
+            }
+
+            assert_eq!(players[player].snapshots[0].state_hash(), reference.state_hash(), "player {}", player);
+        }
+
+        assert!(players.iter().all(|player| player.rollbacks > 0 && player.desync.is_none()));
+        assert!(frame > 300);
+    }
+
+    #[test]
+    fn peers_and_a_spectator_over_localhost() {
+        let mut first = NetplayPeer::bind("127.0.0.1:0", "127.0.0.1:9".parse().unwrap(), RollbackSession::new(pong2(), 10, 0)).unwrap();
+        let mut second = NetplayPeer::bind("127.0.0.1:0", first.local_addr().unwrap(), RollbackSession::new(pong2(), 10, 1)).unwrap();
+        let mut watcher = SpectatorPeer::bind("127.0.0.1:0", first.local_addr().unwrap(), Spectator::new(pong2(), 10)).unwrap();
+
+        first.remote = second.local_addr().unwrap();
+
+        for _ in 0..2000 {
+            if first.session.frame() >= 200 && second.session.frame() >= 200 && watcher.spectator.frame() >= 150 {
+                break;
+            }
+
+            if first.session.frame() < 200 {
+                first.advance(keys(0, first.session.frame())).unwrap();
+            }
+
+            if second.session.frame() < 200 {
+                second.advance(keys(1, second.session.frame())).unwrap();
+            }
+
+            watcher.poll().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        // Keep exchanging until both sides have the other's keys for every frame.
+        for _ in 0..200 {
+            first.advance(0).unwrap();
+            second.advance(0).unwrap();
+            watcher.poll().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        // The players ran their keys for the first 200 frames and nothing after, the spectator has to end up where
+        // they were at the same frame.
+        let mut reference = pong2();
+
+        for frame in 0..=first.session.snapshot_start.max(watcher.spectator.frame()) {
+            if frame == first.session.snapshot_start {
+                assert_eq!(first.session.snapshots[0].state_hash(), reference.state_hash());
+            }
+            if frame == watcher.spectator.frame() {
+                assert_eq!(watcher.spectator.chip8().state_hash(), reference.state_hash());
+            }
+
+            match frame < 200 {
+                true => super::run_frame(&mut reference, 10, [keys(0, frame), keys(1, frame)]),
+                false => super::run_frame(&mut reference, 10, [0, 0])
+            };
+        }
+
+        // A spectator that stops asking is forgotten, and a player serves MAX_SPECTATORS at most.
+        let crowd: Vec<UdpSocket> = (0..MAX_SPECTATORS + 1).map(|_| UdpSocket::bind("127.0.0.1:0").unwrap()).collect();
+
+        for socket in &crowd {
+            socket.send_to(&Message::Spectate { next_frame: u32::MAX }.encode(), second.local_addr().unwrap()).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        second.advance(0).unwrap();
+
+        for _ in 0..SPECTATOR_TIMEOUT {
+            first.advance(0).unwrap();
+        }
+
+        let mut desynced = RollbackSession::new(pong2(), 10, 0);
+        let mut in_step = pong2();
+
+        desynced.chip8.seed_rng(3);
+
+        for frame in 0..31 {
+            desynced.advance(0);
+            desynced.receive(Message::Inputs { start: frame, inputs: vec![0], ack: 0 });
+        }
+
+        for _ in 0..30 {
+            super::run_frame(&mut in_step, 10, [0, 0]);
+        }
+
+        desynced.advance(0);
+        desynced.receive(Message::Checksum { frame: 30, hash: in_step.state_hash() });
+
+        assert_eq!(first.session.desync, None);
+        assert_eq!(second.session.desync, None);
+        assert!(watcher.spectator.frame() >= 200);
+        assert!(first.spectators.is_empty());
+        assert_eq!(second.spectators.len(), MAX_SPECTATORS);
+        assert_eq!(desynced.desync, Some(30));
+        assert_eq!(Message::decode(&Message::Confirmed { start: 7, inputs: vec![[1, 2]] }.encode()), Ok(Message::Confirmed { start: 7, inputs: vec![[1, 2]] }));
+        assert!(Message::decode(&[1, 0]).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+// Two or more players on one keypad: each key is owned by a player or shared, and every player's keyboard keys and
+// gamepad feed into one PlayerInput, which decides what reaches the machine.
+//
+// Games for two split the keypad between the players. PONG and PONG2 give the left paddle 1 and 4 and the right one
+// C and D. TANK has one tank, so one player drives with 2, 4, 6 and 8 and the other fires with 5.
+// When a player presses a key another player owns, Conflict decides whether the press counts.
+use crate::cpu::Keypad;
+use crate::frontend::Input;
+use crate::gamepad::{Gamepad, GamepadConfig, GamepadProfile};
+use crate::keymap::KeyMap;
+use crate::loader;
+
+// The gamepad bindings of the second player for the ROMs split between two, on top of the built-in profiles.
+const SECOND_PLAYER_GAMEPADS: &str = "
+rom b232ef880bd6060fb45fa6effed7edf0ae95670e
+bind Up C
+bind Down D
+bind LeftY- C
+bind LeftY+ D
+
+rom a60611339661e3ab2d8af024ad1da5880a6f8665
+bind Up C
+bind Down D
+bind LeftY- C
+bind LeftY+ D
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeySplit {
+    // The player owning each key, None for keys anyone may press.
+    owners: [Option<usize>; 16],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conflict {
+    // Presses of keys another player owns are dropped.
+    Reject,
+    // Anyone may press any key, which is down while any player holds it.
+    Share,
+}
+
+pub struct PlayerInput {
+    split: KeySplit,
+    conflict: Conflict,
+    // The keys each player holds on the keyboard or otherwise, as masks with bit 0 for key 0.
+    held: Vec<u16>,
+    gamepads: Vec<Option<Gamepad>>,
+    // The keys that reached the machine at the last poll.
+    keys: u16,
+    pub rejected: u64,
+}
+
+impl KeySplit {
+    pub fn shared() -> KeySplit {
+        KeySplit { owners: [None; 16] }
+    }
+
+    // The split for the ROM with this SHA-1, shared for ROMs not made for two players.
+    pub fn built_in(hash: &str) -> KeySplit {
+        let (first, second): (&[u8], &[u8]) = match hash.to_lowercase().as_str() {
+            "b232ef880bd6060fb45fa6effed7edf0ae95670e" | "a60611339661e3ab2d8af024ad1da5880a6f8665" => (&[0x1, 0x4], &[0xC, 0xD]),
+            "18b9d15f4c159e1f0ed58c2d8ec1d89325d3a3b6" => (&[0x2, 0x4, 0x6, 0x8], &[0x5]),
+            _ => (&[], &[])
+        };
+        let mut split = KeySplit::shared();
+
+        first.iter().for_each(|&key| split.assign(key, Some(0)));
+        second.iter().for_each(|&key| split.assign(key, Some(1)));
+        split
+    }
+
+    pub fn for_rom(rom: &[u8]) -> KeySplit {
+        KeySplit::built_in(&loader::rom_hash(rom))
+    }
+
+    pub fn assign(&mut self, key: u8, owner: Option<usize>) {
+        self.owners[(key & 0xF) as usize] = owner;
+    }
+
+    pub fn owner(&self, key: u8) -> Option<usize> {
+        self.owners[(key & 0xF) as usize]
+    }
+
+    // The keys a player owns, as a mask.
+    pub fn keys_of(&self, player: usize) -> u16 {
+        (0..16).filter(|&key| self.owners[key] == Some(player)).fold(0, |mask, key| mask | 1 << key)
+    }
+
+    pub fn allows(&self, player: usize, key: u8) -> bool {
+        self.owner(key).is_none_or(|owner| owner == player)
+    }
+}
+
+// The gamepad profile of a player for the ROM with this SHA-1. The first player gets the built-in profile,
+// the second one the same with the other half of split keypads.
+pub fn gamepad_profile(hash: &str, player: usize) -> GamepadProfile {
+    match player {
+        0 => GamepadConfig::built_in().for_hash(hash),
+        _ => GamepadConfig::parse(SECOND_PLAYER_GAMEPADS).expect("the second player's profiles are valid").for_hash(hash)
+    }
+}
+
+impl PlayerInput {
+    pub fn new(players: usize, split: KeySplit, conflict: Conflict) -> PlayerInput {
+        PlayerInput { split, conflict, held: vec![0; players], gamepads: (0..players).map(|_| None).collect(), keys: 0, rejected: 0 }
+    }
+
+    // Two players with the split and gamepad profiles for the ROM with this SHA-1.
+    pub fn for_hash(hash: &str, conflict: Conflict) -> PlayerInput {
+        let mut input = PlayerInput::new(2, KeySplit::built_in(hash), conflict);
+
+        for player in 0..2 {
+            input.set_gamepad(player, Some(Gamepad::new(gamepad_profile(hash, player))));
+        }
+
+        input
+    }
+
+    pub fn split(&self) -> &KeySplit {
+        &self.split
+    }
+
+    pub fn players(&self) -> usize {
+        self.held.len()
+    }
+
+    // Returns false for players there are not.
+    pub fn set_gamepad(&mut self, player: usize, gamepad: Option<Gamepad>) -> bool {
+        match self.gamepads.get_mut(player) {
+            Some(slot) => {
+                *slot = gamepad;
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn gamepad_mut(&mut self, player: usize) -> Option<&mut Gamepad> {
+        self.gamepads.get_mut(player).and_then(Option::as_mut)
+    }
+
+    // Returns false for presses the conflict handling dropped and for players there are not.
+    pub fn set_key(&mut self, player: usize, key: u8, pressed: bool) -> bool {
+        let key = key & 0xF;
+
+        if player >= self.players() {
+            return false;
+        }
+
+        match (pressed, self.accepts(player, key)) {
+            (true, true) => self.held[player] |= 1 << key,
+            (true, false) => {
+                self.rejected += 1;
+                return false;
+            }
+            (false, _) => self.held[player] &= !(1 << key)
+        }
+
+        true
+    }
+
+    // Replaces what a player holds, as from a netplay peer or an agent, and returns the keys that count,
+    // none for players there are not.
+    pub fn set_keys(&mut self, player: usize, mask: u16) -> u16 {
+        if player >= self.players() {
+            return 0;
+        }
+
+        let accepted = self.filter(player, mask);
+
+        self.held[player] = accepted;
+        accepted
+    }
+
+    // Routes a host key of a keyboard the players share to the player owning the CHIP-8 key it maps to,
+    // or to the first player for shared keys. Returns false for unmapped keys and dropped presses.
+    pub fn set_host_key(&mut self, key_map: &KeyMap, host_key: &str, pressed: bool) -> bool {
+        match key_map.key_for(host_key) {
+            Some(key) => self.set_key(self.split.owner(key).unwrap_or(0), key, pressed),
+            None => false
+        }
+    }
+
+    // The keys the machine saw at the last poll.
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    fn accepts(&self, player: usize, key: u8) -> bool {
+        self.conflict == Conflict::Share || self.split.allows(player, key)
+    }
+
+    fn filter(&mut self, player: usize, mask: u16) -> u16 {
+        let accepted = (0..16).filter(|&key| mask & (1 << key) != 0 && self.accepts(player, key)).fold(0, |accepted, key| accepted | 1 << key);
+
+        self.rejected += (mask & !accepted).count_ones() as u64;
+        accepted
+    }
+}
+
+impl Input for PlayerInput {
+    // Combines what every player holds with their gamepads, a key being down while any player holds it.
+    fn poll(&mut self, keypad: &mut Keypad) {
+        let mut keys = 0;
+
+        for player in 0..self.players() {
+            let from_gamepad = match self.gamepads[player].as_mut() {
+                Some(gamepad) => {
+                    let mut pressed = Keypad { keys: [false; 16] };
+
+                    gamepad.update(&mut pressed);
+                    pressed.keys.iter().enumerate().filter(|(_, &down)| down).fold(0, |mask, (key, _)| mask | 1 << key)
+                }
+                None => 0
+            };
+
+            keys |= self.held[player] | self.filter(player, from_gamepad);
+        }
+
+        for (key, pressed) in keypad.keys.iter_mut().enumerate() {
+            *pressed = keys & (1 << key) != 0;
+        }
+
+        self.keys = keys;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conflict, KeySplit, PlayerInput};
+    use crate::cpu::Keypad;
+    use crate::frontend::Input;
+    use crate::gamepad::{Button, GamepadEvent};
+    use crate::keymap::KeyMapConfig;
+    use crate::loader;
+
+    #[test]
+    fn pong_players_keep_to_their_paddles() {
+        let rom = loader::read_rom_file("roms/PONG2").unwrap();
+        let hash = loader::rom_hash(&rom);
+        let key_map = KeyMapConfig::built_in().for_hash(&hash);
+        let mut input = PlayerInput::for_hash(&hash, Conflict::Reject);
+        let mut keypad = Keypad { keys: [false; 16] };
+
+        assert_eq!((input.split().keys_of(0), input.split().keys_of(1)), (1 << 0x1 | 1 << 0x4, 1 << 0xC | 1 << 0xD));
+
+        // One keyboard for both: W moves the left paddle, the arrows the right one.
+        assert!(input.set_host_key(&key_map, "KeyW", true));
+        assert!(input.set_host_key(&key_map, "ArrowDown", true));
+        assert!(!input.set_key(0, 0xC, true));
+        assert!(input.set_key(1, 0x7, true));
+
+        // Both gamepads push up, each moving its own paddle.
+        for player in 0..2 {
+            input.gamepad_mut(player).unwrap().handle(GamepadEvent::Button(Button::Up, true));
+        }
+
+        input.poll(&mut keypad);
+
+        assert_eq!(input.keys(), 1 << 0x1 | 1 << 0xC | 1 << 0xD | 1 << 0x7);
+        assert!(keypad.keys[0xC] && !keypad.keys[0x4]);
+        assert_eq!(input.set_keys(1, 1 << 0x4 | 1 << 0xD), 1 << 0xD);
+        assert_eq!(input.rejected, 2);
+    }
+
+    #[test]
+    fn players_there_are_not_are_refused() {
+        let mut input = PlayerInput::new(2, KeySplit::shared(), Conflict::Reject);
+
+        assert!(!input.set_key(2, 0x5, true));
+        assert_eq!(input.set_keys(7, 0xFFFF), 0);
+        assert!(!input.set_gamepad(2, None));
+        assert!(input.gamepad_mut(2).is_none());
+        assert!(input.set_gamepad(1, None));
+        assert_eq!(input.rejected, 0);
+    }
+
+    #[test]
+    fn tank_splits_driving_and_firing_and_sharing_lets_anyone_press() {
+        let rom = loader::read_rom_file("roms/TANK").unwrap();
+        let mut split = KeySplit::for_rom(&rom);
+        let mut input = PlayerInput::new(2, split, Conflict::Share);
+        let mut keypad = Keypad { keys: [false; 16] };
+
+        assert_eq!(split.owner(0x5), Some(1));
+        assert_eq!(split.owner(0x8), Some(0));
+        assert_eq!(split.owner(0xA), None);
+        assert!(input.set_key(1, 0x8, true));
+        assert!(input.set_key(0, 0x8, true));
+        assert!(input.set_key(1, 0x8, false));
+
+        input.poll(&mut keypad);
+        assert!(keypad.keys[0x8]);
+
+        split.assign(0x8, None);
+        assert!(split.allows(1, 0x8));
+        assert_eq!(KeySplit::built_in("ffff"), KeySplit::shared());
+    }
+}
@@ -0,0 +1,402 @@
+// Counts where cycles go: per address, per kind of instruction, per subroutine and per loop.
+//
+// Subroutines are tracked with a shadow call stack following 2NNN and 00EE, every cycle is charged
+// to the subroutine running when it was fetched. Code outside of any call is reported as main.
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::cpu::{Chip8, Cycle, CycleObserver};
+use crate::instruction::{self, Instruction, InstructionClass};
+
+const ADDRESS_SPACE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    // Cycles spent in the subroutine itself.
+    pub self_cycles: u64,
+    // Cycles spent in the subroutine and everything it called.
+    pub total_cycles: u64,
+}
+
+// A backward jump, from the 1NNN at `end` to `start`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HotLoop {
+    pub start: u16,
+    pub end: u16,
+    pub iterations: u64,
+    // Cycles spent on the addresses between start and end, including the jump.
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub frames: u64,
+    pub min_cycles: u64,
+    pub max_cycles: u64,
+    pub total_cycles: u64,
+    // Frames that cleared the screen or drew a sprite.
+    pub drawing_frames: u64,
+}
+
+pub struct Profiler {
+    cycles: u64,
+    address_hits: Vec<u64>,
+    op_codes: Vec<u16>,
+    pattern_hits: BTreeMap<&'static str, u64>,
+    class_hits: BTreeMap<InstructionClass, u64>,
+    call_stack: Vec<u16>,
+    calls: BTreeMap<u16, u64>,
+    stack_cycles: BTreeMap<Vec<u16>, u64>,
+    loops: BTreeMap<(u16, u16), u64>,
+    frame_stats: FrameStats,
+    frame_cycles: u64,
+    frame_drew: bool,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl FrameStats {
+    pub fn average_cycles(&self) -> f64 {
+        match self.frames {
+            0 => 0.0,
+            frames => self.total_cycles as f64 / frames as f64
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            cycles: 0,
+            address_hits: vec![0; ADDRESS_SPACE],
+            op_codes: vec![0; ADDRESS_SPACE],
+            pattern_hits: BTreeMap::new(),
+            class_hits: BTreeMap::new(),
+            call_stack: Vec::new(),
+            calls: BTreeMap::new(),
+            stack_cycles: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            frame_stats: FrameStats::default(),
+            frame_cycles: 0,
+            frame_drew: false,
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn hits_at(&self, address: u16) -> u64 {
+        self.address_hits.get(address as usize).cloned().unwrap_or(0)
+    }
+
+    // Addresses that were executed, most executed first.
+    pub fn hottest_addresses(&self) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self.address_hits
+            .iter()
+            .enumerate()
+            .filter(|(_, &hits)| hits > 0)
+            .map(|(address, &hits)| (address as u16, hits))
+            .collect();
+
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    // Hits per op code pattern such as DXYN.
+    pub fn pattern_hits(&self) -> &BTreeMap<&'static str, u64> {
+        &self.pattern_hits
+    }
+
+    pub fn class_hits(&self) -> &BTreeMap<InstructionClass, u64> {
+        &self.class_hits
+    }
+
+    // Keyed by the address the subroutine was called at, None for code outside of any call.
+    pub fn subroutines(&self) -> BTreeMap<Option<u16>, SubroutineProfile> {
+        let mut subroutines: BTreeMap<Option<u16>, SubroutineProfile> = BTreeMap::new();
+
+        for (stack, &cycles) in &self.stack_cycles {
+            let running = subroutines.entry(stack.last().cloned()).or_default();
+            running.self_cycles += cycles;
+
+            let mut counted = vec![None];
+            counted.extend(stack.iter().cloned().map(Some));
+            counted.sort();
+            counted.dedup();
+
+            for subroutine in counted {
+                subroutines.entry(subroutine).or_default().total_cycles += cycles;
+            }
+        }
+
+        for (&address, &calls) in &self.calls {
+            subroutines.entry(Some(address)).or_default().calls = calls;
+        }
+
+        subroutines
+    }
+
+    // Backward jumps, the loops taking the most cycles first.
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let mut loops: Vec<HotLoop> = self.loops
+            .iter()
+            .map(|(&(start, end), &iterations)| HotLoop {
+                start,
+                end,
+                iterations,
+                cycles: (start..=end.saturating_add(1)).map(|address| self.hits_at(address)).sum(),
+            })
+            .collect();
+
+        loops.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
+    // One line per call stack with the cycles spent in it, the input format of flamegraph.pl and inferno.
+    pub fn folded_stacks(&self) -> String {
+        self.stack_cycles
+            .iter()
+            .map(|(stack, cycles)| {
+                let mut frames = vec!["main".to_string()];
+                frames.extend(stack.iter().map(|&address| subroutine_name(Some(address))));
+                format!("{} {}\n", frames.join(";"), cycles)
+            })
+            .collect()
+    }
+
+    // A report for reading, listing at most `limit` entries per table.
+    pub fn report(&self, limit: usize) -> String {
+        let mut report = String::new();
+        let frames = self.frame_stats;
+
+        report.push_str(&format!("{} cycles over {} frames\n", self.cycles, frames.frames));
+        if frames.frames > 0 {
+            report.push_str(&format!(
+                "cycles per frame: min {}, average {:.1}, max {}, {} frames drew to the screen\n",
+                frames.min_cycles, frames.average_cycles(), frames.max_cycles, frames.drawing_frames
+            ));
+        }
+
+        report.push_str("\nHottest addresses\n");
+        for (address, hits) in self.hottest_addresses().into_iter().take(limit) {
+            report.push_str(&format!(
+                "  {:03X} {:>10} {:>6.2}%  {}\n",
+                address, hits, self.percentage(hits), instruction::mnemonic(self.op_codes[address as usize])
+            ));
+        }
+
+        report.push_str("\nInstructions\n");
+        let mut patterns: Vec<(&&str, &u64)> = self.pattern_hits.iter().collect();
+        patterns.sort_by(|a, b| b.1.cmp(a.1));
+        for (pattern, &hits) in patterns.into_iter().take(limit) {
+            report.push_str(&format!("  {} {:>10} {:>6.2}%\n", pattern, hits, self.percentage(hits)));
+        }
+
+        report.push_str("\nInstruction classes\n");
+        for (class, &hits) in &self.class_hits {
+            report.push_str(&format!("  {:<10} {:>10} {:>6.2}%\n", format!("{:?}", class), hits, self.percentage(hits)));
+        }
+
+        report.push_str("\nSubroutines              calls       self      total\n");
+        let mut subroutines: Vec<(Option<u16>, SubroutineProfile)> = self.subroutines().into_iter().collect();
+        subroutines.sort_by_key(|(_, profile)| std::cmp::Reverse(profile.total_cycles));
+        for (subroutine, profile) in subroutines.into_iter().take(limit) {
+            report.push_str(&format!(
+                "  {:<16} {:>10} {:>10} {:>10}\n",
+                subroutine_name(subroutine), profile.calls, profile.self_cycles, profile.total_cycles
+            ));
+        }
+
+        report.push_str("\nHot loops           iterations     cycles\n");
+        for hot_loop in self.hot_loops().into_iter().take(limit) {
+            report.push_str(&format!(
+                "  {:03X}-{:03X} {:>20} {:>10}\n",
+                hot_loop.start, hot_loop.end, hot_loop.iterations, hot_loop.cycles
+            ));
+        }
+
+        report
+    }
+
+    pub fn to_json(&self) -> Value {
+        let frames = self.frame_stats;
+
+        json!({
+            "cycles": self.cycles,
+            "frames": {
+                "count": frames.frames,
+                "min_cycles": frames.min_cycles,
+                "max_cycles": frames.max_cycles,
+                "average_cycles": frames.average_cycles(),
+                "drawing_frames": frames.drawing_frames,
+            },
+            "addresses": self.hottest_addresses().into_iter().map(|(address, hits)| json!({
+                "address": address,
+                "hits": hits,
+                "instruction": instruction::mnemonic(self.op_codes[address as usize]),
+            })).collect::<Vec<_>>(),
+            "instructions": self.pattern_hits,
+            "classes": self.class_hits.iter().map(|(class, hits)| (format!("{:?}", class).to_lowercase(), *hits)).collect::<BTreeMap<_, _>>(),
+            "subroutines": self.subroutines().into_iter().map(|(subroutine, profile)| json!({
+                "name": subroutine_name(subroutine),
+                "address": subroutine,
+                "calls": profile.calls,
+                "self_cycles": profile.self_cycles,
+                "total_cycles": profile.total_cycles,
+            })).collect::<Vec<_>>(),
+            "loops": self.hot_loops().into_iter().map(|hot_loop| json!({
+                "start": hot_loop.start,
+                "end": hot_loop.end,
+                "iterations": hot_loop.iterations,
+                "cycles": hot_loop.cycles,
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    fn percentage(&self, hits: u64) -> f64 {
+        match self.cycles {
+            0 => 0.0,
+            cycles => hits as f64 * 100.0 / cycles as f64
+        }
+    }
+
+    fn count_stack_cycle(&mut self) {
+        match self.stack_cycles.get_mut(self.call_stack.as_slice()) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.stack_cycles.insert(self.call_stack.clone(), 1);
+            }
+        }
+    }
+}
+
+fn subroutine_name(subroutine: Option<u16>) -> String {
+    match subroutine {
+        Some(address) => format!("sub_{:03X}", address),
+        None => "main".to_string()
+    }
+}
+
+impl CycleObserver for Profiler {
+    fn on_cycle(&mut self, _chip8: &Chip8, cycle: &Cycle) {
+        let address = cycle.address as usize;
+
+        self.cycles += 1;
+        self.frame_cycles += 1;
+        self.address_hits[address] += 1;
+        self.op_codes[address] = cycle.op_code;
+        self.count_stack_cycle();
+
+        let instruction = match Instruction::decode(cycle.op_code) {
+            Ok(instruction) => instruction,
+            Err(_) => return
+        };
+
+        *self.pattern_hits.entry(instruction.pattern()).or_insert(0) += 1;
+        *self.class_hits.entry(instruction.class()).or_insert(0) += 1;
+
+        if cycle.result.is_err() {
+            return;
+        }
+
+        match instruction {
+            Instruction::Call(address) => {
+                *self.calls.entry(address).or_insert(0) += 1;
+                self.call_stack.push(address);
+            }
+            Instruction::Return => {
+                self.call_stack.pop();
+            }
+            Instruction::Jump(target) if target <= cycle.address => {
+                *self.loops.entry((target, cycle.address)).or_insert(0) += 1;
+            }
+            Instruction::ClearScreen | Instruction::Draw(..) => self.frame_drew = true,
+            _ => {}
+        }
+    }
+
+    fn on_frame(&mut self, _chip8: &Chip8) {
+        let stats = &mut self.frame_stats;
+
+        stats.min_cycles = match stats.frames {
+            0 => self.frame_cycles,
+            _ => stats.min_cycles.min(self.frame_cycles)
+        };
+        stats.max_cycles = stats.max_cycles.max(self.frame_cycles);
+        stats.total_cycles += self.frame_cycles;
+        stats.frames += 1;
+
+        if self.frame_drew {
+            stats.drawing_frames += 1;
+        }
+
+        self.frame_cycles = 0;
+        self.frame_drew = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profiler, SubroutineProfile};
+    use crate::cpu::Chip8;
+    use crate::instruction::InstructionClass;
+
+    // main: CALL 208, JP 202 / 208: CALL 20E, RET / 20E: ADD V0, 01, RET
+    const ROM: [u8; 20] = [
+        0x22, 0x08, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x0E,
+        0x00, 0xEE, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE, 0x00, 0x00,
+    ];
+
+    fn profile(frames: usize) -> Profiler {
+        let mut chip8 = Chip8::initialize();
+        let mut profiler = Profiler::new();
+
+        chip8.load_rom(&ROM).unwrap();
+
+        for _ in 0..frames {
+            chip8.emulate_frame_observed(6, &mut profiler).unwrap();
+        }
+
+        profiler
+    }
+
+    #[test]
+    fn counts_hits_per_address_and_instruction() {
+        let profiler = profile(2);
+
+        assert_eq!(profiler.cycles(), 12);
+        assert_eq!(profiler.hits_at(0x200), 2);
+        assert_eq!(profiler.hits_at(0x20E), 2);
+        assert_eq!(profiler.pattern_hits()["2NNN"], 4);
+        assert_eq!(profiler.pattern_hits()["00EE"], 4);
+        assert_eq!(profiler.class_hits()[&InstructionClass::Arithmetic], 2);
+        assert_eq!(profiler.frame_stats().frames, 2);
+        assert_eq!(profiler.frame_stats().average_cycles(), 6.0);
+    }
+
+    #[test]
+    fn attributes_cycles_to_subroutines() {
+        let profiler = profile(2);
+        let subroutines = profiler.subroutines();
+
+        assert_eq!(subroutines[&None], SubroutineProfile { calls: 0, self_cycles: 4, total_cycles: 12 });
+        assert_eq!(subroutines[&Some(0x208)], SubroutineProfile { calls: 2, self_cycles: 4, total_cycles: 8 });
+        assert_eq!(subroutines[&Some(0x20E)], SubroutineProfile { calls: 2, self_cycles: 4, total_cycles: 4 });
+        assert_eq!(profiler.folded_stacks(), "main 4\nmain;sub_208 4\nmain;sub_208;sub_20E 4\n");
+
+        let hot_loop = profiler.hot_loops()[0];
+        assert_eq!((hot_loop.start, hot_loop.end, hot_loop.iterations), (0x200, 0x202, 2));
+        assert_eq!(profiler.to_json()["subroutines"][1]["name"], "sub_208");
+    }
+}
@@ -0,0 +1,54 @@
+// Behaviours that differ between CHIP-8 interpreters.
+// ROMs written for one platform often misbehave on another unless these match.
+// The default, everything off, is the behaviour most ROMs found online expect.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    // 8XY6 and 8XYE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    // FX55 and FX65 leave I pointing past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    // BNNN jumps to NNN + VX (X being the high nibble of NNN) instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    // Sprites drawn past the screen edge are cut off instead of wrapping around.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    // The original COSMAC VIP interpreter.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+        }
+    }
+
+    // SUPER-CHIP 1.1 on the HP 48.
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+        }
+    }
+
+    // Looks a profile up by the name used on the command line and in configuration files.
+    pub fn by_name(name: &str) -> Option<Quirks> {
+        let profiles: [(&[&str], Quirks); 3] = [
+            (&["default"], Quirks::default()),
+            (&["vip", "cosmac-vip", "chip-8"], Quirks::cosmac_vip()),
+            (&["schip", "super-chip"], Quirks::super_chip()),
+        ];
+
+        profiles
+            .iter()
+            .find(|(names, _)| names.iter().any(|known| known.eq_ignore_ascii_case(name)))
+            .map(|&(_, quirks)| quirks)
+    }
+}
@@ -0,0 +1,309 @@
+// Differential tests running random instruction sequences through Chip8::execute_op_code
+// and through a deliberately naive interpreter, then comparing the whole machine state.
+use proptest::prelude::*;
+
+use crate::cpu::{Chip8, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Machine {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    sp: u16,
+    stack: [u16; 16],
+    ram: Vec<u8>,
+    gfx: Vec<bool>,
+    delay_timer: u8,
+    sound_timer: u8,
+    keys: [bool; 16],
+}
+
+impl Machine {
+    fn from_chip8(chip8: &Chip8) -> Machine {
+        Machine {
+            v: chip8.registers.v,
+            i: chip8.registers.i,
+            pc: chip8.registers.program_counter,
+            sp: chip8.stack.stack_pointer,
+            stack: chip8.stack.stack,
+            ram: chip8.memory.ram.to_vec(),
+            gfx: chip8.graphics.gfx.to_vec(),
+            delay_timer: chip8.timers.delay_timer,
+            sound_timer: chip8.timers.sound_timer,
+            keys: chip8.keypad.keys,
+        }
+    }
+
+    fn read(&self, address: u16) -> Result<u8, Chip8Error> {
+        self.ram.get(address as usize).copied().ok_or(Chip8Error::MemoryOutOfBounds(address as usize))
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), Chip8Error> {
+        match self.ram.get_mut(address as usize) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(Chip8Error::MemoryOutOfBounds(address as usize))
+        }
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    // The program counter is assumed to already point past the instruction, as it does after a fetch.
+    // Run on a copy, a failing instruction may have been partially applied.
+    fn execute(&mut self, op_code: u16, quirks: &Quirks) -> Result<(), Chip8Error> {
+        let x = ((op_code >> 8) & 0xF) as usize;
+        let y = ((op_code >> 4) & 0xF) as usize;
+        let n = (op_code & 0xF) as u8;
+        let kk = (op_code & 0xFF) as u8;
+        let nnn = op_code & 0xFFF;
+
+        match (op_code >> 12, n) {
+            (0x0, _) if op_code == 0x00E0 => self.gfx.iter_mut().for_each(|pixel| *pixel = false),
+            (0x0, _) if op_code == 0x00EE => {
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow);
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
+            }
+            (0x1, _) => self.pc = nnn,
+            (0x2, _) => {
+                if self.sp as usize >= self.stack.len() {
+                    return Err(Chip8Error::StackOverflow);
+                }
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+                self.pc = nnn;
+            }
+            (0x3, _) => self.skip_if(self.v[x] == kk),
+            (0x4, _) => self.skip_if(self.v[x] != kk),
+            (0x5, 0) => self.skip_if(self.v[x] == self.v[y]),
+            (0x6, _) => self.v[x] = kk,
+            (0x7, _) => self.v[x] = self.v[x].wrapping_add(kk),
+            (0x8, 0x0) => self.v[x] = self.v[y],
+            (0x8, 0x1) | (0x8, 0x2) | (0x8, 0x3) => {
+                self.v[x] = match n {
+                    0x1 => self.v[x] | self.v[y],
+                    0x2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y],
+                };
+                if quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, 0x4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = if sum > 0xFF { 1 } else { 0 };
+            }
+            (0x8, 0x5) => {
+                let no_borrow = self.v[x] >= self.v[y];
+                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0xF] = if no_borrow { 1 } else { 0 };
+            }
+            (0x8, 0x7) => {
+                let no_borrow = self.v[y] >= self.v[x];
+                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0xF] = if no_borrow { 1 } else { 0 };
+            }
+            (0x8, 0x6) | (0x8, 0xE) => {
+                let source = if quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+                let (result, flag) = if n == 0x6 { (source / 2, source % 2) } else { (source.wrapping_mul(2), source / 128) };
+                self.v[x] = result;
+                self.v[0xF] = flag;
+            }
+            (0x9, 0) => self.skip_if(self.v[x] != self.v[y]),
+            (0xA, _) => self.i = nnn,
+            (0xB, _) => self.pc = nnn + self.v[if quirks.jump_uses_vx { x } else { 0 }] as u16,
+            (0xD, _) => {
+                let left = self.v[x] as usize % SCREEN_WIDTH;
+                let top = self.v[y] as usize % SCREEN_HEIGHT;
+                let mut collision = false;
+
+                for row in 0..n as u16 {
+                    let sprite = self.read(self.i + row)?;
+
+                    for column in 0..8 {
+                        if sprite & (0x80 >> column) == 0 {
+                            continue;
+                        }
+
+                        let mut pixel_x = left + column;
+                        let mut pixel_y = top + row as usize;
+
+                        if quirks.clip_sprites && (pixel_x >= SCREEN_WIDTH || pixel_y >= SCREEN_HEIGHT) {
+                            continue;
+                        }
+
+                        pixel_x %= SCREEN_WIDTH;
+                        pixel_y %= SCREEN_HEIGHT;
+
+                        let pixel = &mut self.gfx[pixel_y * SCREEN_WIDTH + pixel_x];
+                        collision |= *pixel;
+                        *pixel = !*pixel;
+                    }
+                }
+
+                self.v[0xF] = if collision { 1 } else { 0 };
+            }
+            (0xE, _) if kk == 0x9E => self.skip_if(self.keys[(self.v[x] & 0xF) as usize]),
+            (0xE, _) if kk == 0xA1 => self.skip_if(!self.keys[(self.v[x] & 0xF) as usize]),
+            (0xF, _) => match kk {
+                0x07 => self.v[x] = self.delay_timer,
+                0x0A => match (0..16).find(|&key| self.keys[key]) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => self.pc = self.pc.wrapping_sub(2),
+                },
+                0x15 => self.delay_timer = self.v[x],
+                0x18 => self.sound_timer = self.v[x],
+                0x1E => self.i = (self.i + self.v[x] as u16) % 0x1000,
+                0x29 => self.i = (self.v[x] % 16) as u16 * 5,
+                0x33 => {
+                    self.write(self.i, self.v[x] / 100)?;
+                    self.write(self.i + 1, (self.v[x] / 10) % 10)?;
+                    self.write(self.i + 2, self.v[x] % 10)?;
+                }
+                0x55 | 0x65 => {
+                    for register in 0..=x {
+                        let address = self.i + register as u16;
+
+                        if kk == 0x55 {
+                            self.write(address, self.v[register])?;
+                        } else {
+                            self.v[register] = self.read(address)?;
+                        }
+                    }
+                    if quirks.load_store_increments_i {
+                        self.i += x as u16 + 1;
+                    }
+                }
+                _ => return Err(Chip8Error::UnknownOpCode(op_code)),
+            },
+            (0x0, _) => {}
+            _ => return Err(Chip8Error::UnknownOpCode(op_code)),
+        }
+
+        Ok(())
+    }
+}
+
+fn assert_machines_equal(chip8: &Chip8, reference: &Machine, op_code: u16) -> Result<(), TestCaseError> {
+    let actual = Machine::from_chip8(chip8);
+
+    prop_assert_eq!(actual.v, reference.v, "V registers after {:04X}", op_code);
+    prop_assert_eq!(actual.i, reference.i, "I after {:04X}", op_code);
+    prop_assert_eq!(actual.pc, reference.pc, "program counter after {:04X}", op_code);
+    prop_assert_eq!(actual.sp, reference.sp, "stack pointer after {:04X}", op_code);
+    prop_assert_eq!(actual.stack, reference.stack, "stack after {:04X}", op_code);
+    prop_assert!(actual.ram == reference.ram, "memory differs after {:04X}", op_code);
+    prop_assert!(actual.gfx == reference.gfx, "screen differs after {:04X}", op_code);
+    prop_assert_eq!(actual.delay_timer, reference.delay_timer, "delay timer after {:04X}", op_code);
+    prop_assert_eq!(actual.sound_timer, reference.sound_timer, "sound timer after {:04X}", op_code);
+
+    Ok(())
+}
+
+fn quirks_strategy() -> impl Strategy<Value = Quirks> {
+    any::<[bool; 5]>().prop_map(|flags| Quirks {
+        vf_reset: flags[0],
+        shift_uses_vy: flags[1],
+        load_store_increments_i: flags[2],
+        jump_uses_vx: flags[3],
+        clip_sprites: flags[4],
+    })
+}
+
+// CXKK is left out because its result depends on the random number generator.
+fn op_code_strategy() -> impl Strategy<Value = u16> {
+    prop_oneof![
+        any::<u16>(),
+        (0x8000u16..=0x8FFF).prop_map(|op_code| op_code & 0xFFF0 | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][op_code as usize % 9]),
+        (0xF000u16..=0xFF00).prop_map(|op_code| op_code & 0xFF00 | [0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65][op_code as usize % 9]),
+        Just(0x00E0),
+        Just(0x00EE),
+    ]
+    .prop_filter("CXKK is not deterministic", |op_code| op_code & 0xF000 != 0xC000)
+}
+
+fn machine_strategy() -> impl Strategy<Value = Machine> {
+    (
+        any::<[u8; 16]>(),
+        0u16..0x1000,
+        0x200u16..0x1000,
+        0u16..=16,
+        prop::array::uniform16(0u16..0x1000),
+        prop::collection::vec(any::<u8>(), 4096 - 0x200),
+        prop::collection::vec(any::<bool>(), SCREEN_WIDTH * SCREEN_HEIGHT),
+        any::<(u8, u8)>(),
+        any::<[bool; 16]>(),
+    )
+        .prop_map(|(v, i, pc, sp, stack, rom, gfx, timers, keys)| {
+            let mut ram = Chip8::initialize().memory.ram.to_vec();
+            ram[0x200..].copy_from_slice(&rom);
+
+            Machine { v, i, pc, sp, stack, ram, gfx, delay_timer: timers.0, sound_timer: timers.1, keys }
+        })
+}
+
+fn chip8_from_machine(machine: &Machine, quirks: Quirks) -> Chip8 {
+    let mut chip8 = Chip8::initialize_with_quirks(quirks);
+
+    chip8.registers.v = machine.v;
+    chip8.registers.i = machine.i;
+    chip8.registers.program_counter = machine.pc;
+    chip8.stack.stack_pointer = machine.sp;
+    chip8.stack.stack = machine.stack;
+    chip8.memory.ram.copy_from_slice(&machine.ram);
+    chip8.graphics.gfx.copy_from_slice(&machine.gfx);
+    chip8.timers.delay_timer = machine.delay_timer;
+    chip8.timers.sound_timer = machine.sound_timer;
+    chip8.keypad.keys = machine.keys;
+
+    chip8
+}
+
+proptest! {
+    #[test]
+    fn instruction_sequences_match_reference_interpreter(
+        mut reference in machine_strategy(),
+        quirks in quirks_strategy(),
+        op_codes in prop::collection::vec(op_code_strategy(), 1..32),
+    ) {
+        let mut chip8 = chip8_from_machine(&reference, quirks);
+
+        for op_code in op_codes {
+            let mut expected = reference.clone();
+            let expected_result = expected.execute(op_code, &quirks);
+
+            prop_assert_eq!(chip8.execute_op_code(op_code), expected_result, "result of {:04X}", op_code);
+
+            if expected_result.is_ok() {
+                reference = expected;
+            }
+
+            assert_machines_equal(&chip8, &reference, op_code)?;
+        }
+    }
+
+    #[test]
+    fn random_byte_is_masked_by_kk(before in machine_strategy(), register in 0u16..16, kk in any::<u8>()) {
+        let mut chip8 = chip8_from_machine(&before, Quirks::default());
+
+        chip8.execute_op_code(0xC000 | register << 8 | kk as u16).unwrap();
+
+        prop_assert_eq!(chip8.registers.v[register as usize] & !kk, 0);
+
+        let mut after = Machine::from_chip8(&chip8);
+        after.v[register as usize] = before.v[register as usize];
+
+        prop_assert_eq!(after, before);
+    }
+}
@@ -0,0 +1,54 @@
+// The random numbers behind CXKK, from a seeded xorshift generator so the core needs no operating system
+// and a run can be repeated exactly from its seed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct XorShift {
+    state: u64,
+}
+
+// Xorshift never leaves a zero state, so a zero seed is swapped for this one.
+const ZERO_SEED_REPLACEMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        match seed {
+            0 => XorShift { state: ZERO_SEED_REPLACEMENT },
+            _ => XorShift { state: seed }
+        }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // The high byte, the low bits of xorshift being the weakest.
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::XorShift;
+
+    #[test]
+    fn same_seed_gives_the_same_bytes() {
+        let mut first = XorShift::new(42);
+        let mut second = XorShift::new(42);
+        let mut bytes = [0u8; 256];
+        let mut again = [0u8; 256];
+
+        bytes.iter_mut().for_each(|byte| *byte = first.next_u8());
+        again.iter_mut().for_each(|byte| *byte = second.next_u8());
+
+        assert_eq!(bytes, again);
+        assert!(bytes.iter().any(|&byte| byte != bytes[0]));
+        assert_ne!(XorShift::new(0).next_u64(), 0);
+    }
+}
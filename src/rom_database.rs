@@ -0,0 +1,225 @@
+// Known ROMs, identified by SHA-1, with the settings they play best with.
+//
+// A database is a JSON array of entries, or an object with the array under "roms":
+//
+//     {"hash": "f100197f...", "title": "Space Invaders", "author": "David Winter",
+//      "platform": "default", "quirks": {"clip_sprites": true}, "cycles_per_frame": 10,
+//      "keys": {"left": 4, "fire": 5, "right": 6}}
+//
+// Only the hash is required. The platform is a quirks profile name, or one of the platform names of
+// the community chip-8-database (originalChip8, modernChip8, superchip), and quirks listed by name
+// override the ones of the platform. TOML files hold the same entries as [[roms]] tables.
+use std::collections::BTreeMap;
+use std::fs;
+
+use serde_json::Value;
+
+use crate::loader;
+use crate::quirks::Quirks;
+
+const BUILT_IN: &str = include_str!("rom_database.json");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub hash: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub platform: String,
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+    // What each key does in the game, by name, as keypad values 0 to F.
+    pub keys: BTreeMap<String, u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomDatabase {
+    entries: BTreeMap<String, RomInfo>,
+}
+
+// The quirks of a platform named in a database entry.
+pub fn platform_quirks(name: &str) -> Option<Quirks> {
+    match name {
+        "originalChip8" => Some(Quirks::cosmac_vip()),
+        "modernChip8" => Some(Quirks::default()),
+        "superchip" | "superchip1" => Some(Quirks::super_chip()),
+        _ => Quirks::by_name(name)
+    }
+}
+
+impl RomInfo {
+    fn from_json(entry: &Value) -> Result<RomInfo, String> {
+        let hash = entry["hash"].as_str().ok_or(format!("entry without a hash: {}", entry))?.to_lowercase();
+        let invalid = |field: &str| format!("{}: can not read {}", hash, field);
+        let platform = entry["platform"].as_str().unwrap_or("default").to_string();
+        let mut quirks = platform_quirks(&platform).ok_or(format!("{}: unknown platform {}", hash, platform))?;
+
+        for (name, enabled) in entry["quirks"].as_object().into_iter().flatten() {
+            let enabled = enabled.as_bool().ok_or_else(|| invalid(name))?;
+
+            match name.as_str() {
+                "vf_reset" => quirks.vf_reset = enabled,
+                "shift_uses_vy" => quirks.shift_uses_vy = enabled,
+                "load_store_increments_i" => quirks.load_store_increments_i = enabled,
+                "jump_uses_vx" => quirks.jump_uses_vx = enabled,
+                "clip_sprites" => quirks.clip_sprites = enabled,
+                _ => return Err(format!("{}: unknown quirk {}", hash, name))
+            }
+        }
+
+        let mut keys = BTreeMap::new();
+
+        for (name, key) in entry["keys"].as_object().into_iter().flatten() {
+            match key.as_u64() {
+                Some(key) if key < 16 => keys.insert(name.clone(), key as u8),
+                _ => return Err(invalid(&format!("key {}", name)))
+            };
+        }
+
+        let cycles_per_frame = match &entry["cycles_per_frame"] {
+            Value::Null => 10,
+            cycles => cycles.as_u64().ok_or_else(|| invalid("cycles_per_frame"))? as u32
+        };
+
+        Ok(RomInfo {
+            title: entry["title"].as_str().unwrap_or(&hash).to_string(),
+            author: entry["author"].as_str().map(String::from),
+            hash,
+            platform,
+            quirks,
+            cycles_per_frame,
+            keys,
+        })
+    }
+}
+
+impl RomDatabase {
+    pub fn new() -> RomDatabase {
+        RomDatabase::default()
+    }
+
+    // The ROMs shipped in the roms directory.
+    pub fn built_in() -> RomDatabase {
+        RomDatabase::parse_json(BUILT_IN).expect("the built-in ROM database is valid")
+    }
+
+    // Reads TOML for files ending in .toml and JSON for everything else.
+    pub fn load(path: &str) -> Result<RomDatabase, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        let database = match path.ends_with(".toml") {
+            true => RomDatabase::parse_toml(&text),
+            false => RomDatabase::parse_json(&text)
+        };
+
+        database.map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse_json(text: &str) -> Result<RomDatabase, String> {
+        let json: Value = serde_json::from_str(text).map_err(|error| error.to_string())?;
+
+        RomDatabase::from_json(&json)
+    }
+
+    pub fn parse_toml(text: &str) -> Result<RomDatabase, String> {
+        let toml: toml::Value = toml::from_str(text).map_err(|error| error.to_string())?;
+
+        RomDatabase::from_json(&serde_json::to_value(toml).map_err(|error| error.to_string())?)
+    }
+
+    fn from_json(json: &Value) -> Result<RomDatabase, String> {
+        let roms = match json {
+            Value::Array(roms) => roms,
+            _ => json["roms"].as_array().ok_or("expected a list of ROMs")?
+        };
+        let mut database = RomDatabase::new();
+
+        for entry in roms {
+            database.insert(RomInfo::from_json(entry)?);
+        }
+
+        Ok(database)
+    }
+
+    pub fn insert(&mut self, info: RomInfo) {
+        self.entries.insert(info.hash.clone(), info);
+    }
+
+    // Entries of `other` replace the ones for the same ROM.
+    pub fn extend(&mut self, other: RomDatabase) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&RomInfo> {
+        self.entries.get(&hash.to_lowercase())
+    }
+
+    pub fn identify(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.get(&loader::rom_hash(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &RomInfo> {
+        self.entries.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RomDatabase;
+    use crate::cpu::Chip8;
+    use crate::loader;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn identifies_the_built_in_roms() {
+        let database = RomDatabase::built_in();
+        let rom = loader::read_rom_file("roms/INVADERS").unwrap();
+        let info = database.identify(&rom).unwrap();
+
+        assert_eq!(database.len(), 24);
+        assert_eq!(info.title, "Space Invaders");
+        assert_eq!(info.author.as_deref(), Some("David Winter"));
+        assert_eq!(info.keys["fire"], 5);
+        assert!(database.identify(&[0x12, 0x00]).is_none());
+
+        for entry in database.entries() {
+            let path = std::fs::read_dir("roms")
+                .unwrap()
+                .map(|file| file.unwrap().path())
+                .find(|path| loader::rom_hash(&std::fs::read(path).unwrap()) == entry.hash);
+
+            assert!(path.is_some(), "no ROM for {}", entry.title);
+        }
+    }
+
+    #[test]
+    fn user_entries_override_the_built_in_ones() {
+        let rom = [0x12, 0x00];
+        let hash = loader::rom_hash(&rom);
+        let mut database = RomDatabase::built_in();
+        let toml = format!(
+            "[[roms]]\nhash = \"{}\"\ntitle = \"Loop\"\nplatform = \"superchip\"\ncycles_per_frame = 30\n\n[roms.quirks]\nclip_sprites = false\n\n[roms.keys]\nstart = 15\n",
+            hash
+        );
+
+        database.extend(RomDatabase::parse_toml(&toml).unwrap());
+        database.extend(RomDatabase::parse_json(&format!(r#"{{"roms": [{{"hash": "{}", "platform": "originalChip8"}}]}}"#, "F100197F0F2F05B4F3C8C31AB9C2C3930D3E9571")).unwrap());
+
+        let mut chip8 = Chip8::initialize();
+        let info = chip8.load_rom_from_database(&rom, &database).unwrap().unwrap();
+
+        assert_eq!(info.cycles_per_frame, 30);
+        assert_eq!(info.keys["start"], 0xF);
+        assert_eq!(chip8.quirks, Quirks { clip_sprites: false, ..Quirks::super_chip() });
+        assert_eq!(database.get("f100197f0f2f05b4f3c8c31ab9c2c3930d3e9571").unwrap().quirks, Quirks::cosmac_vip());
+        assert_eq!(database.len(), 25);
+        assert!(RomDatabase::parse_json(r#"[{"hash": "00", "keys": {"fire": 16}}]"#).is_err());
+        assert!(RomDatabase::parse_json(r#"[{"hash": "00", "platform": "amiga"}]"#).is_err());
+    }
+}
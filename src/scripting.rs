@@ -0,0 +1,328 @@
+// Rhai scripts driving a run: pressing keys, poking memory, stopping the run and showing text next to the screen.
+//
+// A script may define hooks, called with the machine at hand:
+//
+//     fn on_frame(frame) { ... }             before each frame, numbered from 0
+//     fn on_cycle(address, op_code) { ... }  after each instruction, which slows the run down
+//
+// and reaches the machine through these functions, all taking and returning integers:
+//
+//     v(x) set_v(x, value) i() set_i(value) pc() ram(address) set_ram(address, value)
+//     key(k) press(k) release(k) delay_timer() sound_timer() cycles()
+//     stop()          ends the run once the current hook returns
+//     overlay(text)   a line of text to show with the frame, as print does
+//
+// Statements outside functions run once, before the first hook. Rhai functions can not see variables defined
+// outside them, so state between calls lives in the machine or comes from the frame number. For example:
+//
+//     fn on_frame(frame) {
+//         if frame % 30 == 0 { press(5) } else { release(5) }
+//         if ram(0x3F0) == 0 { stop() }
+//     }
+use std::cell::RefCell;
+use std::fs;
+use std::ops::Range;
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+
+use crate::cpu::Chip8;
+use crate::engine::Executor;
+
+// What the registered functions work on. The machine is swapped in for the length of each hook.
+struct Host {
+    chip8: Chip8,
+    stopped: bool,
+    output: Vec<String>,
+    // The addresses the script wrote, for engines caching decoded code.
+    written: Option<Range<usize>>,
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    host: Rc<RefCell<Host>>,
+    started: bool,
+    has_on_frame: bool,
+    has_on_cycle: bool,
+}
+
+type HostResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn index(value: INT, length: usize, what: &str) -> HostResult<usize> {
+    match value >= 0 && (value as usize) < length {
+        true => Ok(value as usize),
+        false => Err(format!("{} {} is out of range", what, value).into())
+    }
+}
+
+// Registers the functions scripts reach the machine through.
+fn register_host(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+    let shared = host.clone();
+    engine.register_fn("v", move |x: INT| -> HostResult<INT> { Ok(shared.borrow().chip8.registers.v[index(x, 16, "register")?] as INT) });
+
+    let shared = host.clone();
+    engine.register_fn("set_v", move |x: INT, value: INT| -> HostResult<()> {
+        shared.borrow_mut().chip8.registers.v[index(x, 16, "register")?] = value as u8;
+        Ok(())
+    });
+
+    let shared = host.clone();
+    engine.register_fn("i", move || shared.borrow().chip8.registers.i as INT);
+
+    let shared = host.clone();
+    engine.register_fn("set_i", move |value: INT| shared.borrow_mut().chip8.registers.i = value as u16);
+
+    let shared = host.clone();
+    engine.register_fn("pc", move || shared.borrow().chip8.registers.program_counter as INT);
+
+    let shared = host.clone();
+    engine.register_fn("ram", move |address: INT| -> HostResult<INT> {
+        let host = shared.borrow();
+
+        Ok(host.chip8.memory.ram[index(address, host.chip8.memory.ram.len(), "address")?] as INT)
+    });
+
+    let shared = host.clone();
+    engine.register_fn("set_ram", move |address: INT, value: INT| -> HostResult<()> {
+        let mut host = shared.borrow_mut();
+        let address = index(address, host.chip8.memory.ram.len(), "address")?;
+
+        host.chip8.memory.ram[address] = value as u8;
+        host.written = Some(match host.written.take() {
+            Some(written) => written.start.min(address)..written.end.max(address + 1),
+            None => address..address + 1
+        });
+        Ok(())
+    });
+
+    let shared = host.clone();
+    engine.register_fn("key", move |key: INT| -> HostResult<bool> { Ok(shared.borrow().chip8.keypad.keys[index(key, 16, "key")?]) });
+
+    let shared = host.clone();
+    engine.register_fn("press", move |key: INT| -> HostResult<()> {
+        shared.borrow_mut().chip8.keypad.keys[index(key, 16, "key")?] = true;
+        Ok(())
+    });
+
+    let shared = host.clone();
+    engine.register_fn("release", move |key: INT| -> HostResult<()> {
+        shared.borrow_mut().chip8.keypad.keys[index(key, 16, "key")?] = false;
+        Ok(())
+    });
+
+    let shared = host.clone();
+    engine.register_fn("delay_timer", move || shared.borrow().chip8.timers.delay_timer as INT);
+
+    let shared = host.clone();
+    engine.register_fn("sound_timer", move || shared.borrow().chip8.timers.sound_timer as INT);
+
+    let shared = host.clone();
+    engine.register_fn("cycles", move || shared.borrow().chip8.cycle_count as INT);
+
+    let shared = host.clone();
+    engine.register_fn("stop", move || shared.borrow_mut().stopped = true);
+
+    let shared = host.clone();
+    engine.register_fn("overlay", move |text: &str| shared.borrow_mut().output.push(text.to_string()));
+
+    let shared = host.clone();
+    engine.on_print(move |text| shared.borrow_mut().output.push(text.to_string()));
+}
+
+impl Script {
+    pub fn compile(source: &str) -> Result<Script, String> {
+        let host = Rc::new(RefCell::new(Host { chip8: Chip8::initialize(), stopped: false, output: Vec::new(), written: None }));
+        let mut engine = Engine::new();
+
+        register_host(&mut engine, &host);
+
+        let ast = engine.compile(source).map_err(|error| error.to_string())?;
+        let defines = |name: &str, parameters: usize| ast.iter_functions().any(|function| function.name == name && function.params.len() == parameters);
+        let (has_on_frame, has_on_cycle) = (defines("on_frame", 1), defines("on_cycle", 2));
+
+        Ok(Script { engine, ast, scope: Scope::new(), host, started: false, has_on_frame, has_on_cycle })
+    }
+
+    pub fn load(path: &str) -> Result<Script, String> {
+        let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+
+        Script::compile(&source).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    // Whether the script wants on_cycle calls, which means running instructions one by one.
+    pub fn wants_cycles(&self) -> bool {
+        self.has_on_cycle
+    }
+
+    // Whether the script called stop.
+    pub fn stopped(&self) -> bool {
+        self.host.borrow().stopped
+    }
+
+    // The overlay lines and printed text since the last call.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.host.borrow_mut().output)
+    }
+
+    // The addresses written since the last call, which engines caching decoded code have to forget.
+    pub fn take_written(&mut self) -> Option<Range<usize>> {
+        self.host.borrow_mut().written.take()
+    }
+
+    pub fn on_frame(&mut self, chip8: &mut Chip8, frame: u64) -> Result<(), String> {
+        match self.has_on_frame {
+            true => self.call(chip8, "on_frame", vec![Dynamic::from(frame as INT)]),
+            false => self.call_top_level(chip8)
+        }
+    }
+
+    pub fn on_cycle(&mut self, chip8: &mut Chip8, address: u16, op_code: u16) -> Result<(), String> {
+        match self.has_on_cycle {
+            true => self.call(chip8, "on_cycle", vec![Dynamic::from(address as INT), Dynamic::from(op_code as INT)]),
+            false => self.call_top_level(chip8)
+        }
+    }
+
+    // Runs a frame with the hooks on the executor, the way it would run one without them, unless the script stops
+    // first. The executor forgets the code the hooks wrote to.
+    pub fn run_frame(&mut self, chip8: &mut Chip8, executor: &mut Executor, cycles_per_frame: u32, frame: u64) -> Result<(), String> {
+        self.on_frame(chip8, frame)?;
+        self.invalidate_written(executor);
+
+        if self.stopped() {
+            return Ok(());
+        }
+
+        if !self.has_on_cycle {
+            return executor.emulate_frame(chip8, cycles_per_frame).map_err(|error| error.to_string());
+        }
+
+        for _ in 0..cycles_per_frame {
+            let address = chip8.registers.program_counter;
+            let op_code = chip8.fetch_op_code().map_err(|error| error.to_string())?;
+
+            executor.emulate_cycle(chip8).map_err(|error| error.to_string())?;
+            self.on_cycle(chip8, address, op_code)?;
+            self.invalidate_written(executor);
+
+            if self.stopped() {
+                return Ok(());
+            }
+        }
+
+        chip8.tick_timers();
+
+        Ok(())
+    }
+
+    fn invalidate_written(&mut self, executor: &mut Executor) {
+        if let Some(written) = self.take_written() {
+            executor.invalidate(written);
+        }
+    }
+
+    fn call_top_level(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        match self.started {
+            true => Ok(()),
+            false => self.with_machine(chip8, |script| {
+                script.started = true;
+                script.engine.run_ast_with_scope(&mut script.scope, &script.ast)
+            })
+        }
+    }
+
+    fn call(&mut self, chip8: &mut Chip8, name: &str, arguments: Vec<Dynamic>) -> Result<(), String> {
+        self.call_top_level(chip8)?;
+        self.with_machine(chip8, |script| {
+            let options = CallFnOptions::new().eval_ast(false).rewind_scope(true);
+
+            script.engine.call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, name, arguments).map(|_| ())
+        })
+    }
+
+    fn with_machine(&mut self, chip8: &mut Chip8, run: impl FnOnce(&mut Script) -> Result<(), Box<EvalAltResult>>) -> Result<(), String> {
+        std::mem::swap(chip8, &mut self.host.borrow_mut().chip8);
+
+        let result = run(self);
+
+        std::mem::swap(chip8, &mut self.host.borrow_mut().chip8);
+        result.map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Script;
+    use crate::cpu::Chip8;
+    use crate::engine::{Engine, Executor};
+
+    #[test]
+    fn presses_keys_reads_memory_and_stops() {
+        // Waits for a key, stores it at 300 and loops.
+        let rom = [0xF0, 0x0A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+        let mut script = Script::compile(
+            "
+            set_ram(0x300, 0xFF);
+
+            fn on_frame(frame) {
+                if frame == 3 { press(0xB) }
+                if ram(0x300) == 0xB { overlay(`stored at frame ${frame}`); stop() }
+            }
+            ",
+        )
+        .unwrap();
+        let mut chip8 = Chip8::initialize();
+        let mut executor = Executor::new(Engine::Interpreter);
+        let mut frame = 0;
+
+        chip8.load_rom(&rom).unwrap();
+
+        while !script.stopped() {
+            script.run_frame(&mut chip8, &mut executor, 10, frame).unwrap();
+            frame += 1;
+        }
+
+        assert_eq!(frame, 5);
+        assert_eq!(script.take_output(), vec!["stored at frame 4"]);
+        assert_eq!(script.take_written(), None);
+        assert!(chip8.keypad.keys[0xB]);
+        assert!(!script.wants_cycles());
+    }
+
+    #[test]
+    fn cycle_hooks_and_errors() {
+        let mut script = Script::compile("fn on_cycle(address, op_code) { if op_code == 0x6005 { set_v(1, v(0) + address) } }").unwrap();
+        let mut chip8 = Chip8::initialize();
+        let mut executor = Executor::new(Engine::Interpreter);
+
+        // LD V0, 05; JP 202
+        chip8.load_rom(&[0x60, 0x05, 0x12, 0x02]).unwrap();
+        script.run_frame(&mut chip8, &mut executor, 4, 0).unwrap();
+
+        assert!(script.wants_cycles());
+        assert_eq!(chip8.registers.v[1], 0x05);
+
+        let mut broken = Script::compile("fn on_frame(frame) { ram(5000) }").unwrap();
+
+        assert!(broken.run_frame(&mut chip8, &mut executor, 1, 0).unwrap_err().contains("address 5000 is out of range"));
+        assert!(Script::compile("fn on_frame(").is_err());
+    }
+
+    #[test]
+    fn cycle_hooks_run_on_the_engine_and_their_writes_are_seen() {
+        for &engine in Engine::ALL.iter() {
+            let mut script = Script::compile("fn on_cycle(address, op_code) { if v(0) == 3 { set_ram(0x201, 0x10) } }").unwrap();
+            let mut executor = Executor::new(engine);
+            let mut chip8 = Chip8::initialize();
+
+            // ADD V0, 01; JP 200
+            chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+            script.run_frame(&mut chip8, &mut executor, 10, 0).unwrap();
+
+            assert_eq!(executor.engine(), engine);
+            assert_eq!(chip8.registers.v[0], 3 + 2 * 0x10, "{}", engine.name());
+        }
+    }
+}
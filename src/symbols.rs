@@ -0,0 +1,262 @@
+// Labels and source lines from the assembler, to show addresses as `main_loop+4 (game.8o:37)`.
+//
+// Symbol files have one entry per line, addresses in hexadecimal with or without 0x:
+//
+//     label main_loop 2A0
+//     line 2A4 game.8o:37
+//
+// Blank lines and lines starting with # are ignored. A JSON file of the form
+// {"labels": {"main_loop": 672}, "lines": [{"address": 676, "path": "game.8o", "line": 37}]} works too,
+// addresses there can also be strings like "0x2A4".
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+
+use serde_json::Value;
+
+use crate::error::Chip8Error;
+use crate::instruction::{self, Instruction};
+use crate::quirks::Quirks;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLine {
+    pub path: String,
+    pub line: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+    addresses: BTreeMap<String, u16>,
+    lines: BTreeMap<u16, SourceLine>,
+}
+
+pub fn parse_address(text: &str) -> Option<u16> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+
+    u16::from_str_radix(hex, 16).ok()
+}
+
+// Numbers are addresses as they are, strings are hexadecimal.
+pub fn parse_json_address(value: &Value) -> Option<u16> {
+    match value {
+        Value::Number(number) => number.as_u64().filter(|&address| address <= u16::MAX as u64).map(|address| address as u16),
+        Value::String(text) => parse_address(text),
+        _ => None
+    }
+}
+
+// Paths match when one ends with the other, so a symbol file can name sources relative to the project.
+pub fn same_source(path: &str, other: &str) -> bool {
+    let path = path.replace('\\', "/");
+    let other = other.replace('\\', "/");
+
+    path == other || path.ends_with(&format!("/{}", other)) || other.ends_with(&format!("/{}", path))
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn load(path: &str) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+
+        Symbols::parse(&text).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        match text.trim_start().starts_with('{') {
+            true => Symbols::parse_json(text),
+            false => Symbols::parse_text(text)
+        }
+    }
+
+    fn parse_text(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("line {}: can not read {}", index + 1, line.trim());
+
+            match fields.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["label", name, address] => symbols.add_label(name, parse_address(address).ok_or_else(invalid)?),
+                ["line", address, location] => {
+                    let (path, line) = location.rsplit_once(':').ok_or_else(invalid)?;
+                    symbols.add_line(parse_address(address).ok_or_else(invalid)?, path, line.parse().map_err(|_| invalid())?);
+                }
+                _ => return Err(invalid())
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    fn parse_json(text: &str) -> Result<Symbols, String> {
+        let json: Value = serde_json::from_str(text).map_err(|error| error.to_string())?;
+        let mut symbols = Symbols::new();
+
+        if let Some(labels) = json["labels"].as_object() {
+            for (name, address) in labels {
+                symbols.add_label(name, parse_json_address(address).ok_or(format!("label {} has no address", name))?);
+            }
+        }
+
+        for entry in json["lines"].as_array().into_iter().flatten() {
+            symbols.add_source_line(entry).ok_or(format!("can not read line entry {}", entry))?;
+        }
+
+        Ok(symbols)
+    }
+
+    // Adds an entry of the form {"address": 676, "path": "game.8o", "line": 37}.
+    pub fn add_source_line(&mut self, entry: &Value) -> Option<()> {
+        self.add_line(parse_json_address(&entry["address"])?, entry["path"].as_str()?, entry["line"].as_u64()?);
+        Some(())
+    }
+
+    // When an address has several labels the first one added is shown.
+    pub fn add_label(&mut self, name: &str, address: u16) {
+        self.labels.entry(address).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn add_line(&mut self, address: u16, path: &str, line: u64) {
+        self.lines.insert(address, SourceLine { path: path.to_string(), line });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).cloned()
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    pub fn line_at(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.get(&address)
+    }
+
+    // Every address generated for a source line, lowest first.
+    pub fn addresses_for_line(&self, path: &str, line: u64) -> Vec<u16> {
+        self.lines
+            .iter()
+            .filter(|(_, source_line)| source_line.line == line && same_source(&source_line.path, path))
+            .map(|(&address, _)| address)
+            .collect()
+    }
+
+    // The closest label at or before the address plus an offset, or the address itself without labels.
+    pub fn label_and_offset(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&label_address, label)) if label_address == address => label.clone(),
+            Some((&label_address, label)) => format!("{}+{}", label, address - label_address),
+            None => format!("{:03X}", address)
+        }
+    }
+
+    // label_and_offset, plus the source line when known.
+    pub fn describe(&self, address: u16) -> String {
+        let location = self.label_and_offset(address);
+
+        match self.line_at(address) {
+            Some(source_line) => format!("{} ({}:{})", location, source_line.path, source_line.line),
+            None => location
+        }
+    }
+
+    // Like instruction::mnemonic, with jump, call and I targets shown as labels where there is one.
+    pub fn mnemonic(&self, op_code: u16) -> String {
+        self.mnemonic_with_quirks(op_code, &Quirks::default())
+    }
+
+    // Like instruction::mnemonic_with_quirks, with labels for the addresses.
+    pub fn mnemonic_with_quirks(&self, op_code: u16, quirks: &Quirks) -> String {
+        let text = instruction::mnemonic_with_quirks(op_code, quirks);
+        let target = match Instruction::decode(op_code) {
+            Ok(Instruction::Jump(nnn)) | Ok(Instruction::Call(nnn)) | Ok(Instruction::LoadI(nnn))
+            | Ok(Instruction::JumpWithOffset(nnn)) | Ok(Instruction::System(nnn)) => nnn,
+            _ => return text
+        };
+
+        match self.label_at(target) {
+            Some(label) => format!("{}{}", text.trim_end_matches(&format!("{:03X}", target)), label),
+            None => text
+        }
+    }
+
+    pub fn describe_error(&self, error: &Chip8Error, address: u16) -> String {
+        format!("{} at {}", error, self.describe(address))
+    }
+
+    // A listing of `range` two bytes at a time, with labels above the addresses they name.
+    pub fn disassemble(&self, ram: &[u8], range: Range<usize>) -> String {
+        let end = range.end.min(ram.len());
+        let mut listing = String::new();
+
+        for address in (range.start..end).step_by(2) {
+            if let Some(label) = self.label_at(address as u16) {
+                listing.push_str(&format!("{}:\n", label));
+            }
+
+            let op_code = match ram.get(address + 1) {
+                Some(&low) => (ram[address] as u16) << 8 | low as u16,
+                None => (ram[address] as u16) << 8
+            };
+            let source = match self.line_at(address as u16) {
+                Some(source_line) => format!("  ; {}:{}", source_line.path, source_line.line),
+                None => String::new()
+            };
+            let line = format!("  {:03X}  {:04X}  {:<20}{}", address, op_code, self.mnemonic(op_code), source);
+
+            listing.push_str(line.trim_end());
+            listing.push('\n');
+        }
+
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{same_source, Symbols};
+    use crate::error::Chip8Error;
+
+    const SYMBOLS: &str = "# game\nlabel main 200\nlabel main_loop 0x2A0\nline 2A4 game.8o:37\nline 0x2A6 game.8o:38\n";
+
+    #[test]
+    fn describes_addresses_with_labels_and_source_lines() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.describe(0x2A4), "main_loop+4 (game.8o:37)");
+        assert_eq!(symbols.describe(0x2A0), "main_loop");
+        assert_eq!(symbols.describe(0x1FE), "1FE");
+        assert_eq!(symbols.address_of("main_loop"), Some(0x2A0));
+        assert_eq!(symbols.addresses_for_line("/home/me/game/game.8o", 38), vec![0x2A6]);
+        assert_eq!(symbols.mnemonic(0x12A0), "JP main_loop");
+        assert_eq!(symbols.mnemonic(0x62A0), "LD V2, A0");
+        assert_eq!(symbols.describe_error(&Chip8Error::UnknownOpCode(0x8008), 0x2A4), "unknown op code 8008 at main_loop+4 (game.8o:37)");
+        assert!(Symbols::parse("label main").unwrap_err().starts_with("line 1"));
+        assert!(same_source("C:\\games\\game.8o", "game.8o"));
+    }
+
+    #[test]
+    fn reads_json_and_disassembles() {
+        let symbols = Symbols::parse(r#"{"labels": {"start": "0x200"}, "lines": [{"address": 514, "path": "game.8o", "line": 2}]}"#).unwrap();
+        let mut ram = [0u8; 0x206];
+
+        ram[0x200..].copy_from_slice(&[0x00, 0xE0, 0x60, 0x05, 0x12, 0x00]);
+
+        assert_eq!(symbols.describe(0x202), "start+2 (game.8o:2)");
+        assert_eq!(
+            symbols.disassemble(&ram, 0x200..0x206),
+            "start:\n  200  00E0  CLS\n  202  6005  LD V0, 05             ; game.8o:2\n  204  1200  JP start\n"
+        );
+    }
+}
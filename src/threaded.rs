@@ -0,0 +1,245 @@
+// Threaded code: straight-line runs of instructions are decoded once into blocks of handlers, which run
+// back to back without fetching or decoding.
+//
+// A block ends after the first instruction that can go somewhere other than the next one (jumps, calls, returns,
+// skips, waiting for a key), draws or clears the screen, or stores to memory. Ending at stores means a ROM
+// rewriting its own code never runs a stale block: the blocks overlapping what FX33 and FX55 wrote are dropped
+// before the next one starts. Anything else writing to memory has to call invalidate or clear.
+use std::ops::Range;
+
+use crate::cpu::{Chip8, Handler};
+use crate::error::Chip8Error;
+
+const MEMORY_SIZE: usize = 4096;
+
+// Longer blocks gain little and make invalidation look further back.
+const MAX_BLOCK_INSTRUCTIONS: usize = 32;
+
+struct Block {
+    // The op code at each address from the start of the block, with the method running it.
+    instructions: Vec<(u16, Handler)>,
+}
+
+pub struct ThreadedCode {
+    // The block starting at each address, built the first time the program counter lands there.
+    blocks: Vec<Option<Block>>,
+    pub blocks_built: u64,
+    pub blocks_run: u64,
+}
+
+fn ends_block(op_code: u16) -> bool {
+    match op_code & 0xF000 {
+        0x0000 => op_code == 0x00E0 || op_code == 0x00EE,
+        0x1000 | 0x2000 | 0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xB000 | 0xD000 | 0xE000 => true,
+        _ => matches!(op_code & 0xF0FF, 0xF00A | 0xF033 | 0xF055)
+    }
+}
+
+// The bytes an instruction stores to, worked out before it runs.
+fn written_by(chip8: &Chip8, op_code: u16) -> Option<Range<usize>> {
+    let i = chip8.registers.i as usize;
+
+    match op_code & 0xF0FF {
+        0xF033 => Some(i..i + 3),
+        0xF055 => Some(i..i + ((op_code >> 8) & 0xF) as usize + 1),
+        _ => None
+    }
+}
+
+impl ThreadedCode {
+    pub fn new() -> ThreadedCode {
+        ThreadedCode { blocks: (0..MEMORY_SIZE).map(|_| None).collect(), blocks_built: 0, blocks_run: 0 }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.iter_mut().for_each(|block| *block = None);
+    }
+
+    // Drops the blocks holding any of the written bytes.
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        let first_start = written.start.saturating_sub(2 * MAX_BLOCK_INSTRUCTIONS).min(MEMORY_SIZE);
+        let end = written.end.min(MEMORY_SIZE);
+
+        for start in first_start..end.max(first_start) {
+            let overlaps = match &self.blocks[start] {
+                Some(block) => start + 2 * block.instructions.len() > written.start,
+                None => false
+            };
+
+            if overlaps {
+                self.blocks[start] = None;
+            }
+        }
+    }
+
+    // Decodes from the address up to the end of the block, an instruction that can not be fetched or decoded,
+    // or MAX_BLOCK_INSTRUCTIONS. Fails when not even the first instruction can be.
+    fn build(chip8: &Chip8, start: u16) -> Result<Block, Chip8Error> {
+        let mut instructions = Vec::new();
+        let mut address = start as usize;
+
+        while instructions.len() < MAX_BLOCK_INSTRUCTIONS {
+            let decoded = match chip8.memory.ram.get(address..address + 2) {
+                Some(bytes) => {
+                    let op_code = (bytes[0] as u16) << 8 | bytes[1] as u16;
+
+                    Chip8::decode(op_code).map(|handler| (op_code, handler))
+                }
+                None => Err(Chip8Error::MemoryOutOfBounds(MEMORY_SIZE.max(address)))
+            };
+
+            match (decoded, instructions.is_empty()) {
+                (Ok((op_code, handler)), _) => {
+                    instructions.push((op_code, handler));
+
+                    if ends_block(op_code) {
+                        break;
+                    }
+                }
+                (Err(error), true) => return Err(error),
+                (Err(_), false) => break
+            }
+
+            address += 2;
+        }
+
+        Ok(Block { instructions })
+    }
+}
+
+impl Default for ThreadedCode {
+    fn default() -> ThreadedCode {
+        ThreadedCode::new()
+    }
+}
+
+impl Chip8 {
+    // Behaves like emulate_frame, running whole blocks until the cycles are used up.
+    pub fn emulate_frame_threaded(&mut self, cycles_per_frame: u32, code: &mut ThreadedCode) -> Result<(), Chip8Error> {
+        let mut remaining = cycles_per_frame as usize;
+
+        while remaining > 0 {
+            remaining -= self.run_block(remaining, code)?;
+        }
+
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    // Behaves like emulate_cycle, running the first instruction of the block at the program counter.
+    pub fn emulate_cycle_threaded(&mut self, code: &mut ThreadedCode) -> Result<(), Chip8Error> {
+        self.run_block(1, code).map(|_| ())
+    }
+
+    // Runs at most budget instructions of the block at the program counter and returns how many ran.
+    // On error the program counter is left pointing at the instruction that failed.
+    fn run_block(&mut self, budget: usize, code: &mut ThreadedCode) -> Result<usize, Chip8Error> {
+        let start = self.registers.program_counter;
+
+        if code.blocks.get(start as usize).is_none_or(Option::is_none) {
+            let block = ThreadedCode::build(self, start)?;
+
+            code.blocks[start as usize] = Some(block);
+            code.blocks_built += 1;
+        }
+
+        let instructions = match &code.blocks[start as usize] {
+            Some(block) => &block.instructions[..budget.min(block.instructions.len())],
+            None => unreachable!("the block was built above")
+        };
+        let mut written = None;
+
+        for (index, &(op_code, handler)) in instructions.iter().enumerate() {
+            let address = start.wrapping_add(2 * index as u16);
+
+            written = written_by(self, op_code);
+            self.registers.program_counter = address.wrapping_add(2);
+
+            if let Err(error) = handler(self, op_code) {
+                self.registers.program_counter = address;
+                return Err(error);
+            }
+
+            self.cycle_count += 1;
+        }
+
+        let ran = instructions.len();
+
+        code.blocks_run += 1;
+
+        if let Some(written) = written {
+            code.invalidate(written);
+        }
+
+        Ok(ran)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadedCode;
+    use crate::cpu::Chip8;
+    use crate::decode_cache::DecodeCache;
+    use crate::error::Chip8Error;
+    use crate::loader;
+
+    #[test]
+    fn threaded_runs_match_the_interpreter_frame_by_frame() {
+        for name in &["BRIX", "INVADERS", "TETRIS", "BLINKY", "PONG2", "UFO", "MAZE"] {
+            let rom = loader::read_rom_file(&format!("roms/{}", name)).unwrap();
+            let (mut interpreted, mut cached, mut threaded) = (Chip8::initialize(), Chip8::initialize(), Chip8::initialize());
+            let mut cache = DecodeCache::new();
+            let mut code = ThreadedCode::new();
+
+            for chip8 in [&mut interpreted, &mut cached, &mut threaded].iter_mut() {
+                chip8.seed_rng(11);
+                chip8.load_rom(&rom).unwrap();
+            }
+
+            // An odd number of cycles cuts blocks short at the end of frames.
+            for frame in 0..600 {
+                interpreted.keypad.keys[frame / 20 % 16] = frame % 4 != 0;
+                cached.keypad.keys = interpreted.keypad.keys;
+                threaded.keypad.keys = interpreted.keypad.keys;
+
+                let result = interpreted.emulate_frame(7);
+
+                assert_eq!(result, cached.emulate_frame_cached(7, &mut cache), "{} frame {}", name, frame);
+                assert_eq!(result, threaded.emulate_frame_threaded(7, &mut code), "{} frame {}", name, frame);
+                assert_eq!(interpreted.registers, threaded.registers, "{} frame {}", name, frame);
+                assert_eq!(interpreted.cycle_count, threaded.cycle_count, "{} frame {}", name, frame);
+                assert_eq!((interpreted.timers.delay_timer, interpreted.stack.stack), (threaded.timers.delay_timer, threaded.stack.stack));
+            }
+
+            assert_eq!(&interpreted.memory.ram[..], &threaded.memory.ram[..], "{}", name);
+            assert_eq!(&interpreted.graphics.gfx[..], &threaded.graphics.gfx[..], "{}", name);
+            assert!(code.blocks_run > code.blocks_built, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stores_into_a_later_block_and_failing_instructions() {
+        // 200: LD I, 20A; LD V0, 62; LD V1, 07; LD [I], V1 rewrites 20A from LD V2, 01 to LD V2, 07,
+        // 20A: LD V2, 01; SE V2, 07; JP 200 (skipped); then 0x5001 which is no instruction.
+        let rom = [0xA2, 0x0A, 0x60, 0x62, 0x61, 0x07, 0xF1, 0x55, 0x12, 0x0A, 0x62, 0x01, 0x32, 0x07, 0x12, 0x00, 0x50, 0x01];
+        let mut chip8 = Chip8::initialize();
+        let mut code = ThreadedCode::new();
+
+        chip8.load_rom(&rom).unwrap();
+        code.blocks[0x20A] = Some(ThreadedCode::build(&chip8, 0x20A).unwrap());
+
+        assert_eq!(chip8.run_block(10, &mut code), Ok(4));
+        assert!(code.blocks[0x20A].is_none());
+        assert_eq!(chip8.run_block(10, &mut code), Ok(1));
+        assert_eq!(chip8.run_block(1, &mut code), Ok(1));
+        assert_eq!(chip8.registers.v[2], 0x07);
+        assert_eq!(chip8.run_block(10, &mut code), Ok(1));
+        assert_eq!(chip8.registers.program_counter, 0x210);
+
+        let error = chip8.emulate_frame_threaded(5, &mut code);
+
+        assert_eq!(error, Err(Chip8Error::UnknownOpCode(0x5001)));
+        assert_eq!((chip8.registers.program_counter, chip8.cycle_count), (0x210, 7));
+    }
+}
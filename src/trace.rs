@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::{Chip8, Cycle, CycleObserver};
+use crate::instruction::{self, Instruction, InstructionClass};
+use crate::symbols::Symbols;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    // cycle PC op code mnemonic, changed registers, I and SP, aligned for reading.
+    Text,
+    // The same columns separated by commas, with a header line and the mnemonic quoted.
+    // With symbols a location column is added.
+    Csv,
+}
+
+// An instruction is traced when it matches every filter that is set.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub classes: Option<Vec<InstructionClass>>,
+}
+
+enum TraceSink {
+    Writer(Box<dyn Write>),
+    RingBuffer(VecDeque<String>, usize),
+}
+
+// Emits one line per executed instruction, either to a writer or into a ring buffer keeping the latest lines.
+pub struct Tracer {
+    format: TraceFormat,
+    filter: TraceFilter,
+    sink: TraceSink,
+    symbols: Option<Symbols>,
+    header_written: bool,
+    error: Option<io::Error>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, address: u16, op_code: u16) -> bool {
+        let address_matches = self.addresses.as_ref().is_none_or(|addresses| addresses.contains(&address));
+        let class_matches = self.classes.as_ref().is_none_or(|classes| {
+            Instruction::decode(op_code).is_ok_and(|instruction| classes.contains(&instruction.class()))
+        });
+
+        address_matches && class_matches
+    }
+}
+
+impl Tracer {
+    pub fn to_writer(writer: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer::with_sink(TraceSink::Writer(writer), format)
+    }
+
+    pub fn to_file(path: &str, format: TraceFormat) -> io::Result<Tracer> {
+        Ok(Tracer::to_writer(Box::new(BufWriter::new(File::create(path)?)), format))
+    }
+
+    pub fn ring_buffer(capacity: usize, format: TraceFormat) -> Tracer {
+        Tracer::with_sink(TraceSink::RingBuffer(VecDeque::with_capacity(capacity), capacity), format)
+    }
+
+    fn with_sink(sink: TraceSink, format: TraceFormat) -> Tracer {
+        Tracer { format, filter: TraceFilter::default(), sink, symbols: None, header_written: false, error: None }
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Tracer {
+        self.filter = filter;
+        self
+    }
+
+    // Shows jump targets as labels and adds where each instruction is in the source.
+    pub fn with_symbols(mut self, symbols: Symbols) -> Tracer {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    // The lines currently held by a ring buffer, oldest first, after the CSV header. Always empty when tracing to a
+    // writer.
+    pub fn lines(&self) -> Vec<&str> {
+        match &self.sink {
+            TraceSink::RingBuffer(lines, _) => self.header().into_iter().chain(lines.iter().map(String::as_str)).collect(),
+            TraceSink::Writer(_) => Vec::new()
+        }
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match (self.format, &self.symbols) {
+            (TraceFormat::Text, _) => None,
+            (TraceFormat::Csv, Some(_)) => Some("cycle,pc,op_code,mnemonic,changes,i,sp,error,location"),
+            (TraceFormat::Csv, None) => Some("cycle,pc,op_code,mnemonic,changes,i,sp,error")
+        }
+    }
+
+    // Flushes the writer and reports the first write error hit while tracing, if any.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        match &mut self.sink {
+            TraceSink::Writer(writer) => writer.flush(),
+            TraceSink::RingBuffer(..) => Ok(())
+        }
+    }
+
+    fn format_line(&self, chip8: &Chip8, cycle: &Cycle) -> String {
+        let changes = changed_registers(cycle, chip8);
+        let mnemonic = match &self.symbols {
+            Some(symbols) => symbols.mnemonic_with_quirks(cycle.op_code, &chip8.quirks),
+            None => instruction::mnemonic_with_quirks(cycle.op_code, &chip8.quirks)
+        };
+        let result = match cycle.result {
+            Ok(()) => String::new(),
+            Err(error) => format!(" ! {}", error)
+        };
+        let location = self.symbols.as_ref().map(|symbols| symbols.describe(cycle.address));
+
+        match self.format {
+            TraceFormat::Text => format!(
+                "{:>8} {:03X} {:04X} {:<16} {:<24} I={:03X} SP={:X}{}{}",
+                cycle.number, cycle.address, cycle.op_code, mnemonic, changes, chip8.registers.i, chip8.stack.stack_pointer, result,
+                location.map(|location| format!(" ; {}", location)).unwrap_or_default()
+            ),
+            TraceFormat::Csv => format!(
+                "{},{:03X},{:04X},\"{}\",{},{:03X},{:X},{}{}",
+                cycle.number, cycle.address, cycle.op_code, mnemonic, changes, chip8.registers.i, chip8.stack.stack_pointer, result.trim_start_matches(" ! "),
+                location.map(|location| format!(",\"{}\"", location)).unwrap_or_default()
+            ),
+        }
+    }
+
+    fn emit(&mut self, line: String) {
+        match &mut self.sink {
+            TraceSink::RingBuffer(lines, capacity) => {
+                if *capacity == 0 {
+                    return;
+                }
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            TraceSink::Writer(writer) => {
+                if self.error.is_none() {
+                    self.error = writeln!(writer, "{}", line).err();
+                }
+            }
+        }
+    }
+}
+
+fn changed_registers(cycle: &Cycle, chip8: &Chip8) -> String {
+    (0..16)
+        .filter(|&index| cycle.registers.v[index] != chip8.registers.v[index])
+        .map(|index| format!("V{:X}={:02X}", index, chip8.registers.v[index]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl CycleObserver for Tracer {
+    fn on_cycle(&mut self, chip8: &Chip8, cycle: &Cycle) {
+        if !self.filter.matches(cycle.address, cycle.op_code) {
+            return;
+        }
+
+        // A ring buffer would drop the header once full, lines puts it back in front instead.
+        if let (false, TraceSink::Writer(_), Some(header)) = (self.header_written, &self.sink, self.header()) {
+            self.header_written = true;
+            self.emit(header.to_string());
+        }
+
+        let line = self.format_line(chip8, cycle);
+
+        self.emit(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use super::{TraceFilter, TraceFormat, Tracer};
+    use crate::cpu::Chip8;
+    use crate::instruction::InstructionClass;
+    use crate::symbols::Symbols;
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(rom: &[u8], cycles: usize, tracer: &mut Tracer) -> Chip8 {
+        let mut chip8 = Chip8::initialize();
+
+        chip8.load_rom(rom).unwrap();
+
+        for _ in 0..cycles {
+            let _ = chip8.emulate_cycle_observed(tracer);
+        }
+
+        chip8
+    }
+
+    #[test]
+    fn traces_changed_registers_i_and_stack_pointer() {
+        let mut tracer = Tracer::ring_buffer(8, TraceFormat::Text);
+
+        run(&[0x60, 0x05, 0xA3, 0x00, 0x22, 0x08, 0x00, 0x00, 0x80, 0x08], 4, &mut tracer);
+
+        assert_eq!(tracer.lines(), vec![
+            "       0 200 6005 LD V0, 05        V0=05                    I=000 SP=0",
+            "       1 202 A300 LD I, 300                                 I=300 SP=0",
+            "       2 204 2208 CALL 208                                  I=300 SP=1",
+            "       3 208 8008 DW 8008                                   I=300 SP=1 ! unknown op code 8008",
+        ]);
+    }
+
+    #[test]
+    fn shows_labels_and_source_lines_with_symbols() {
+        let symbols = Symbols::parse("label start 200\nline 202 game.8o:4").unwrap();
+        let mut tracer = Tracer::ring_buffer(2, TraceFormat::Text).with_symbols(symbols);
+
+        run(&[0x70, 0x01, 0x12, 0x00], 2, &mut tracer);
+
+        assert_eq!(tracer.lines(), vec![
+            "       0 200 7001 ADD V0, 01       V0=01                    I=000 SP=0 ; start",
+            "       1 202 1200 JP start                                  I=000 SP=0 ; start+2 (game.8o:4)",
+        ]);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_latest_lines() {
+        let mut tracer = Tracer::ring_buffer(2, TraceFormat::Csv);
+
+        run(&[0x70, 0x01, 0x12, 0x00], 6, &mut tracer);
+
+        assert_eq!(tracer.lines(), vec![
+            "cycle,pc,op_code,mnemonic,changes,i,sp,error",
+            "4,200,7001,\"ADD V0, 01\",V0=03,000,0,",
+            "5,202,1200,\"JP 200\",,000,0,",
+        ]);
+    }
+
+    #[test]
+    fn filters_by_address_and_class() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let filter = TraceFilter { addresses: Some(0x202..=0x206), classes: Some(vec![InstructionClass::Arithmetic]) };
+        let mut tracer = Tracer::to_writer(Box::new(SharedBuffer(buffer.clone())), TraceFormat::Csv).with_filter(filter);
+
+        run(&[0x60, 0x01, 0x61, 0x02, 0xA2, 0x00, 0x80, 0x14, 0x12, 0x08], 5, &mut tracer);
+        tracer.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.lock().unwrap().clone()).unwrap(),
+            "cycle,pc,op_code,mnemonic,changes,i,sp,error\n1,202,6102,\"LD V1, 02\",V1=02,000,0,\n3,206,8014,\"ADD V0, V1\",V0=03,200,0,\n"
+        );
+    }
+}
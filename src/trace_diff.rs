@@ -0,0 +1,280 @@
+// Compares execution step by step against a trace recorded by another emulator.
+//
+// Trace format: one line per executed instruction describing the machine right before it runs,
+// as whitespace separated hexadecimal fields without prefixes:
+//
+//     PC OPCODE V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF I SP
+//
+// for example `2A4 F265 01 02 03 00 00 00 00 00 00 00 00 00 00 00 00 00 300 1`.
+// Blank lines and lines starting with # are ignored.
+use std::fmt;
+
+use crate::cpu::{Chip8, Cycle, CycleObserver};
+use crate::error::Chip8Error;
+use crate::symbols::Symbols;
+
+const FIELD_COUNT: usize = 20;
+const MEMORY_CONTEXT: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub program_counter: u16,
+    pub op_code: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub stack_pointer: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TraceDiff {
+    // Every line of the reference trace matched.
+    Matched(usize),
+    Diverged { cycle: usize, expected: TraceRecord, actual: TraceRecord, fields: Vec<String> },
+    Failed { cycle: usize, error: Chip8Error },
+}
+
+// Collects a TraceRecord for every instruction, in the same format reference traces use.
+#[derive(Default)]
+pub struct TraceRecorder {
+    pub records: Vec<TraceRecord>,
+}
+
+impl TraceRecord {
+    // Fails with the core's own error for an instruction that can not be fetched.
+    pub fn capture(chip8: &Chip8) -> Result<TraceRecord, Chip8Error> {
+        Ok(TraceRecord {
+            program_counter: chip8.registers.program_counter,
+            op_code: chip8.fetch_op_code()?,
+            v: chip8.registers.v,
+            i: chip8.registers.i,
+            stack_pointer: chip8.stack.stack_pointer,
+        })
+    }
+
+    pub fn parse(line: &str) -> Result<TraceRecord, String> {
+        let fields = line
+            .split_whitespace()
+            .map(|field| u16::from_str_radix(field, 16).map_err(|_| format!("{} is not a hexadecimal number", field)))
+            .collect::<Result<Vec<u16>, String>>()?;
+
+        if fields.len() != FIELD_COUNT {
+            return Err(format!("expected {} fields, found {}", FIELD_COUNT, fields.len()));
+        }
+
+        let mut v = [0; 16];
+
+        for (index, (register, &value)) in v.iter_mut().zip(&fields[2..18]).enumerate() {
+            if value > 0xFF {
+                return Err(format!("V{:X} value {:X} is larger than FF", index, value));
+            }
+            *register = value as u8;
+        }
+
+        Ok(TraceRecord { program_counter: fields[0], op_code: fields[1], v, i: fields[18], stack_pointer: fields[19] })
+    }
+
+    // Names of the fields that differ, in trace order.
+    pub fn differences(&self, other: &TraceRecord) -> Vec<String> {
+        let mut fields = Vec::new();
+
+        if self.program_counter != other.program_counter {
+            fields.push("PC".to_string());
+        }
+        if self.op_code != other.op_code {
+            fields.push("op code".to_string());
+        }
+        for register in 0..16 {
+            if self.v[register] != other.v[register] {
+                fields.push(format!("V{:X}", register));
+            }
+        }
+        if self.i != other.i {
+            fields.push("I".to_string());
+        }
+        if self.stack_pointer != other.stack_pointer {
+            fields.push("SP".to_string());
+        }
+
+        fields
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{:03X} {:04X}", self.program_counter, self.op_code)?;
+
+        for value in self.v.iter() {
+            write!(formatter, " {:02X}", value)?;
+        }
+
+        write!(formatter, " {:03X} {:X}", self.i, self.stack_pointer)
+    }
+}
+
+impl CycleObserver for TraceRecorder {
+    fn on_cycle(&mut self, _chip8: &Chip8, cycle: &Cycle) {
+        self.records.push(TraceRecord {
+            program_counter: cycle.address,
+            op_code: cycle.op_code,
+            v: cycle.registers.v,
+            i: cycle.registers.i,
+            stack_pointer: cycle.stack_pointer,
+        });
+    }
+}
+
+pub fn parse_trace(text: &str) -> Result<Vec<TraceRecord>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| TraceRecord::parse(line).map_err(|error| format!("line {}: {}", index + 1, error)))
+        .collect()
+}
+
+// Runs the emulator alongside the expected trace and stops at the first difference,
+// leaving the machine in the state right before the diverging instruction.
+pub fn compare(chip8: &mut Chip8, expected: &[TraceRecord], cycles_per_frame: u32) -> TraceDiff {
+    for (cycle, expected) in expected.iter().enumerate() {
+        let actual = match TraceRecord::capture(chip8) {
+            Ok(actual) => actual,
+            Err(error) => return TraceDiff::Failed { cycle, error }
+        };
+        let fields = actual.differences(expected);
+
+        if !fields.is_empty() {
+            return TraceDiff::Diverged { cycle, expected: *expected, actual, fields };
+        }
+
+        if let Err(error) = chip8.emulate_cycle() {
+            return TraceDiff::Failed { cycle, error };
+        }
+
+        if (cycle as u32 + 1).is_multiple_of(cycles_per_frame) {
+            chip8.tick_timers();
+        }
+    }
+
+    TraceDiff::Matched(expected.len())
+}
+
+fn hex_dump_around(chip8: &Chip8, address: u16) -> String {
+    let start = (address as usize).saturating_sub(MEMORY_CONTEXT).min(chip8.memory.ram.len());
+    let end = (address as usize + MEMORY_CONTEXT).min(chip8.memory.ram.len());
+
+    chip8.memory.ram[start..end]
+        .iter()
+        .enumerate()
+        .map(|(offset, byte)| match start + offset == address as usize {
+            true => format!("[{:02X}]", byte),
+            false => format!("{:02X}", byte)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A report of the outcome, with registers, stack and the memory around PC and I at the point of divergence.
+// Locations are described with the symbols, pass Symbols::default() when there are none.
+pub fn format_report(diff: &TraceDiff, chip8: &Chip8, symbols: &Symbols) -> String {
+    let mut report = String::new();
+
+    match diff {
+        TraceDiff::Matched(cycles) => report.push_str(&format!("All {} cycles match the reference trace\n", cycles)),
+        TraceDiff::Failed { cycle, error } => {
+            report.push_str(&format!("Cycle {}: emulator failed: {}\n", cycle, symbols.describe_error(error, chip8.registers.program_counter)));
+        }
+        TraceDiff::Diverged { cycle, expected, actual, fields } => {
+            report.push_str(&format!(
+                "Cycle {}: {} at {} differs in {}\n",
+                cycle, symbols.mnemonic_with_quirks(actual.op_code, &chip8.quirks), symbols.describe(actual.program_counter), fields.join(", ")
+            ));
+            report.push_str(&format!("  expected {}\n", expected));
+            report.push_str(&format!("  actual   {}\n", actual));
+            report.push_str(&format!("  stack    {:03X?}\n", &chip8.stack.stack[..chip8.stack.stack_pointer.min(16) as usize]));
+            report.push_str(&format!("  timers   DT={:02X} ST={:02X}\n", chip8.timers.delay_timer, chip8.timers.sound_timer));
+            report.push_str(&format!("  at PC    {}\n", hex_dump_around(chip8, actual.program_counter)));
+            report.push_str(&format!("  at I     {}\n", hex_dump_around(chip8, actual.i)));
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, parse_trace, TraceDiff, TraceRecord, TraceRecorder};
+    use crate::cpu::Chip8;
+    use crate::error::Chip8Error;
+
+    const ROM: [u8; 10] = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x33, 0x70, 0x01, 0x12, 0x04];
+
+    fn record_trace(cycles: usize) -> Vec<TraceRecord> {
+        let mut chip8 = Chip8::initialize();
+        let mut recorder = TraceRecorder::default();
+
+        chip8.load_rom(&ROM).unwrap();
+
+        for _ in 0..cycles {
+            chip8.emulate_cycle_observed(&mut recorder).unwrap();
+        }
+
+        recorder.records
+    }
+
+    #[test]
+    fn can_format_and_parse_records() {
+        let line = "2A4 F265 01 02 03 00 00 00 00 00 00 00 00 00 00 00 00 FF 300 1";
+        let record = TraceRecord::parse(line).unwrap();
+
+        assert_eq!(record.program_counter, 0x2A4);
+        assert_eq!(record.op_code, 0xF265);
+        assert_eq!(record.v[2], 3);
+        assert_eq!(record.v[0xF], 0xFF);
+        assert_eq!(record.i, 0x300);
+        assert_eq!(record.stack_pointer, 1);
+        assert_eq!(record.to_string(), line);
+        assert!(TraceRecord::parse("2A4 F265").is_err());
+        assert!(TraceRecord::parse("2A4 F265 01 1FF 03 00 00 00 00 00 00 00 00 00 00 00 00 FF 300 1").is_err());
+        assert!(parse_trace("# header\n\n2A4 F265 zz").unwrap_err().starts_with("line 3"));
+    }
+
+    #[test]
+    fn matching_trace_reports_all_cycles() {
+        let expected = record_trace(12);
+        let mut chip8 = Chip8::initialize();
+
+        chip8.load_rom(&ROM).unwrap();
+
+        assert_eq!(compare(&mut chip8, &expected, 10), TraceDiff::Matched(12));
+    }
+
+    #[test]
+    fn reports_first_diverging_cycle() {
+        let mut expected = record_trace(12);
+        let mut chip8 = Chip8::initialize();
+
+        expected[7].i = 0x301;
+        expected[9].v[0] = 0;
+
+        chip8.load_rom(&ROM).unwrap();
+
+        match compare(&mut chip8, &expected, 10) {
+            TraceDiff::Diverged { cycle, fields, actual, .. } => {
+                assert_eq!(cycle, 7);
+                assert_eq!(fields, vec!["I".to_string()]);
+                assert_eq!(actual.program_counter, chip8.registers.program_counter);
+            }
+            diff => panic!("unexpected {:?}", diff),
+        }
+    }
+
+    #[test]
+    fn reports_the_fetch_error_at_the_end_of_memory() {
+        let mut expected = record_trace(1);
+        let mut chip8 = Chip8::initialize();
+
+        chip8.registers.program_counter = 0xFFF;
+        expected[0].program_counter = 0xFFF;
+
+        assert_eq!(compare(&mut chip8, &expected, 10), TraceDiff::Failed { cycle: 0, error: Chip8Error::MemoryOutOfBounds(0x1000) });
+    }
+}
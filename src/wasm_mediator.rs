@@ -1,11 +1,416 @@
 extern crate wasm_bindgen;
 
-use std::mem;
+use std::sync::Mutex;
 
 use wasm_bindgen::prelude::*;
-use crate::cpu;
+use crate::cpu::{Chip8, Keypad};
+use crate::frontend::{Audio, Display, FixedClock, Frontend, Input};
+use crate::gamepad::{Axis, Button, Gamepad, GamepadConfig, GamepadEvent};
+use crate::keymap::{self, KeyMap, KeyMapConfig};
+use crate::loader;
+use crate::memory_watch::DirtyTracker;
+use crate::players::{Conflict, PlayerInput};
+
+// The machine the page runs. The core knows nothing of it, frontends get at it through the functions here.
+// Functions holding more than one lock take them in the order KEY_MAP, GAMEPAD, KEYBOARD, PLAYERS, CHIP8, BUZZER.
+lazy_static! {
+    pub static ref CHIP8: Mutex<Chip8> = Mutex::new(Chip8::initialize());
+    static ref DIRTY_TRACKER: Mutex<DirtyTracker> = Mutex::new(DirtyTracker::new(&CHIP8.lock().unwrap().memory.ram));
+    static ref KEY_MAP: Mutex<KeyMap> = Mutex::new(KeyMap::standard());
+    // The keys held on the keyboard with one player, kept apart from the machine's keypad which the gamepad also sets.
+    static ref KEYBOARD: Mutex<Keypad> = Mutex::new(Keypad { keys: [false; 16] });
+    static ref GAMEPAD: Mutex<Gamepad> = Mutex::new(Gamepad::new(GamepadConfig::built_in().for_hash("")));
+    static ref BUZZER: Mutex<BrowserAudio> = Mutex::new(BrowserAudio { on: false });
+    // Set while two players share the page, taking over from KEY_MAP's direct presses and GAMEPAD.
+    static ref PLAYERS: Mutex<Option<PlayerInput>> = Mutex::new(None);
+}
+
+// The page draws from the machine's own pixels, so the display only notes that they changed.
+struct BrowserDisplay {
+    changed: bool,
+}
+
+struct BrowserAudio {
+    on: bool,
+}
+
+// One player's keyboard and gamepad together, a key being down while either holds it.
+struct SinglePlayerInput<'a> {
+    keyboard: &'a Keypad,
+    gamepad: &'a mut Gamepad,
+}
+
+impl Display for BrowserDisplay {
+    fn present(&mut self, _pixels: &[bool]) {
+        self.changed = true;
+    }
+}
+
+impl Audio for BrowserAudio {
+    fn set_buzzer(&mut self, on: bool) {
+        self.on = on;
+    }
+}
+
+impl Input for SinglePlayerInput<'_> {
+    fn poll(&mut self, keypad: &mut Keypad) {
+        let mut from_gamepad = Keypad { keys: [false; 16] };
+
+        self.gamepad.update(&mut from_gamepad);
+
+        for (key, pressed) in keypad.keys.iter_mut().enumerate() {
+            *pressed = self.keyboard.keys[key] || from_gamepad.keys[key];
+        }
+    }
+}
+
+// Starts a ROM on a new machine with the quirks the built-in database knows for it, and the built-in key map and
+// gamepad profile. Returns the cycles per frame to pass to run_frame.
+#[wasm_bindgen]
+pub fn load_rom(rom: &[u8]) -> Result<u32, JsValue> {
+    let (chip8, cycles_per_frame) = loader::load_chip8(rom).map_err(|error| JsValue::from_str(&error.to_string()))?;
+    let hash = loader::rom_hash(rom);
+
+    *DIRTY_TRACKER.lock().unwrap() = DirtyTracker::new(&chip8.memory.ram);
+    *CHIP8.lock().unwrap() = chip8;
+    *KEY_MAP.lock().unwrap() = KeyMapConfig::built_in().for_hash(&hash);
+    *GAMEPAD.lock().unwrap() = Gamepad::new(GamepadConfig::built_in().for_hash(&hash));
+    *KEYBOARD.lock().unwrap() = Keypad { keys: [false; 16] };
+
+    Ok(cycles_per_frame)
+}
 
 #[wasm_bindgen]
 pub fn next_frame() -> *const bool {
-    cpu::get_pointer_to_gfx()
-}
\ No newline at end of file
+    CHIP8.lock().unwrap().graphics.gfx.as_ptr()
+}
+
+// Reads are clipped to the end of memory.
+#[wasm_bindgen]
+pub fn read_ram(start: u16, length: u16) -> Vec<u8> {
+    let chip8 = CHIP8.lock().unwrap();
+    let ram = &chip8.memory.ram;
+    let start = (start as usize).min(ram.len());
+    let end = (start + length as usize).min(ram.len());
+
+    ram[start..end].to_vec()
+}
+
+// Returns false, writing nothing, when the bytes do not fit in memory.
+#[wasm_bindgen]
+pub fn write_ram(start: u16, bytes: &[u8]) -> bool {
+    let mut chip8 = CHIP8.lock().unwrap();
+    let start = start as usize;
+
+    match chip8.memory.ram.get_mut(start..start + bytes.len()) {
+        Some(ram) => {
+            ram.copy_from_slice(bytes);
+            true
+        }
+        None => false
+    }
+}
+
+// Ranges of memory written since the previous call, flattened as start, end (exclusive), start, end...
+#[wasm_bindgen]
+pub fn take_dirty_ranges() -> Vec<u16> {
+    // The tracker first, it takes a snapshot of memory the first time it is used.
+    let mut tracker = DIRTY_TRACKER.lock().unwrap();
+    let chip8 = CHIP8.lock().unwrap();
+
+    tracker
+        .take_dirty_ranges(&chip8.memory.ram)
+        .into_iter()
+        .flat_map(|range| vec![range.start as u16, range.end as u16])
+        .collect()
+}
+
+// Returns undefined for an index past VF.
+#[wasm_bindgen]
+pub fn get_v_register(index: u8) -> Option<u8> {
+    CHIP8.lock().unwrap().registers.v.get(index as usize).cloned()
+}
+
+// Returns false, setting nothing, for an index past VF.
+#[wasm_bindgen]
+pub fn set_v_register(index: u8, value: u8) -> bool {
+    match CHIP8.lock().unwrap().registers.v.get_mut(index as usize) {
+        Some(register) => {
+            *register = value;
+            true
+        }
+        None => false
+    }
+}
+
+#[wasm_bindgen]
+pub fn get_i() -> u16 {
+    CHIP8.lock().unwrap().registers.i
+}
+
+#[wasm_bindgen]
+pub fn set_i(value: u16) {
+    CHIP8.lock().unwrap().registers.i = value;
+}
+
+#[wasm_bindgen]
+pub fn get_program_counter() -> u16 {
+    CHIP8.lock().unwrap().registers.program_counter
+}
+
+#[wasm_bindgen]
+pub fn set_program_counter(value: u16) {
+    CHIP8.lock().unwrap().registers.program_counter = value;
+}
+
+#[wasm_bindgen]
+pub fn get_stack_pointer() -> u16 {
+    CHIP8.lock().unwrap().stack.stack_pointer
+}
+
+// Returns false for a stack pointer beyond the 16 levels of the stack.
+#[wasm_bindgen]
+pub fn set_stack_pointer(value: u16) -> bool {
+    let mut chip8 = CHIP8.lock().unwrap();
+
+    if value as usize > chip8.stack.stack.len() {
+        return false;
+    }
+
+    chip8.stack.stack_pointer = value;
+    true
+}
+
+#[wasm_bindgen]
+pub fn get_delay_timer() -> u8 {
+    CHIP8.lock().unwrap().timers.delay_timer
+}
+
+#[wasm_bindgen]
+pub fn set_delay_timer(value: u8) {
+    CHIP8.lock().unwrap().timers.delay_timer = value;
+}
+
+#[wasm_bindgen]
+pub fn get_sound_timer() -> u8 {
+    CHIP8.lock().unwrap().timers.sound_timer
+}
+
+#[wasm_bindgen]
+pub fn set_sound_timer(value: u8) {
+    CHIP8.lock().unwrap().timers.sound_timer = value;
+}
+
+// Picks the key map for a ROM from the built-in ones plus a key map file, see src/keymap.rs.
+// Returns false, keeping the current key map, when the file can not be read.
+#[wasm_bindgen]
+pub fn use_key_map(config: &str, rom_hash: &str) -> bool {
+    match KeyMapConfig::parse(config) {
+        Ok(config) => {
+            *KEY_MAP.lock().unwrap() = config.for_hash(rom_hash);
+            true
+        }
+        Err(_) => false
+    }
+}
+
+// Takes host key names as in KeyboardEvent.code or .key, returns false for keys that are not mapped.
+// With two players, the key goes to the player owning the CHIP-8 key it maps to.
+#[wasm_bindgen]
+pub fn key_down(host_key: &str) -> bool {
+    set_host_key(host_key, true)
+}
+
+#[wasm_bindgen]
+pub fn key_up(host_key: &str) -> bool {
+    set_host_key(host_key, false)
+}
+
+fn set_host_key(host_key: &str, pressed: bool) -> bool {
+    let key_map = KEY_MAP.lock().unwrap();
+    let mut keyboard = KEYBOARD.lock().unwrap();
+    let mut players = PLAYERS.lock().unwrap();
+
+    match players.as_mut() {
+        Some(players) => players.set_host_key(&key_map, host_key, pressed),
+        // The machine sees the key at once, and again at every frame's poll for as long as it is held.
+        None => match key_map.set_key(&mut keyboard, host_key, pressed) {
+            true => key_map.set_key(&mut CHIP8.lock().unwrap().keypad, host_key, pressed),
+            false => false
+        }
+    }
+}
+
+// The 16 keys of the on-screen keypad, row by row.
+#[wasm_bindgen]
+pub fn keypad_layout() -> Vec<u8> {
+    keymap::KEYPAD_LAYOUT.iter().flatten().cloned().collect()
+}
+
+// The host keys pressing a CHIP-8 key, separated by spaces, to label the on-screen keypad.
+#[wasm_bindgen]
+pub fn key_labels(key: u8) -> String {
+    KEY_MAP.lock().unwrap().host_keys_for(key).join(" ")
+}
+
+// Picks the gamepad profile for a ROM from the built-in ones plus a profile file, see src/gamepad.rs.
+#[wasm_bindgen]
+pub fn use_gamepad_profile(config: &str, rom_hash: &str) -> bool {
+    match GamepadConfig::parse(config) {
+        Ok(config) => {
+            *GAMEPAD.lock().unwrap() = Gamepad::new(config.for_hash(rom_hash));
+            true
+        }
+        Err(_) => false
+    }
+}
+
+// Button and axis indexes as in the standard mapping of the Gamepad API, returns false for unknown ones.
+#[wasm_bindgen]
+pub fn gamepad_button(index: usize, pressed: bool) -> bool {
+    match Button::from_index(index) {
+        Some(button) => {
+            GAMEPAD.lock().unwrap().handle(GamepadEvent::Button(button, pressed));
+            true
+        }
+        None => false
+    }
+}
+
+#[wasm_bindgen]
+pub fn gamepad_axis(index: usize, value: f32) -> bool {
+    match Axis::from_index(index) {
+        Some(axis) => {
+            GAMEPAD.lock().unwrap().handle(GamepadEvent::Axis(axis, value));
+            true
+        }
+        None => false
+    }
+}
+
+// Splits the keypad between two players as the ROM with this SHA-1 expects, each with a gamepad of their own.
+// With share_keys either player may press any key, otherwise presses of the other player's keys are dropped.
+#[wasm_bindgen]
+pub fn use_players(rom_hash: &str, share_keys: bool) {
+    let conflict = match share_keys {
+        true => Conflict::Share,
+        false => Conflict::Reject
+    };
+
+    *PLAYERS.lock().unwrap() = Some(PlayerInput::for_hash(rom_hash, conflict));
+}
+
+// Back to one player on the key map and the gamepad.
+#[wasm_bindgen]
+pub fn use_single_player() {
+    *PLAYERS.lock().unwrap() = None;
+}
+
+// The player owning a CHIP-8 key, or -1 for keys both may press and with one player.
+#[wasm_bindgen]
+pub fn key_owner(key: u8) -> i32 {
+    match PLAYERS.lock().unwrap().as_ref().and_then(|players| players.split().owner(key)) {
+        Some(player) => player as i32,
+        None => -1
+    }
+}
+
+// Like gamepad_button and gamepad_axis for the gamepad of a player, returns false without two players.
+#[wasm_bindgen]
+pub fn player_gamepad_button(player: usize, index: usize, pressed: bool) -> bool {
+    player_gamepad_event(player, Button::from_index(index).map(|button| GamepadEvent::Button(button, pressed)))
+}
+
+#[wasm_bindgen]
+pub fn player_gamepad_axis(player: usize, index: usize, value: f32) -> bool {
+    player_gamepad_event(player, Axis::from_index(index).map(|axis| GamepadEvent::Axis(axis, value)))
+}
+
+fn player_gamepad_event(player: usize, event: Option<GamepadEvent>) -> bool {
+    let mut players = PLAYERS.lock().unwrap();
+    let gamepad = players.as_mut().and_then(|players| players.gamepad_mut(player));
+
+    match (gamepad, event) {
+        (Some(gamepad), Some(event)) => {
+            gamepad.handle(event);
+            true
+        }
+        _ => false
+    }
+}
+
+// Runs one frame, polling the keyboard and gamepad first, or the players' keys and gamepads with two players. Returns whether the screen changed,
+// the page reads it through the pointer from next_frame.
+#[wasm_bindgen]
+pub fn run_frame(cycles_per_frame: u32) -> Result<bool, JsValue> {
+    let mut gamepad = GAMEPAD.lock().unwrap();
+    let keyboard = KEYBOARD.lock().unwrap();
+    let mut players = PLAYERS.lock().unwrap();
+    let mut single_player = SinglePlayerInput { keyboard: &keyboard, gamepad: &mut gamepad };
+    let input: &mut dyn Input = match players.as_mut() {
+        Some(players) => players,
+        None => &mut single_player
+    };
+    let mut chip8 = CHIP8.lock().unwrap();
+    let mut display = BrowserDisplay { changed: false };
+    let mut audio = BUZZER.lock().unwrap();
+    let mut frontend = Frontend { display: &mut display, audio: &mut *audio, input, clock: &mut FixedClock };
+
+    frontend.run_frame(&mut chip8, cycles_per_frame).map_err(|error| JsValue::from_str(&error.to_string()))?;
+
+    Ok(display.changed)
+}
+
+// Whether the page should be beeping, as of the last frame run.
+#[wasm_bindgen]
+pub fn buzzer_on() -> bool {
+    BUZZER.lock().unwrap().on
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CHIP8, get_v_register, key_down, key_labels, key_up, keypad_layout, read_ram, run_frame, set_stack_pointer, set_v_register, take_dirty_ranges,
+        use_key_map, write_ram,
+    };
+
+    #[test]
+    fn pokes_memory_and_reports_dirty_ranges() {
+        take_dirty_ranges();
+
+        assert!(write_ram(0x300, &[1, 2, 3]));
+        assert!(!write_ram(0xFFF, &[1, 2]));
+        assert_eq!(read_ram(0x300, 4), vec![1, 2, 3, 0]);
+        assert_eq!(read_ram(0xFFE, 4).len(), 2);
+        assert_eq!(take_dirty_ranges(), vec![0x300, 0x303]);
+        assert!(take_dirty_ranges().is_empty());
+        assert!(!set_stack_pointer(17));
+        assert!(set_v_register(0xE, 0x2A));
+        assert_eq!(get_v_register(0xE), Some(0x2A));
+        assert!(!set_v_register(0x12, 1));
+        assert_eq!(get_v_register(0x12), None);
+    }
+
+    #[test]
+    fn maps_host_keys_for_a_rom() {
+        assert!(use_key_map("key Space 5", "b232ef880bd6060fb45fa6effed7edf0ae95670e"));
+        assert!(!use_key_map("key Space", ""));
+        assert!(key_down("ArrowUp"));
+        assert!(CHIP8.lock().unwrap().keypad.keys[0xC]);
+        assert!(key_up("ArrowUp"));
+        assert!(!CHIP8.lock().unwrap().keypad.keys[0xC]);
+        assert!(!key_down("Enter"));
+
+        // 5 is bound on the gamepad, which is idle, and the keyboard still holds it through the frames.
+        assert!(key_down("Space"));
+        run_frame(0).unwrap();
+        run_frame(0).unwrap();
+        assert!(CHIP8.lock().unwrap().keypad.keys[0x5]);
+        assert!(key_up("Space"));
+        run_frame(0).unwrap();
+        assert!(!CHIP8.lock().unwrap().keypad.keys[0x5]);
+        assert_eq!(key_labels(0x5), "SPACE");
+        assert_eq!(key_labels(0x1), "1 W");
+        assert_eq!(keypad_layout()[..4], [0x1, 0x2, 0x3, 0xC]);
+    }
+}